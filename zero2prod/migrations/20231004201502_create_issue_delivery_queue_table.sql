@@ -0,0 +1,8 @@
+-- Add migration script here
+create table issue_delivery_queue (
+    newsletter_issue_id uuid not null references newsletter_issues (newsletter_issue_id),
+    subscriber_email text not null,
+    n_retries smallint not null default 0,
+    execute_after timestamptz not null default now(),
+    primary key (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+create table sessions (
+    session_key text not null,
+    state text not null,
+    expires_at timestamptz not null,
+    primary key (session_key)
+);
+
+create index sessions_expires_at_idx on sessions (expires_at);
@@ -0,0 +1,7 @@
+-- Add migration script here
+create table password_change_audit (
+    audit_id uuid not null,
+    user_id uuid not null references users (user_id),
+    changed_at timestamptz not null,
+    primary key (audit_id)
+);
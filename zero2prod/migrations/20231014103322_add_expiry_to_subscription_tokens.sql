@@ -0,0 +1,4 @@
+-- Add migration script here
+alter table subscription_tokens
+    add column created_at timestamptz not null default now(),
+    add column consumed_at timestamptz null;
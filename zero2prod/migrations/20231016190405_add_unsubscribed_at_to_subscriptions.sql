@@ -0,0 +1,3 @@
+-- Add migration script here
+-- `status` gains the 'unsubscribed' value, this records when it was set
+alter table subscriptions add column unsubscribed_at timestamptz null;
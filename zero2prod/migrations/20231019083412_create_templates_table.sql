@@ -0,0 +1,8 @@
+-- Add migration script here
+-- email templates edited from the database, they override the files in templates/email
+create table templates(
+    name text not null primary key,
+    html_body text not null,
+    text_body text,
+    updated_at timestamptz not null default now()
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- 'scheduled' until the scheduler enqueues the deliveries, then 'published'
+alter table newsletter_issues add column status text not null default 'published';
+alter table newsletter_issues alter column status drop default;
+
+alter table newsletter_issues add column send_at timestamptz;
+update newsletter_issues set send_at = published_at;
+alter table newsletter_issues alter column send_at set not null;
+
+-- only set once the issue has been published
+alter table newsletter_issues alter column published_at drop not null;
+
+create index newsletter_issues_scheduled_send_at
+    on newsletter_issues (send_at)
+    where status = 'scheduled';
@@ -0,0 +1,11 @@
+-- Add migration script here
+create table subscriber_tags (
+    subscriber_id uuid not null references subscriptions (id) on delete cascade,
+    tag text not null,
+    primary key (subscriber_id, tag)
+);
+create index subscriber_tags_tag on subscriber_tags (tag);
+
+-- the segment expression an issue is delivered to, every confirmed
+-- subscriber when null
+alter table newsletter_issues add column segment text;
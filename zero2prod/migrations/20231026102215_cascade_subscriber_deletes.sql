@@ -0,0 +1,9 @@
+-- Add migration script here
+-- deleting a subscriber takes their tokens with them
+alter table subscription_tokens
+    drop constraint subscription_tokens_subscriber_id_fkey,
+    add constraint subscription_tokens_subscriber_id_fkey
+        foreign key (subscriber_id) references subscriptions (id) on delete cascade;
+
+-- keyset pagination of the admin subscriber list
+create index subscriptions_subscribed_at_id on subscriptions (subscribed_at desc, id desc);
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- one-time links for a subscriber to export or erase their data
+create table data_request_tokens (
+    data_request_token text not null,
+    subscriber_id uuid not null references subscriptions (id) on delete cascade,
+    -- 'export' or 'erasure'
+    kind text not null,
+    created_at timestamptz not null default now(),
+    consumed_at timestamptz null,
+    primary key (data_request_token)
+);
+
+-- what is left of an erased subscriber, only enough to prove the erasure
+-- happened for someone who knows the email
+create table erasure_tombstones (
+    tombstone_id uuid not null,
+    email_hash text not null,
+    erased_at timestamptz not null,
+    primary key (tombstone_id)
+);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- `status` gains 'bounced' and 'complained' from SES notifications, this
+-- records when it was set
+alter table subscriptions add column suppressed_at timestamptz null;
@@ -0,0 +1,31 @@
+-- Add migration script here
+-- tracking is opt-in, per issue
+alter table newsletter_issues
+    add column track_opens boolean not null default false,
+    add column track_clicks boolean not null default false;
+
+-- one row per issue and subscriber, updated on every attempt
+create table deliveries (
+    delivery_id uuid not null,
+    newsletter_issue_id uuid not null
+        references newsletter_issues (newsletter_issue_id) on delete cascade,
+    -- null once the subscriber is deleted, the issue stats stay right
+    subscriber_id uuid null references subscriptions (id) on delete set null,
+    -- 'pending', 'sent', 'retrying' or 'failed'
+    status text not null,
+    attempted_at timestamptz not null,
+    -- the last error, if the last attempt failed
+    error text null,
+    opened_at timestamptz null,
+    n_opens integer not null default 0,
+    primary key (delivery_id),
+    unique (newsletter_issue_id, subscriber_id)
+);
+
+create table delivery_clicks (
+    delivery_id uuid not null references deliveries (delivery_id) on delete cascade,
+    url text not null,
+    clicked_at timestamptz not null default now()
+);
+
+create index delivery_clicks_delivery_id on delivery_clicks (delivery_id);
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- how each subscriber gave, confirmed or withdrew their consent
+create table consent_events (
+    consent_event_id uuid not null,
+    subscriber_id uuid not null references subscriptions (id) on delete cascade,
+    -- 'signup', 'import', 'confirmation' or 'unsubscription'
+    kind text not null,
+    occurred_at timestamptz not null,
+    ip text null,
+    user_agent text null,
+    -- the form the subscriber used, or 'admin'
+    source text null,
+    -- the version of the consent text they were shown
+    consent_text_version text null,
+    primary key (consent_event_id)
+);
+create index consent_events_subscriber_id on consent_events (subscriber_id);
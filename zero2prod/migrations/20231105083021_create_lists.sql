@@ -0,0 +1,28 @@
+-- Add migration script here
+-- every subscription and issue belongs to a list
+create table lists (
+    list_id uuid not null,
+    -- how forms and API calls name the list
+    slug text not null unique,
+    name text not null,
+    -- the sender of the email client when null
+    sender_email text null,
+    created_at timestamptz not null default now(),
+    primary key (list_id)
+);
+
+-- the one list there was before, forms and calls without a list still use it
+insert into lists (list_id, slug, name)
+values ('00000000-0000-0000-0000-000000000001', 'default', 'our newsletter');
+
+-- an email is unique per list, not overall. Rows inserted without a list
+-- still go to the default one
+alter table subscriptions
+    add column list_id uuid not null
+        default '00000000-0000-0000-0000-000000000001' references lists (list_id),
+    drop constraint subscriptions_email_key,
+    add constraint subscriptions_list_id_email_key unique (list_id, email);
+
+alter table newsletter_issues
+    add column list_id uuid not null
+        default '00000000-0000-0000-0000-000000000001' references lists (list_id);
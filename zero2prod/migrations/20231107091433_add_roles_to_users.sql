@@ -0,0 +1,29 @@
+-- 'admin', 'editor' or 'viewer', the users from before roles keep the rights
+-- they had
+alter table users add column role text null;
+update users set role = 'admin';
+alter table users alter column role set not null;
+
+-- deactivated users can no longer log in nor publish, their rows stay for
+-- the idempotency keys and audit records pointing at them
+alter table users add column deactivated_at timestamptz null;
+
+-- the lists an editor may publish to, admins may publish to every list
+create table list_permissions (
+    user_id uuid not null references users (user_id) on delete cascade,
+    list_id uuid not null references lists (list_id) on delete cascade,
+    primary key (user_id, list_id)
+);
+
+-- one-time links for someone to pick a username and password and join as a user
+create table user_invitations (
+    invitation_token text not null,
+    email text not null,
+    role text not null,
+    -- granted to the new user on acceptance
+    list_ids uuid[] not null,
+    invited_by uuid not null references users (user_id),
+    created_at timestamptz not null default now(),
+    accepted_at timestamptz null,
+    primary key (invitation_token)
+);
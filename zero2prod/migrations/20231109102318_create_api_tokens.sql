@@ -0,0 +1,17 @@
+-- personal tokens for machines to call the API on behalf of a user, only a
+-- hash of the token is stored
+create table api_tokens (
+    api_token_id uuid not null,
+    user_id uuid not null references users (user_id) on delete cascade,
+    name text not null,
+    token_hash text not null unique,
+    -- what the token may be used for, e.g. 'publish'
+    scopes text[] not null,
+    created_at timestamptz not null default now(),
+    expires_at timestamptz null,
+    last_used_at timestamptz null,
+    revoked_at timestamptz null,
+    primary key (api_token_id)
+);
+
+create index api_tokens_user_id_idx on api_tokens (user_id);
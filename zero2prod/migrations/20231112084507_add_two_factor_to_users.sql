@@ -0,0 +1,19 @@
+-- optional TOTP second factor. The secret has to be readable to compute the
+-- expected codes, so unlike passwords it cannot be hashed
+alter table users
+    add column totp_secret text null,
+    -- set once the user proved their app has the secret
+    add column totp_enabled_at timestamptz null,
+    -- the step of the last accepted code, which cannot be used again
+    add column totp_last_step bigint null,
+    add column totp_failed_attempts integer not null default 0,
+    add column totp_last_failure_at timestamptz null;
+
+-- single use codes for when the authenticator app is lost, only their hashes
+-- are stored
+create table recovery_codes (
+    user_id uuid not null references users (user_id) on delete cascade,
+    code_hash text not null,
+    used_at timestamptz null,
+    primary key (user_id, code_hash)
+);
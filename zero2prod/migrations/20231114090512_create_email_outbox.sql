@@ -0,0 +1,19 @@
+-- emails sent in response to a request, e.g. a confirmation link, queued in
+-- the transaction that creates them and sent by a background worker
+create table email_outbox (
+    email_id uuid not null,
+    -- the default sender when null
+    sender text null,
+    recipient text not null,
+    subject text not null,
+    html_content text not null,
+    text_content text not null,
+    -- null for emails that are not about a subscription
+    unsubscribe_link text null,
+    n_retries smallint not null default 0,
+    execute_after timestamptz not null default now(),
+    created_at timestamptz not null default now(),
+    primary key (email_id)
+);
+
+create index email_outbox_execute_after_idx on email_outbox (execute_after);
@@ -0,0 +1,3 @@
+-- SES notifications, and admins, look subscribers up by email regardless of
+-- case: without this index every lookup scans the table
+create index subscriptions_lower_email_idx on subscriptions (lower(email));
@@ -0,0 +1,6 @@
+-- Bob@x.com and bob@x.com are one mailbox: a list must not hold both, or a
+-- bounced address could come back with different case. Fails if a list
+-- already has such duplicates, merge them by hand first.
+alter table subscriptions drop constraint subscriptions_list_id_email_key;
+create unique index subscriptions_list_id_lower_email_key
+    on subscriptions (list_id, lower(email));
@@ -0,0 +1,13 @@
+mod api_token;
+mod middleware;
+mod password;
+mod totp;
+mod two_factor;
+mod user;
+
+pub use api_token::*;
+pub use middleware::*;
+pub use password::*;
+pub use totp::*;
+pub use two_factor::*;
+pub use user::*;
@@ -0,0 +1,128 @@
+use crate::authentication::AuthError;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha3::Digest;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// makes leaked tokens easy to spot, e.g. by secret scanners
+const TOKEN_PREFIX: &str = "z2p_";
+const TOKEN_LENGTH: usize = 40;
+
+/// What an API token may be used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    // `POST /newsletters`
+    Publish,
+    // listing, rescheduling and cancelling scheduled issues
+    Schedule,
+}
+
+impl ApiScope {
+    pub fn parse(s: &str) -> Result<ApiScope, String> {
+        match s {
+            "publish" => Ok(ApiScope::Publish),
+            "schedule" => Ok(ApiScope::Schedule),
+            other => Err(format!(
+                "{} is not a scope, use either `publish` or `schedule`.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Publish => "publish",
+            ApiScope::Schedule => "schedule",
+        }
+    }
+}
+
+/// A valid token, acting on behalf of its user.
+pub struct ApiToken {
+    pub api_token_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<String>,
+}
+
+impl ApiToken {
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        self.scopes.iter().any(|s| s == scope.as_str())
+    }
+}
+
+pub fn generate_api_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect();
+    Secret::new(format!("{}{}", TOKEN_PREFIX, token))
+}
+
+pub fn hash_api_token(token: &Secret<String>) -> String {
+    hash_random_secret(token.expose_secret())
+}
+
+/// Hex encoded SHA3 of a secret we generated, e.g. an API token or a recovery
+/// code. They are long and random, unlike passwords: a fast hash is enough to
+/// keep them secret.
+pub(crate) fn hash_random_secret(secret: &str) -> String {
+    format!("{:x}", sha3::Sha3_256::digest(secret.as_bytes()))
+}
+
+/// The token, unless it is unknown, revoked, expired or its user was
+/// deactivated. Its last use is recorded.
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+pub async fn validate_api_token(
+    token: Secret<String>,
+    pool: &PgPool,
+) -> Result<ApiToken, AuthError> {
+    sqlx::query_as!(
+        ApiToken,
+        r#"update api_tokens t
+           set last_used_at = now()
+           from users u
+           where t.token_hash = $1
+               and t.revoked_at is null
+               and (t.expires_at is null or t.expires_at > now())
+               and u.user_id = t.user_id
+               and u.deactivated_at is null
+           returning t.api_token_id, t.user_id, t.scopes
+        "#,
+        hash_api_token(&token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("Unknown, revoked or expired API token."))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_prefixed_and_unique() {
+        let token = generate_api_token();
+
+        assert!(token.expose_secret().starts_with(TOKEN_PREFIX));
+        assert_eq!(
+            token.expose_secret().len(),
+            TOKEN_PREFIX.len() + TOKEN_LENGTH
+        );
+        assert_ne!(token.expose_secret(), generate_api_token().expose_secret());
+    }
+
+    #[test]
+    fn scopes_are_parsed_back_from_their_names() {
+        for scope in [ApiScope::Publish, ApiScope::Schedule] {
+            assert_eq!(ApiScope::parse(scope.as_str()), Ok(scope));
+        }
+        assert!(ApiScope::parse("admin").is_err());
+    }
+}
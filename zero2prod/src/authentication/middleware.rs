@@ -0,0 +1,70 @@
+use crate::authentication::get_active_user;
+use crate::helpers::{e500, see_other};
+use crate::session_state::TypedSession;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpMessage};
+use actix_web_lab::middleware::Next;
+use sqlx::PgPool;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use uuid::Uuid;
+
+/// The logged in user, inserted in the request extensions by
+/// `reject_anonymous_users`.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl Display for UserId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => {
+            let response = see_other("/login");
+            let e = anyhow::anyhow!("The user has not logged in");
+            return Err(InternalError::from_response(e, response).into());
+        }
+    };
+
+    // looked up on every request, so that deactivating a user ends their sessions
+    let pool = req
+        .app_data::<Data<PgPool>>()
+        .ok_or_else(|| e500("The connection pool is missing."))?;
+    let user = get_active_user(user_id, pool).await.map_err(e500)?;
+    match user {
+        Some(user) => {
+            req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(user);
+            next.call(req).await
+        }
+        None => {
+            session.log_out();
+            let response = see_other("/login");
+            let e = anyhow::anyhow!("The user was deactivated");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
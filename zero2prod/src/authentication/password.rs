@@ -0,0 +1,284 @@
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sha3::Digest;
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+// Verified against when the username is unknown, so that the response time
+// does not tell whether the user exists.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$\
+    CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+// Hashes written before the switch to Argon2 are unsalted SHA3-256 hex digests.
+#[derive(Debug)]
+enum Verified {
+    Current,
+    Legacy,
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(DUMMY_PASSWORD_HASH.to_string());
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let password = credentials.password.clone();
+    let verified =
+        spawn_blocking_with_tracing(move || verify_password_hash(expected_password_hash, password))
+            .await
+            .context("Failed to spawn blocking task.")??;
+
+    // only reached with a matching password, the dummy hash never matches
+    let user_id = user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)?;
+
+    if let Verified::Legacy = verified {
+        // a failed upgrade must not fail the login, it is retried next time
+        if let Err(e) = upgrade_password_hash(user_id, credentials.password, pool).await {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to upgrade a legacy password hash."
+            );
+        }
+    }
+
+    Ok(user_id)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, anyhow::Error> {
+    // deactivated users are as good as unknown ones
+    let row = sqlx::query!(
+        r#"select user_id, password_hash
+           from users
+           where username = $1 and deactivated_at is null
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<Verified, AuthError> {
+    if !expected_password_hash.expose_secret().starts_with('$') {
+        return verify_legacy_password_hash(expected_password_hash, password_candidate);
+    }
+
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)?;
+
+    Ok(Verified::Current)
+}
+
+fn verify_legacy_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<Verified, AuthError> {
+    let password_hash = sha3::Sha3_256::digest(password_candidate.expose_secret().as_bytes());
+    let password_hash = format!("{:x}", password_hash);
+    let matches: bool = password_hash
+        .as_bytes()
+        .ct_eq(expected_password_hash.expose_secret().as_bytes())
+        .into();
+
+    // SHA3 is much faster than Argon2: without the extra work the response
+    // time would tell which users still have a legacy hash.
+    let dummy_password_hash =
+        PasswordHash::new(DUMMY_PASSWORD_HASH).context("Failed to parse the dummy hash.")?;
+    let _ = Argon2::default().verify_password(
+        password_candidate.expose_secret().as_bytes(),
+        &dummy_password_hash,
+    );
+
+    if matches {
+        Ok(Verified::Legacy)
+    } else {
+        Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "Invalid password."
+        )))
+    }
+}
+
+#[tracing::instrument(name = "Upgrade legacy password hash", skip(password, pool))]
+async fn upgrade_password_hash(
+    user_id: Uuid,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password.")?;
+
+    sqlx::query!(
+        r#"update users set password_hash = $1 where user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the user's password hash in the database.")?;
+
+    Ok(())
+}
+
+/// Replace the user's password and record the change in `password_change_audit`.
+#[tracing::instrument(name = "Change password", skip(password, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password.")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    sqlx::query!(
+        r#"update users set password_hash = $1 where user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to change user's password in the database.")?;
+
+    sqlx::query!(
+        r#"insert into password_change_audit (audit_id, user_id, changed_at)
+           values ($1, $2, now())
+        "#,
+        Uuid::new_v4(),
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the password change.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a password.")?;
+
+    Ok(())
+}
+
+/// Hash a password with Argon2id, returning it in PHC string format.
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+
+    Ok(Secret::new(password_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn a_computed_hash_verifies_the_same_password() {
+        let password = Secret::new("correct horse battery staple".to_string());
+        let password_hash = compute_password_hash(password.clone()).unwrap();
+
+        assert!(password_hash.expose_secret().starts_with("$argon2id$"));
+        assert_ok!(verify_password_hash(password_hash, password));
+    }
+
+    #[test]
+    fn a_computed_hash_rejects_another_password() {
+        let password = Secret::new("correct horse battery staple".to_string());
+        let password_hash = compute_password_hash(password).unwrap();
+
+        let outcome = verify_password_hash(password_hash, Secret::new("Tr0ub4dor&3".to_string()));
+        assert_err!(outcome);
+    }
+
+    #[test]
+    fn legacy_sha3_hashes_are_still_verified() {
+        let password = "correct horse battery staple";
+        let legacy_hash = format!("{:x}", sha3::Sha3_256::digest(password.as_bytes()));
+
+        let outcome =
+            verify_password_hash(Secret::new(legacy_hash), Secret::new(password.to_string()));
+        assert!(matches!(outcome, Ok(Verified::Legacy)));
+    }
+
+    #[test]
+    fn legacy_sha3_hashes_reject_another_password() {
+        let legacy_hash = format!(
+            "{:x}",
+            sha3::Sha3_256::digest("correct horse battery staple".as_bytes())
+        );
+
+        let outcome = verify_password_hash(
+            Secret::new(legacy_hash),
+            Secret::new("Tr0ub4dor&3".to_string()),
+        );
+        assert_err!(outcome);
+    }
+
+    #[test]
+    fn the_dummy_hash_is_a_valid_phc_string() {
+        assert_ok!(PasswordHash::new(DUMMY_PASSWORD_HASH));
+    }
+}
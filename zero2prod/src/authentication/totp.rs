@@ -0,0 +1,229 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use secrecy::{ExposeSecret, Secret, SecretVec};
+use sha1::Sha1;
+
+type HmacSha1 = Hmac<Sha1>;
+
+pub const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+// 160 bits, as RFC 4226 recommends
+const SECRET_LENGTH: usize = 20;
+// codes of the previous and next steps are accepted, for clocks that drift
+const ALLOWED_SKEW: i64 = 1;
+const ISSUER: &str = "zero2prod";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The time step `now` falls in.
+pub fn time_step(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(STEP_SECONDS)
+}
+
+/// The key shared with the authenticator app of a user, for time-based
+/// one-time passwords as in RFC 6238. We use the defaults authenticator apps
+/// expect: HMAC-SHA1, 6 digits and 30 second steps.
+pub struct TotpSecret(SecretVec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut secret = vec![0; SECRET_LENGTH];
+        thread_rng().fill_bytes(&mut secret);
+        Self(Secret::new(secret))
+    }
+
+    /// Parse the base32 form users type into their app, case and padding do
+    /// not matter.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match base32_decode(s) {
+            Some(secret) if !secret.is_empty() => Ok(Self(Secret::new(secret))),
+            _ => Err("A TOTP secret must be a non-empty base32 string.".into()),
+        }
+    }
+
+    pub fn to_base32(&self) -> Secret<String> {
+        Secret::new(base32_encode(self.0.expose_secret()))
+    }
+
+    /// The `otpauth://` uri authenticator apps scan as a QR code.
+    pub fn provisioning_uri(&self, username: &str) -> String {
+        let mut uri = reqwest::Url::parse("otpauth://totp/").unwrap();
+        uri.set_path(&format!("/{}:{}", ISSUER, username));
+        uri.query_pairs_mut()
+            .append_pair("secret", self.to_base32().expose_secret())
+            .append_pair("issuer", ISSUER)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &STEP_SECONDS.to_string());
+        uri.to_string()
+    }
+
+    /// The code of a time step, see `time_step`.
+    pub fn code_at(&self, step: i64) -> String {
+        let mut mac =
+            HmacSha1::new_from_slice(self.0.expose_secret()).expect("HMAC takes keys of any size");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // dynamic truncation, RFC 4226 section 5.3
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset],
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]) & 0x7fff_ffff;
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+
+    /// The step `code` was generated for, if it is valid at `now`. Steps up to
+    /// `last_used_step` are skipped, so that a code cannot be used twice.
+    pub fn verify(
+        &self,
+        code: &str,
+        now: DateTime<Utc>,
+        last_used_step: Option<i64>,
+    ) -> Option<i64> {
+        let code = code.trim();
+        let current = time_step(now);
+        (current - ALLOWED_SKEW..=current + ALLOWED_SKEW)
+            .filter(|step| last_used_step.is_none_or(|last| *step > last))
+            .find(|step| constant_time_eq(self.code_at(*step).as_bytes(), code.as_bytes()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// RFC 4648, without padding
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            encoded.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 0x1f) as usize] as char);
+            bits -= 5;
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            decoded.push((buffer >> (bits - 8)) as u8);
+            bits -= 8;
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // the SHA1 secret of RFC 6238's test vectors, "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        let secret = TotpSecret::parse(RFC_SECRET).unwrap();
+        // the last 6 of the 8 digits in RFC 6238, appendix B
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+
+        for (timestamp, code) in vectors {
+            assert_eq!(secret.code_at(time_step(at(timestamp))), code);
+        }
+    }
+
+    #[test]
+    fn secrets_survive_a_round_trip_through_base32() {
+        let secret = TotpSecret::parse(RFC_SECRET).unwrap();
+        assert_eq!(secret.0.expose_secret(), b"12345678901234567890");
+        assert_eq!(secret.to_base32().expose_secret(), RFC_SECRET);
+
+        let generated = TotpSecret::generate();
+        let parsed = TotpSecret::parse(&generated.to_base32().expose_secret().to_lowercase());
+        assert_eq!(
+            parsed.unwrap().0.expose_secret(),
+            generated.0.expose_secret()
+        );
+    }
+
+    #[test]
+    fn invalid_secrets_are_rejected() {
+        assert!(TotpSecret::parse("").is_err());
+        assert!(TotpSecret::parse("not base32!").is_err());
+    }
+
+    #[test]
+    fn codes_of_neighbouring_steps_are_accepted() {
+        let secret = TotpSecret::parse(RFC_SECRET).unwrap();
+        let now = at(1111111111);
+        let step = time_step(now);
+
+        assert_eq!(secret.verify(&secret.code_at(step), now, None), Some(step));
+        assert_eq!(
+            secret.verify(&secret.code_at(step - 1), now, None),
+            Some(step - 1)
+        );
+        assert_eq!(
+            secret.verify(&secret.code_at(step + 1), now, None),
+            Some(step + 1)
+        );
+        assert_eq!(secret.verify(&secret.code_at(step - 2), now, None), None);
+        assert_eq!(secret.verify("123", now, None), None);
+    }
+
+    #[test]
+    fn a_code_is_not_accepted_twice() {
+        let secret = TotpSecret::parse(RFC_SECRET).unwrap();
+        let now = at(1111111111);
+        let step = time_step(now);
+
+        assert_eq!(secret.verify(&secret.code_at(step), now, Some(step)), None);
+        assert_eq!(
+            secret.verify(&secret.code_at(step + 1), now, Some(step)),
+            Some(step + 1)
+        );
+    }
+
+    #[test]
+    fn the_provisioning_uri_names_the_user_and_the_secret() {
+        let secret = TotpSecret::parse(RFC_SECRET).unwrap();
+
+        let uri = secret.provisioning_uri("ursula le guin");
+
+        assert!(uri.starts_with("otpauth://totp/zero2prod:ursula%20le%20guin?"));
+        assert!(uri.contains(&format!("secret={}", RFC_SECRET)));
+        assert!(uri.contains("issuer=zero2prod"));
+    }
+}
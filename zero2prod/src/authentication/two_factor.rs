@@ -0,0 +1,232 @@
+use crate::authentication::{hash_random_secret, AuthError, TotpSecret};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_GROUPS: usize = 4;
+const RECOVERY_CODE_GROUP_LENGTH: usize = 4;
+// after that many failed codes in a row, codes are refused for a while
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+const LOCKOUT_MINUTES: i64 = 5;
+
+/// Codes such as `a1b2-c3d4-e5f6-g7h8`, meant to be written down.
+pub fn generate_recovery_codes() -> Vec<Secret<String>> {
+    let mut rng = thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let groups: Vec<String> = (0..RECOVERY_CODE_GROUPS)
+                .map(|_| {
+                    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                        .map(|c| char::from(c).to_ascii_lowercase())
+                        .take(RECOVERY_CODE_GROUP_LENGTH)
+                        .collect()
+                })
+                .collect();
+            Secret::new(groups.join("-"))
+        })
+        .collect()
+}
+
+/// Case, dashes and spaces do not matter, they are easy to get wrong when
+/// typing a code from paper.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_random_secret(&normalized)
+}
+
+/// Replace the recovery codes of a user.
+pub async fn store_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    codes: &[Secret<String>],
+) -> Result<(), anyhow::Error> {
+    sqlx::query!("delete from recovery_codes where user_id = $1", user_id)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to delete the previous recovery codes.")?;
+    let code_hashes: Vec<String> = codes
+        .iter()
+        .map(|code| hash_recovery_code(code.expose_secret()))
+        .collect();
+    sqlx::query!(
+        r#"insert into recovery_codes (user_id, code_hash)
+           select $1, unnest($2::text[])
+        "#,
+        user_id,
+        &code_hashes
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the recovery codes.")?;
+
+    Ok(())
+}
+
+/// Whether the user has to give a second factor after their password.
+pub async fn second_factor_enabled(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        "select totp_enabled_at from users where user_id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the second factor of the user.")?;
+
+    Ok(matches!(row, Some(row) if row.totp_enabled_at.is_some()))
+}
+
+/// Check a code from the authenticator app of the user, or one of their
+/// unused recovery codes, which is then used up.
+#[tracing::instrument(name = "Verify second factor", skip(code, pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &Secret<String>,
+    now: DateTime<Utc>,
+    pool: &PgPool,
+) -> Result<(), AuthError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // locked, so that concurrent requests cannot use the same code twice
+    let row = sqlx::query!(
+        r#"select totp_secret, totp_last_step, totp_failed_attempts, totp_last_failure_at
+           from users
+           where user_id = $1 and totp_enabled_at is not null
+           for update
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the second factor of the user.")?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("The user has no second factor."))
+    })?;
+
+    let lockout_ends_at = row
+        .totp_last_failure_at
+        .map(|last_failure_at| last_failure_at + chrono::Duration::minutes(LOCKOUT_MINUTES));
+    let locked_out = row.totp_failed_attempts >= MAX_FAILED_ATTEMPTS
+        && matches!(lockout_ends_at, Some(ends_at) if now < ends_at);
+    if locked_out {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "Too many failed attempts at a second factor."
+        )));
+    }
+
+    let secret = TotpSecret::parse(row.totp_secret.as_deref().unwrap_or_default())
+        .map_err(anyhow::Error::msg)
+        .context("The stored TOTP secret is invalid.")?;
+    let verified = match secret.verify(code.expose_secret(), now, row.totp_last_step) {
+        Some(step) => {
+            sqlx::query!(
+                "update users set totp_last_step = $2 where user_id = $1",
+                user_id,
+                step
+            )
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to record the step of the code.")?;
+            true
+        }
+        None => use_recovery_code(&mut transaction, user_id, code, now).await?,
+    };
+
+    if verified {
+        sqlx::query!(
+            "update users set totp_failed_attempts = 0 where user_id = $1",
+            user_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to reset the failed attempts.")?;
+    } else {
+        sqlx::query!(
+            r#"update users
+               set totp_failed_attempts = totp_failed_attempts + 1, totp_last_failure_at = $2
+               where user_id = $1
+            "#,
+            user_id,
+            now
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to record the failed attempt.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to verify a second factor.")?;
+
+    if verified {
+        Ok(())
+    } else {
+        Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "Invalid second factor."
+        )))
+    }
+}
+
+// whether `code` was an unused recovery code of the user
+async fn use_recovery_code(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    code: &Secret<String>,
+    now: DateTime<Utc>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"update recovery_codes
+           set used_at = $3
+           where user_id = $1 and code_hash = $2 and used_at is null
+        "#,
+        user_id,
+        hash_recovery_code(code.expose_secret()),
+        now
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to use up the recovery code.")?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_are_unique() {
+        let codes = generate_recovery_codes();
+        let mut hashes: Vec<String> = codes
+            .iter()
+            .map(|code| hash_recovery_code(code.expose_secret()))
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+
+        assert_eq!(hashes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].expose_secret().len(), 19);
+    }
+
+    #[test]
+    fn recovery_codes_may_be_typed_loosely() {
+        assert_eq!(
+            hash_recovery_code("a1b2-c3d4-e5f6-g7h8"),
+            hash_recovery_code(" A1B2 C3D4 e5f6g7h8 ")
+        );
+        assert_ne!(
+            hash_recovery_code("a1b2-c3d4-e5f6-g7h8"),
+            hash_recovery_code("a1b2-c3d4-e5f6-g7h9")
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// What a user may do, each role being allowed what the ones before it are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    // looks at subscribers and issues
+    Viewer,
+    // also publishes, to the lists they were given
+    Editor,
+    // also manages users, lists and subscribers, and publishes to every list
+    Admin,
+}
+
+impl Role {
+    pub fn parse(s: &str) -> Result<Role, String> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!(
+                "{} is not a role, use either `admin`, `editor` or `viewer`.",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// A user who may use the application, inserted in the request extensions by
+/// `reject_anonymous_users`.
+#[derive(Copy, Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub role: Role,
+}
+
+impl AuthenticatedUser {
+    pub async fn may_publish_to(&self, pool: &PgPool, list_id: Uuid) -> Result<bool, sqlx::Error> {
+        match self.role {
+            Role::Admin => Ok(true),
+            Role::Viewer => Ok(false),
+            Role::Editor => {
+                let row = sqlx::query!(
+                    r#"select exists (
+                           select 1 from list_permissions where user_id = $1 and list_id = $2
+                       ) as "allowed!"
+                    "#,
+                    self.user_id,
+                    list_id
+                )
+                .fetch_one(pool)
+                .await?;
+
+                Ok(row.allowed)
+            }
+        }
+    }
+
+    /// A 403 for anyone but admins.
+    pub fn require_admin(&self) -> Result<(), actix_web::Error> {
+        if self.role < Role::Admin {
+            return Err(actix_web::error::ErrorForbidden(
+                "Only admins are allowed to do this.",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The user, unless they do not exist or were deactivated.
+#[tracing::instrument(name = "Get active user", skip(pool))]
+pub async fn get_active_user(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<AuthenticatedUser>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"select role from users where user_id = $1 and deactivated_at is null"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a user.")?;
+
+    row.map(|row| {
+        let role = Role::parse(&row.role).map_err(|e| anyhow::anyhow!(e))?;
+        Ok(AuthenticatedUser { user_id, role })
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::assert_err;
+
+    #[test]
+    fn roles_are_parsed_back_from_their_names() {
+        for role in [Role::Viewer, Role::Editor, Role::Admin] {
+            assert_eq!(Role::parse(role.as_str()), Ok(role));
+        }
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected() {
+        assert_err!(Role::parse("owner"));
+        assert_err!(Role::parse("Admin"));
+    }
+
+    #[test]
+    fn admins_are_allowed_more_than_editors_and_viewers() {
+        assert!(Role::Viewer < Role::Editor);
+        assert!(Role::Editor < Role::Admin);
+    }
+}
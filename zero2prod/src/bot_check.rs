@@ -0,0 +1,219 @@
+use crate::clock::Clock;
+use crate::keyed_mac::{self, MacPurpose};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::web::{Bytes, Data};
+use actix_web::{FromRequest, HttpResponse};
+use actix_web_lab::middleware::Next;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+
+// past this, a token is being replayed rather than submitted
+const MAX_TOKEN_AGE_HOURS: i64 = 24;
+
+/// Signs the time the subscription form was rendered.
+///
+/// The form carries the token back, so the server can tell how long it took
+/// to fill it in without trusting a timestamp sent by the client.
+#[derive(Clone)]
+pub struct FormTokens {
+    hmac_secret: Secret<String>,
+    min_time_to_submit: chrono::Duration,
+}
+
+impl FormTokens {
+    pub fn new(hmac_secret: Secret<String>, min_time_to_submit: chrono::Duration) -> Self {
+        Self {
+            hmac_secret,
+            min_time_to_submit,
+        }
+    }
+
+    /// `<unix timestamp>.<hex encoded HMAC of the timestamp>`
+    pub fn issue(&self, rendered_at: DateTime<Utc>) -> String {
+        let timestamp = rendered_at.timestamp().to_string();
+        let signature = keyed_mac::sign(
+            &self.hmac_secret,
+            MacPurpose::FormToken,
+            &[timestamp.as_bytes()],
+        );
+        format!("{}.{}", timestamp, signature)
+    }
+
+    /// `Valid` if we issued `token`, long enough before `now` for a person to
+    /// have filled in the form and not so long ago that it is being replayed.
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> FormTokenCheck {
+        let (timestamp, signature) = match token.split_once('.') {
+            Some(parts) => parts,
+            None => return FormTokenCheck::Invalid,
+        };
+        if !keyed_mac::verify(
+            &self.hmac_secret,
+            MacPurpose::FormToken,
+            &[timestamp.as_bytes()],
+            signature,
+        ) {
+            return FormTokenCheck::Invalid;
+        }
+
+        let rendered_at = match timestamp
+            .parse()
+            .ok()
+            .and_then(|timestamp| DateTime::<Utc>::from_timestamp(timestamp, 0))
+        {
+            Some(rendered_at) => rendered_at,
+            None => return FormTokenCheck::Invalid,
+        };
+        let elapsed = now - rendered_at;
+        if elapsed > chrono::Duration::hours(MAX_TOKEN_AGE_HOURS) {
+            FormTokenCheck::Invalid
+        } else if elapsed < self.min_time_to_submit {
+            FormTokenCheck::TooFast
+        } else {
+            FormTokenCheck::Valid
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FormTokenCheck {
+    Valid,
+    // signed by us, but sent back faster than a person fills in the form
+    TooFast,
+    // forged, malformed or too old
+    Invalid,
+}
+
+#[derive(serde::Deserialize)]
+struct BotCheckFields {
+    // hidden from people, only bots fill it in
+    #[serde(default)]
+    website: String,
+    form_token: Option<String>,
+}
+
+/// Answer a subscription that looks automated with a 200 and go no further:
+/// a bot should not learn that it was caught.
+///
+/// It looks automated when the honeypot is filled in, or when the form token
+/// says the form was submitted faster than a person could. A missing, forged
+/// or expired token is a 400 instead, e.g. for a form that was copied
+/// elsewhere rather than loaded from `GET /subscriptions`: whoever posted it
+/// needs to know. The form is read here and handed back to the handler.
+pub async fn reject_automated_subscriptions(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let form_tokens = req
+        .app_data::<Data<FormTokens>>()
+        .expect("The form tokens are not registered")
+        .clone();
+    let now = req
+        .app_data::<Data<Clock>>()
+        .expect("The clock is not registered")
+        .now();
+
+    let body = {
+        let (http_request, payload) = req.parts_mut();
+        Bytes::from_request(http_request, payload).await
+    }?;
+    let (website, form_token) = match serde_urlencoded::from_bytes(&body) {
+        Ok(BotCheckFields {
+            website,
+            form_token: Some(form_token),
+        }) => (website, form_token),
+        _ => return Err(invalid_form_token()),
+    };
+    let looks_automated = match form_tokens.verify(&form_token, now) {
+        FormTokenCheck::Valid => !website.is_empty(),
+        FormTokenCheck::TooFast => true,
+        FormTokenCheck::Invalid => return Err(invalid_form_token()),
+    };
+    if looks_automated {
+        let e = anyhow::anyhow!("The subscription looks automated");
+        return Err(InternalError::from_response(e, HttpResponse::Ok().finish()).into());
+    }
+    req.set_payload(Payload::from(body));
+
+    next.call(req).await
+}
+
+fn invalid_form_token() -> actix_web::Error {
+    actix_web::error::ErrorBadRequest(
+        "The form token is missing or invalid, load the subscription form again.",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form_tokens() -> FormTokens {
+        FormTokens::new(
+            Secret::new("a-secret-used-only-in-tests".into()),
+            chrono::Duration::seconds(3),
+        )
+    }
+
+    #[test]
+    fn a_token_is_valid_once_a_person_could_have_filled_in_the_form() {
+        let form_tokens = form_tokens();
+        let rendered_at = Utc::now();
+        let token = form_tokens.issue(rendered_at);
+
+        assert_eq!(
+            form_tokens.verify(&token, rendered_at + chrono::Duration::seconds(1)),
+            FormTokenCheck::TooFast
+        );
+        assert_eq!(
+            form_tokens.verify(&token, rendered_at + chrono::Duration::seconds(3)),
+            FormTokenCheck::Valid
+        );
+        assert_eq!(
+            form_tokens.verify(&token, rendered_at + chrono::Duration::hours(1)),
+            FormTokenCheck::Valid
+        );
+    }
+
+    #[test]
+    fn an_old_token_is_rejected() {
+        let form_tokens = form_tokens();
+        let rendered_at = Utc::now();
+        let token = form_tokens.issue(rendered_at);
+
+        assert_eq!(
+            form_tokens.verify(&token, rendered_at + chrono::Duration::days(2)),
+            FormTokenCheck::Invalid
+        );
+    }
+
+    #[test]
+    fn a_token_with_another_timestamp_is_rejected() {
+        let form_tokens = form_tokens();
+        let rendered_at = Utc::now();
+        let token = form_tokens.issue(rendered_at);
+        let (_, signature) = token.split_once('.').unwrap();
+        let earlier = format!("{}.{}", rendered_at.timestamp() - 60, signature);
+
+        assert_eq!(
+            form_tokens.verify(&earlier, rendered_at),
+            FormTokenCheck::Invalid
+        );
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let form_tokens = form_tokens();
+        let now = Utc::now();
+
+        for token in ["", "1700000000", "1700000000.not-hex", "not-a-number.00"] {
+            assert_eq!(
+                form_tokens.verify(token, now),
+                FormTokenCheck::Invalid,
+                "{} was not rejected",
+                token
+            );
+        }
+    }
+}
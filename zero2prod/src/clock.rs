@@ -0,0 +1,48 @@
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex};
+
+/// Where time based codes get the current time from.
+///
+/// Tests use a fixed clock, so they can compute the code the application
+/// expects and move on to the next time step when they need to.
+#[derive(Clone)]
+pub enum Clock {
+    System,
+    Fixed(Arc<Mutex<DateTime<Utc>>>),
+}
+
+impl Clock {
+    pub fn fixed(now: DateTime<Utc>) -> Self {
+        Clock::Fixed(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Fixed(now) => *now.lock().unwrap(),
+        }
+    }
+
+    /// Move a fixed clock forward, the system clock cannot be moved.
+    pub fn advance(&self, by: chrono::Duration) {
+        match self {
+            Clock::System => panic!("The system clock cannot be advanced."),
+            Clock::Fixed(now) => *now.lock().unwrap() += by,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_fixed_clock_only_moves_when_advanced() {
+        let start = Utc::now();
+        let clock = Clock::fixed(start);
+
+        assert_eq!(clock.now(), start);
+        clock.clone().advance(chrono::Duration::seconds(30));
+        assert_eq!(clock.now(), start + chrono::Duration::seconds(30));
+    }
+}
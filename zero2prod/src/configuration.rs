@@ -1,4 +1,10 @@
 use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, FileSender, SesSender, SmtpSender};
+use crate::rate_limit::{RateLimitSettings, SubscribeRateLimiter};
+use crate::request_origin::ClientIpHeader;
+use crate::sns::SnsClient;
+use aws_config::timeout::TimeoutConfig;
+use aws_types::region::Region;
 use secrecy::ExposeSecret;
 use secrecy::Secret;
 use serde_aux::field_attributes::deserialize_number_from_string;
@@ -38,10 +44,38 @@ impl TryFrom<String> for Environment {
 
 #[derive(serde::Deserialize)]
 pub struct EmailClientSettings {
+    pub backend: EmailBackend,
     pub region: String,
     pub endpoint_url: String,
     pub sender_email: String,
     pub timeout_milliseconds: u64,
+    // send issues through SES' bulk API, without the List-Unsubscribe headers
+    pub ses_bulk_sends: bool,
+    pub smtp: Option<SmtpSettings>,
+    pub file: Option<FileSettings>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailBackend {
+    Ses,
+    Smtp,
+    File,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub starttls: bool,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FileSettings {
+    pub directory: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -50,6 +84,13 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    // how long a confirmation link stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_hours: u64,
+    // signs session and flash message cookies, must be at least 64 bytes long
+    pub hmac_secret: Secret<String>,
+    // where the email templates that are not in the database live
+    pub email_templates_directory: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -63,11 +104,34 @@ pub struct DatabaseSettings {
     pub password: Secret<String>,
 }
 
+#[derive(serde::Deserialize)]
+pub struct SesWebhookSettings {
+    // the SNS topic SES publishes bounces and complaints to
+    pub topic_arn: String,
+    // only AWS's signing certificates are trusted when unset
+    pub signing_cert_url_prefix: Option<String>,
+    pub timeout_milliseconds: u64,
+}
+
+/// Protection of `POST /subscriptions` against being used to send spam.
+#[derive(serde::Deserialize)]
+pub struct SubscriptionsSettings {
+    pub rate_limit_per_ip: RateLimitSettings,
+    pub rate_limit_per_email: RateLimitSettings,
+    // the header the proxy in front of us puts the client ip in
+    pub client_ip_header: Option<String>,
+    // forms submitted faster than this are taken for bots
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_seconds_to_submit: u64,
+}
+
 #[derive(serde::Deserialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub ses_webhook: SesWebhookSettings,
+    pub subscriptions: SubscriptionsSettings,
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -84,6 +148,9 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
 
     settings.merge(config::File::from(config_dir.join(env.as_str())).required(true))?;
 
+    // e.g. `APP_APPLICATION__HMAC_SECRET=...` sets `Settings.application.hmac_secret`
+    settings.merge(config::Environment::with_prefix("app").separator("__"))?;
+
     settings.try_into()
 }
 
@@ -120,6 +187,36 @@ impl DatabaseSettings {
     }
 }
 
+impl ApplicationSettings {
+    pub fn subscription_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.subscription_token_ttl_hours as i64)
+    }
+}
+
+impl SubscriptionsSettings {
+    pub fn rate_limiter(&self) -> SubscribeRateLimiter {
+        SubscribeRateLimiter::new(self.rate_limit_per_ip, self.rate_limit_per_email)
+    }
+
+    pub fn client_ip_header(&self) -> ClientIpHeader {
+        ClientIpHeader(self.client_ip_header.clone())
+    }
+
+    pub fn min_time_to_submit(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.min_seconds_to_submit as i64)
+    }
+}
+
+impl SesWebhookSettings {
+    pub fn client(&self) -> SnsClient {
+        SnsClient::new(
+            self.topic_arn.clone(),
+            self.signing_cert_url_prefix.clone(),
+            std::time::Duration::from_millis(self.timeout_milliseconds),
+        )
+    }
+}
+
 impl EmailClientSettings {
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
@@ -128,4 +225,52 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub async fn client(&self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address");
+
+        match self.backend {
+            EmailBackend::Ses => {
+                let aws_conf = aws_config::from_env()
+                    .region(Region::new(self.region.to_owned()))
+                    .timeout_config(
+                        TimeoutConfig::builder()
+                            .operation_timeout(self.timeout())
+                            .build(),
+                    )
+                    .endpoint_url(self.endpoint_url.to_owned())
+                    .load()
+                    .await;
+
+                let sender = SesSender::new(&aws_conf).with_bulk_sends(self.ses_bulk_sends);
+
+                EmailClient::new(sender_email, sender)
+            }
+            EmailBackend::Smtp => {
+                let smtp = self
+                    .smtp
+                    .as_ref()
+                    .expect("The smtp backend needs an `email_client.smtp` section");
+                let credentials = smtp.username.clone().zip(smtp.password.clone());
+                let sender = SmtpSender::new(
+                    &smtp.host,
+                    smtp.port,
+                    smtp.starttls,
+                    credentials,
+                    self.timeout(),
+                )
+                .expect("Failed to set up the SMTP transport");
+
+                EmailClient::new(sender_email, sender)
+            }
+            EmailBackend::File => {
+                let file = self
+                    .file
+                    .as_ref()
+                    .expect("The file backend needs an `email_client.file` section");
+
+                EmailClient::new(sender_email, FileSender::new(&file.directory))
+            }
+        }
+    }
 }
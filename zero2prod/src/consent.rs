@@ -0,0 +1,85 @@
+use crate::request_origin::RequestOrigin;
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+// longer form fields are cut, they are recorded as they came
+const MAX_FIELD_LENGTH: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsentEventKind {
+    Signup,
+    // added by an admin, with consent collected elsewhere
+    Import,
+    Confirmation,
+    Unsubscription,
+}
+
+impl ConsentEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentEventKind::Signup => "signup",
+            ConsentEventKind::Import => "import",
+            ConsentEventKind::Confirmation => "confirmation",
+            ConsentEventKind::Unsubscription => "unsubscription",
+        }
+    }
+}
+
+/// A step in how a subscriber gave or withdrew their consent.
+#[derive(Debug)]
+pub struct ConsentEvent<'a> {
+    pub kind: ConsentEventKind,
+    pub origin: &'a RequestOrigin,
+    // the form, or `admin` for changes made by an admin
+    pub source: Option<&'a str>,
+    // the version of the consent text the subscriber was shown
+    pub consent_text_version: Option<&'a str>,
+}
+
+pub async fn record_consent_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    event: ConsentEvent<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"insert into consent_events (
+               consent_event_id, subscriber_id, kind, occurred_at,
+               ip, user_agent, source, consent_text_version
+           )
+           values ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        event.kind.as_str(),
+        Utc::now(),
+        event.origin.ip.as_deref().map(truncate),
+        event.origin.user_agent.as_deref().map(truncate),
+        event.source.map(truncate),
+        event.consent_text_version.map(truncate),
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+fn truncate(s: &str) -> &str {
+    match s.char_indices().nth(MAX_FIELD_LENGTH) {
+        Some((end, _)) => &s[..end],
+        None => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_fields_are_cut_on_a_char_boundary() {
+        let long = "é".repeat(MAX_FIELD_LENGTH + 1);
+
+        assert_eq!(truncate(&long).chars().count(), MAX_FIELD_LENGTH);
+        assert_eq!(truncate("short"), "short");
+    }
+}
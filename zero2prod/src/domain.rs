@@ -1,7 +1,13 @@
+mod list_slug;
 mod new_subscriber;
+mod segment;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
 
+pub use list_slug::*;
 pub use new_subscriber::*;
+pub use segment::*;
 pub use subscriber_email::*;
 pub use subscriber_name::*;
+pub use subscriber_tag::*;
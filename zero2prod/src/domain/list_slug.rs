@@ -0,0 +1,60 @@
+use std::fmt::{Display, Formatter};
+
+const MAX_LENGTH: usize = 32;
+
+/// How forms and API calls name a list, e.g. `rust-weekly`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    pub fn parse(s: String) -> Result<Self, String> {
+        let slug = s.trim();
+        let is_valid = !slug.is_empty()
+            && slug.len() <= MAX_LENGTH
+            && slug
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+        if is_valid {
+            Ok(Self(slug.to_owned()))
+        } else {
+            Err(format!("{} is not a valid list slug.", s))
+        }
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for ListSlug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn lowercase_letters_digits_and_dashes_are_valid() {
+        assert_ok!(ListSlug::parse("rust-weekly-2".to_string()));
+    }
+
+    #[test]
+    fn a_slug_longer_than_32_characters_is_rejected() {
+        assert_ok!(ListSlug::parse("a".repeat(32)));
+        assert_err!(ListSlug::parse("a".repeat(33)));
+    }
+
+    #[test]
+    fn slugs_containing_an_invalid_character_are_rejected() {
+        for slug in ["", "Rust", "rust weekly", "rust_weekly", "ünïcode"] {
+            assert_err!(ListSlug::parse(slug.to_string()));
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::domain::SubscriberTag;
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+
+const MAX_LENGTH: usize = 1024;
+// nested parentheses and `NOT`s, the parser recurses on each of them
+const MAX_DEPTH: usize = 32;
+
+/// The subscribers an issue goes to, as a boolean expression over their tags,
+/// e.g. `tag:rust AND NOT tag:beta`.
+///
+/// `NOT` binds tighter than `AND`, which binds tighter than `OR`. Keywords are
+/// case-insensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Tag(SubscriberTag),
+    Not(Box<Segment>),
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Tag(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Segment {
+    pub fn parse(s: String) -> Result<Self, String> {
+        if s.len() > MAX_LENGTH {
+            return Err(format!(
+                "The segment is longer than {} characters.",
+                MAX_LENGTH
+            ));
+        }
+
+        let mut parser = Parser {
+            tokens: tokenize(&s)?.into_iter().peekable(),
+            depth: 0,
+        };
+        let segment = parser.parse_or()?;
+        match parser.tokens.next() {
+            None => Ok(segment),
+            Some(token) => Err(format!("Unexpected {} in the segment.", token)),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    s.replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(|word| match word {
+            "(" => Ok(Token::Open),
+            ")" => Ok(Token::Close),
+            _ if word.eq_ignore_ascii_case("and") => Ok(Token::And),
+            _ if word.eq_ignore_ascii_case("or") => Ok(Token::Or),
+            _ if word.eq_ignore_ascii_case("not") => Ok(Token::Not),
+            _ => match word.split_once(':') {
+                Some((prefix, tag)) if prefix.eq_ignore_ascii_case("tag") => {
+                    Ok(Token::Tag(tag.to_owned()))
+                }
+                _ => Err(format!("Unexpected `{}` in the segment.", word)),
+            },
+        })
+        .collect()
+}
+
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+    depth: usize,
+}
+
+impl Parser {
+    fn parse_or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            segment = Segment::Or(Box::new(segment), Box::new(self.parse_and()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_not()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            segment = Segment::And(Box::new(segment), Box::new(self.parse_not()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_not(&mut self) -> Result<Segment, String> {
+        if self.tokens.next_if_eq(&Token::Not).is_none() {
+            return self.parse_atom();
+        }
+        self.nested(|parser| Ok(Segment::Not(Box::new(parser.parse_not()?))))
+    }
+
+    fn parse_atom(&mut self) -> Result<Segment, String> {
+        match self.tokens.next() {
+            Some(Token::Tag(tag)) => SubscriberTag::parse(tag).map(Segment::Tag),
+            Some(Token::Open) => self.nested(|parser| {
+                let segment = parser.parse_or()?;
+                match parser.tokens.next() {
+                    Some(Token::Close) => Ok(segment),
+                    Some(token) => Err(format!("Expected `)`, found {}.", token)),
+                    None => Err("A `(` is never closed in the segment.".into()),
+                }
+            }),
+            Some(token) => Err(format!("Expected a tag, found {}.", token)),
+            None => Err("The segment ended where a tag was expected.".into()),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Segment, String>,
+    ) -> Result<Segment, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!(
+                "The segment is nested more than {} levels deep.",
+                MAX_DEPTH
+            ));
+        }
+        let segment = parse(self);
+        self.depth -= 1;
+        segment
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Tag(tag) => write!(f, "`tag:{}`", tag),
+            Token::And => write!(f, "`AND`"),
+            Token::Or => write!(f, "`OR`"),
+            Token::Not => write!(f, "`NOT`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+/// The canonical form of the expression, as stored alongside an issue.
+impl Display for Segment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Segment::Tag(tag) => write!(f, "tag:{}", tag),
+            Segment::Not(segment) => match segment.as_ref() {
+                Segment::Tag(_) | Segment::Not(_) => write!(f, "NOT {}", segment),
+                _ => write!(f, "NOT ({})", segment),
+            },
+            Segment::And(left, right) => {
+                fmt_operand(left, f)?;
+                write!(f, " AND ")?;
+                fmt_operand(right, f)
+            }
+            Segment::Or(left, right) => write!(f, "{} OR {}", left, right),
+        }
+    }
+}
+
+// an `OR` inside an `AND` needs its parentheses back
+fn fmt_operand(segment: &Segment, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match segment {
+        Segment::Or(_, _) => write!(f, "({})", segment),
+        _ => write!(f, "{}", segment),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::assert_err;
+
+    fn tag(name: &str) -> Box<Segment> {
+        Box::new(Segment::Tag(
+            SubscriberTag::parse(name.to_string()).unwrap(),
+        ))
+    }
+
+    fn parse(s: &str) -> Segment {
+        Segment::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn a_single_tag_is_a_segment() {
+        assert_eq!(parse("tag:rust"), *tag("rust"));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert_eq!(
+            parse("tag:rust AND NOT tag:beta"),
+            Segment::And(tag("rust"), Box::new(Segment::Not(tag("beta"))))
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("tag:a OR tag:b AND tag:c"),
+            Segment::Or(tag("a"), Box::new(Segment::And(tag("b"), tag("c"))))
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_eq!(
+            parse("(tag:a OR tag:b) AND tag:c"),
+            Segment::And(Box::new(Segment::Or(tag("a"), tag("b"))), tag("c"))
+        );
+    }
+
+    #[test]
+    fn keywords_and_tags_are_case_insensitive() {
+        assert_eq!(
+            parse("TAG:Rust and not tag:BETA"),
+            parse("tag:rust AND NOT tag:beta")
+        );
+    }
+
+    #[test]
+    fn the_canonical_form_parses_back_to_the_same_segment() {
+        for s in [
+            "tag:rust",
+            "tag:rust AND NOT tag:beta",
+            "NOT (tag:a OR tag:b) AND tag:c",
+            "(tag:a OR tag:b) AND (tag:c OR NOT NOT tag:d)",
+            "tag:a OR tag:b AND tag:c",
+        ] {
+            let segment = parse(s);
+
+            assert_eq!(segment.to_string(), s);
+            assert_eq!(parse(&segment.to_string()), segment);
+        }
+    }
+
+    #[test]
+    fn malformed_segments_are_rejected() {
+        for s in [
+            "",
+            "rust",
+            "tag:",
+            "tag:rust AND",
+            "AND tag:rust",
+            "tag:rust tag:beta",
+            "(tag:rust",
+            "tag:rust)",
+            "tag:rust OR OR tag:beta",
+            "tag:not valid",
+            "name:rust",
+        ] {
+            assert_err!(Segment::parse(s.to_string()), "{:?} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_segments_are_rejected() {
+        let s = format!("{}tag:rust{}", "(".repeat(33), ")".repeat(33));
+        assert_err!(Segment::parse(s));
+
+        let s = format!("{}tag:rust", "NOT ".repeat(33));
+        assert_err!(Segment::parse(s));
+    }
+
+    #[test]
+    fn overly_long_segments_are_rejected() {
+        let s = vec!["tag:rust"; 200].join(" OR ");
+        assert_err!(Segment::parse(s));
+    }
+}
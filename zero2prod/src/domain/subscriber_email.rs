@@ -1,7 +1,7 @@
 use std::fmt::{Display, Formatter};
 use validator::validate_email;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+
+const MAX_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    /// Tags are case-insensitive, they are stored in lowercase.
+    pub fn parse(s: String) -> Result<Self, String> {
+        let tag = s.trim().to_lowercase();
+        let is_valid = !tag.is_empty()
+            && tag.len() <= MAX_LENGTH
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if is_valid {
+            Ok(Self(tag))
+        } else {
+            Err(format!("{} is not a valid subscriber tag.", s))
+        }
+    }
+
+    /// Parse a comma separated list of tags, as sent by the subscription form.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        let mut tags = s
+            .split(',')
+            .filter(|tag| !tag.trim().is_empty())
+            .map(|tag| Self::parse(tag.to_owned()))
+            .collect::<Result<Vec<_>, _>>()?;
+        tags.sort();
+        tags.dedup();
+
+        Ok(tags)
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for SubscriberTag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn tags_are_lowercased_and_trimmed() {
+        let tag = SubscriberTag::parse(" Rust ".to_string()).unwrap();
+        assert_eq!(tag.as_ref(), "rust");
+    }
+
+    #[test]
+    fn a_32_character_long_tag_is_valid() {
+        assert_ok!(SubscriberTag::parse("a".repeat(32)));
+    }
+
+    #[test]
+    fn a_tag_longer_than_32_characters_is_rejected() {
+        assert_err!(SubscriberTag::parse("a".repeat(33)));
+    }
+
+    #[test]
+    fn empty_tags_are_rejected() {
+        assert_err!(SubscriberTag::parse(" ".to_string()));
+    }
+
+    #[test]
+    fn tags_containing_an_invalid_character_are_rejected() {
+        for tag in ["rust beta", "tag:rust", "(rust)", "rust,beta", "ünïcode"] {
+            assert_err!(SubscriberTag::parse(tag.to_string()));
+        }
+    }
+
+    #[test]
+    fn lists_are_split_on_commas_and_deduplicated() {
+        let tags = SubscriberTag::parse_list("rust, beta,,Rust").unwrap();
+
+        let tags: Vec<&str> = tags.iter().map(|t| t.as_ref()).collect();
+        assert_eq!(tags, vec!["beta", "rust"]);
+    }
+
+    #[test]
+    fn a_list_with_an_invalid_tag_is_rejected() {
+        assert_err!(SubscriberTag::parse_list("rust, not valid"));
+    }
+}
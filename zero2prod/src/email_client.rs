@@ -1,35 +1,53 @@
-use crate::domain::SubscriberEmail;
-use aws_config::retry::RetryConfig;
-use aws_sdk_sesv2 as sesv2;
-use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
-use aws_sdk_sesv2::types::{Body, Content, Destination, EmailContent, Message};
-use aws_sdk_sesv2::{config::Region, meta::PKG_VERSION, Client};
+mod file;
+mod message;
+mod ses;
+mod smtp;
 
-const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+use crate::domain::SubscriberEmail;
+use std::sync::Arc;
+
+pub use file::FileSender;
+pub use message::{BatchMessage, EmailMessage, Recipient};
+pub use ses::SesSender;
+pub use smtp::SmtpSender;
+
+/// A way of getting a rendered message to its recipient.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error>;
+
+    /// Send a batch of emails, with one result per recipient in the order of
+    /// `batch.recipients`.
+    async fn send_batch(&self, batch: &BatchMessage<'_>) -> Vec<Result<(), anyhow::Error>> {
+        let mut results = Vec::with_capacity(batch.recipients.len());
+        for message in batch.messages() {
+            results.push(self.send(&message).await);
+        }
+        results
+    }
+}
 
+/// Sends emails on behalf of the newsletter, whatever the backend.
+#[derive(Clone)]
 pub struct EmailClient {
-    client: Client,
     sender: SubscriberEmail,
+    backend: Arc<dyn EmailSender>,
 }
 
-// #[derive(serde::Serialize)]
-// #[serde(rename_all = "PascalCase")]
-// struct SendEmailRequest<'a> {
-//     from: &'a str,
-//     to: &'a str,
-//     subject: &'a str,
-//     html_body: &'a str,
-//     text_body: &'a str,
-// }
-
 impl EmailClient {
-    pub fn new(
-        sender: SubscriberEmail,
-        timeout: std::time::Duration,
-        config: aws_config::SdkConfig,
-    ) -> Self {
-        let client = Client::new(&config);
-        Self { client, sender }
+    pub fn new(sender: SubscriberEmail, backend: impl EmailSender + 'static) -> Self {
+        Self {
+            sender,
+            backend: Arc::new(backend),
+        }
+    }
+
+    /// The same backend, sending as someone else, e.g. a list.
+    pub fn with_sender(&self, sender: SubscriberEmail) -> Self {
+        Self {
+            sender,
+            backend: self.backend.clone(),
+        }
     }
 
     pub async fn send_email(
@@ -38,162 +56,54 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<SendEmailOutput, sesv2::Error> {
-        let dest = Destination::builder()
-            .to_addresses(self.sender.as_ref())
-            .build();
-        let subject = Content::builder().data(subject).charset("UTF-8").build();
-        let html_body = Content::builder()
-            .data(html_content)
-            .charset("UTF-8")
-            .build();
-        let text_body = Content::builder()
-            .data(text_content)
-            .charset("UTF-8")
-            .build();
-        let body = Body::builder().html(html_body).text(text_body).build();
-        let msg = Message::builder().subject(subject).body(body).build();
-        let email = EmailContent::builder().simple(msg).build();
-
-        self.client
-            .send_email()
-            .from_email_address(recipient.as_ref())
-            .destination(dest)
-            .content(email)
-            .send()
-            .await
-            .map_err(sesv2::Error::from)
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::domain::SubscriberEmail;
-    use aws_config::timeout::TimeoutConfig;
-    use claim::{assert_err, assert_ok};
-    use fake::faker::internet::en::SafeEmail;
-    use fake::faker::lorem::en::{Paragraph, Sentence};
-    use fake::{Fake, Faker};
-    use std::time::Duration;
-    use wiremock::matchers::{any, header, method, path};
-    use wiremock::Request;
-    use wiremock::{Mock, MockServer, ResponseTemplate};
-
-    fn subject() -> String {
-        Sentence(1..2).fake()
-    }
-
-    fn content() -> String {
-        Paragraph(1..10).fake()
-    }
-
-    fn email() -> SubscriberEmail {
-        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
-    }
-
-    fn email_client(conf: aws_config::SdkConfig) -> EmailClient {
-        EmailClient::new(email(), std::time::Duration::from_millis(200), conf)
-    }
-
-    // for validating the json body in the request
-    struct SendEmailBodyMatcher;
-
-    impl wiremock::Match for SendEmailBodyMatcher {
-        fn matches(&self, request: &Request) -> bool {
-            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
-            if let Ok(body) = result {
-                body.get("From").is_some()
-                    && body.get("To").is_some()
-                    && body.get("Subject").is_some()
-                    && body.get("HtmlBody").is_some()
-                    && body.get("TextBody").is_some()
-            } else {
-                false
-            }
-        }
+        unsubscribe_link: &str,
+    ) -> Result<(), anyhow::Error> {
+        let message = EmailMessage {
+            sender: &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_link: Some(unsubscribe_link),
+        };
+
+        self.backend.send(&message).await
     }
 
-    #[tokio::test]
-    async fn send_email_sends_the_expected_request() {
-        // Setup
-        let mock_server = MockServer::start().await;
-        let conf = aws_config::from_env()
-            .endpoint_url(mock_server.uri())
-            .load()
-            .await;
-        let email_client = email_client(conf);
-
-        Mock::given(path(SEND_EMAIL_END_POINT))
-            .and(header("Content-Type", "application/json"))
-            .and(method("POST"))
-            //.and(SendEmailBodyMatcher)
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let content: String = content();
-
-        // Act
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content, &content)
-            .await;
-
-        // Assert
-        assert_ok!(outcome);
-    }
-
-    #[tokio::test]
-    async fn send_email_fails_if_the_server_returns_500() {
-        let mock_server = MockServer::start().await;
-        let conf = aws_config::from_env()
-            .endpoint_url(mock_server.uri())
-            .retry_config(RetryConfig::standard().with_max_attempts(1))
-            .load()
-            .await;
-        let email_client = email_client(conf);
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(500))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let content = content();
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content, &content)
-            .await;
-
-        assert_err!(outcome);
+    /// Send an email that is not about a subscription, e.g. an invitation,
+    /// so it has nothing to unsubscribe from.
+    pub async fn send_transactional_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        let message = EmailMessage {
+            sender: &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_link: None,
+        };
+
+        self.backend.send(&message).await
     }
 
-    #[tokio::test]
-    async fn send_email_times_out_if_the_server_takes_too_long() {
-        let mock_server = MockServer::start().await;
-        let conf = aws_config::from_env()
-            .endpoint_url(mock_server.uri())
-            .timeout_config(
-                TimeoutConfig::builder()
-                    .operation_timeout(Duration::from_secs(2))
-                    .build(),
-            )
-            .load()
-            .await;
-        let email_client = email_client(conf);
-
-        let reponse = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
-        Mock::given(any())
-            .respond_with(reponse)
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let content = content();
-        let outcome = email_client
-            .send_email(&email(), &subject(), &content, &content)
-            .await;
-
-        assert_err!(outcome);
+    /// Send an email to every recipient, so that backends able to do it can
+    /// batch the deliveries. One result per recipient, in the same order.
+    pub async fn send_batch(
+        &self,
+        subject: &str,
+        recipients: &[Recipient<'_>],
+    ) -> Vec<Result<(), anyhow::Error>> {
+        let batch = BatchMessage {
+            sender: &self.sender,
+            subject,
+            recipients,
+        };
+
+        self.backend.send_batch(&batch).await
     }
 }
@@ -0,0 +1,83 @@
+use super::{EmailMessage, EmailSender};
+use anyhow::Context;
+use chrono::Utc;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// Writes every email as an `.eml` file in a directory, for local development.
+pub struct FileSender {
+    directory: PathBuf,
+}
+
+impl FileSender {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSender {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create the email directory.")?;
+
+        // sortable by time, unique even within the same millisecond
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            Uuid::new_v4().simple()
+        );
+        tokio::fs::write(self.directory.join(file_name), message.to_mime())
+            .await
+            .context("Failed to write the email to disk.")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailClient;
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_email_is_written_to_its_own_eml_file() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let email_client =
+            EmailClient::new(email("newsletter@example.com"), FileSender::new(&directory));
+
+        for _ in 0..2 {
+            email_client
+                .send_email(
+                    &email("ursula@example.com"),
+                    "Welcome!",
+                    "<p>Hello</p>",
+                    "Hello",
+                    "https://example.com/subscriptions/unsubscribe?token=abc",
+                )
+                .await
+                .unwrap();
+        }
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 2);
+        for file in files {
+            assert_eq!(file.extension().unwrap(), "eml");
+            let eml = std::fs::read_to_string(file).unwrap();
+            assert!(eml.contains("To: ursula@example.com\r\n"));
+        }
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
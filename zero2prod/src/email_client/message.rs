@@ -0,0 +1,279 @@
+use crate::domain::SubscriberEmail;
+use chrono::Utc;
+use uuid::Uuid;
+
+// base64 encoded lines must not exceed 76 characters (RFC 2045)
+const LINE_LENGTH: usize = 76;
+
+// header lines should not exceed 78 characters (RFC 5322), name included
+const HEADER_LINE_LENGTH: usize = 78;
+
+// base64 encodes 42 bytes to 56 characters, with `=?UTF-8?B?` and `?=` an
+// encoded word and the `Subject: ` in front of the first one fit in a line
+const ENCODED_WORD_BYTES: usize = 42;
+
+/// An email rendered as a MIME `multipart/alternative` message.
+///
+/// SES' simple content does not let us set arbitrary headers, so we build
+/// the raw message ourselves.
+pub struct EmailMessage<'a> {
+    pub sender: &'a SubscriberEmail,
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    // without one the message carries no List-Unsubscribe headers
+    pub unsubscribe_link: Option<&'a str>,
+}
+
+/// One recipient of a batch, with the content rendered for them.
+pub struct Recipient<'a> {
+    pub email: &'a SubscriberEmail,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub unsubscribe_link: &'a str,
+}
+
+/// An email with the same subject sent to many recipients.
+pub struct BatchMessage<'a> {
+    pub sender: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub recipients: &'a [Recipient<'a>],
+}
+
+impl<'a> BatchMessage<'a> {
+    /// The message each recipient gets, in the order of `recipients`.
+    pub fn messages(&self) -> impl Iterator<Item = EmailMessage<'_>> + Send {
+        self.recipients.iter().map(|recipient| EmailMessage {
+            sender: self.sender,
+            recipient: recipient.email,
+            subject: self.subject,
+            html_content: recipient.html_content,
+            text_content: recipient.text_content,
+            unsubscribe_link: Some(recipient.unsubscribe_link),
+        })
+    }
+}
+
+impl<'a> EmailMessage<'a> {
+    pub fn to_mime(&self) -> Vec<u8> {
+        let boundary = format!("boundary-{}", Uuid::new_v4().simple());
+        let sender_domain = self
+            .sender
+            .as_ref()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or("localhost");
+
+        let mut headers = vec![
+            ("From", self.sender.as_ref().to_owned()),
+            ("To", self.recipient.as_ref().to_owned()),
+            ("Subject", encode_header_value("Subject", self.subject)),
+            ("Date", Utc::now().to_rfc2822()),
+            (
+                "Message-ID",
+                format!("<{}@{}>", Uuid::new_v4(), sender_domain),
+            ),
+            ("MIME-Version", "1.0".to_owned()),
+        ];
+        if let Some(unsubscribe_link) = self.unsubscribe_link {
+            // RFC 2369 and RFC 8058 one-click unsubscribe
+            headers.push(("List-Unsubscribe", format!("<{}>", unsubscribe_link)));
+            headers.push((
+                "List-Unsubscribe-Post",
+                "List-Unsubscribe=One-Click".to_owned(),
+            ));
+        }
+        headers.push((
+            "Content-Type",
+            format!("multipart/alternative; boundary=\"{}\"", boundary),
+        ));
+
+        let mut message = String::new();
+        for (name, value) in headers.iter() {
+            message.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        message.push_str("\r\n");
+
+        // the last part is the preferred one, so html goes after text
+        for (content_type, content) in [
+            ("text/plain", self.text_content),
+            ("text/html", self.html_content),
+        ] {
+            message.push_str(&format!("--{}\r\n", boundary));
+            message.push_str(&format!(
+                "Content-Type: {}; charset=UTF-8\r\n",
+                content_type
+            ));
+            message.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+            message.push_str(&encode_body(content));
+        }
+        message.push_str(&format!("--{}--\r\n", boundary));
+
+        message.into_bytes()
+    }
+}
+
+/// `value` as is if it fits on the header line, otherwise as RFC 2047 encoded
+/// words, one per line, so that non-ascii and long subjects survive the trip.
+///
+/// Control characters become spaces: a CR or LF would end the header, and let
+/// whoever wrote `value` add headers of their own or break the MIME structure.
+fn encode_header_value(name: &str, value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if value.is_ascii() && name.len() + 2 + value.len() <= HEADER_LINE_LENGTH {
+        return value;
+    }
+
+    // split on character boundaries, an encoded word must decode on its own
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        if i + c.len_utf8() - start > ENCODED_WORD_BYTES {
+            words.push(&value[start..i]);
+            start = i;
+        }
+    }
+    words.push(&value[start..]);
+
+    words
+        .iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", base64::encode(word)))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+fn encode_body(content: &str) -> String {
+    let encoded = base64::encode(content);
+    let mut body = String::with_capacity(encoded.len() + encoded.len() / LINE_LENGTH * 2 + 2);
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        // base64 output is ascii
+        body.push_str(std::str::from_utf8(line).unwrap());
+        body.push_str("\r\n");
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    fn render() -> String {
+        render_with(Some(
+            "https://example.com/subscriptions/unsubscribe?token=abc",
+        ))
+    }
+
+    fn render_with(unsubscribe_link: Option<&str>) -> String {
+        let sender = email("newsletter@example.com");
+        let recipient = email("ursula@example.com");
+        let message = EmailMessage {
+            sender: &sender,
+            recipient: &recipient,
+            subject: "Welcome!",
+            html_content: "<p>Hello</p>",
+            text_content: "Hello",
+            unsubscribe_link,
+        };
+        String::from_utf8(message.to_mime()).unwrap()
+    }
+
+    #[test]
+    fn the_message_carries_one_click_unsubscribe_headers() {
+        let mime = render();
+
+        assert!(mime.contains(
+            "List-Unsubscribe: <https://example.com/subscriptions/unsubscribe?token=abc>\r\n"
+        ));
+        assert!(mime.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+    }
+
+    #[test]
+    fn a_message_without_an_unsubscribe_link_has_no_unsubscribe_headers() {
+        let mime = render_with(None);
+
+        assert!(!mime.contains("List-Unsubscribe"));
+    }
+
+    #[test]
+    fn the_message_is_addressed_from_the_sender_to_the_recipient() {
+        let mime = render();
+
+        assert!(mime.contains("From: newsletter@example.com\r\n"));
+        assert!(mime.contains("To: ursula@example.com\r\n"));
+    }
+
+    #[test]
+    fn both_bodies_are_base64_encoded() {
+        let mime = render();
+
+        assert!(mime.contains(&base64::encode("Hello")));
+        assert!(mime.contains(&base64::encode("<p>Hello</p>")));
+    }
+
+    #[test]
+    fn non_ascii_subjects_are_encoded() {
+        assert_eq!(
+            encode_header_value("Subject", "Bienvenue à bord"),
+            format!("=?UTF-8?B?{}?=", base64::encode("Bienvenue à bord"))
+        );
+    }
+
+    #[test]
+    fn a_subject_cannot_add_headers() {
+        let sender = email("newsletter@example.com");
+        let recipient = email("ursula@example.com");
+        let message = EmailMessage {
+            sender: &sender,
+            recipient: &recipient,
+            subject: "Welcome!\r\nBcc: everyone@example.com\r\n\r\nHi",
+            html_content: "<p>Hello</p>",
+            text_content: "Hello",
+            unsubscribe_link: None,
+        };
+        let mime = String::from_utf8(message.to_mime()).unwrap();
+
+        assert!(mime.contains("Subject: Welcome!  Bcc: everyone@example.com    Hi\r\n"));
+        assert!(!mime.contains("\r\nBcc:"));
+    }
+
+    #[test]
+    fn long_subjects_are_folded_into_encoded_words() {
+        let subject = "Ünïcödé ".repeat(20);
+
+        let encoded = encode_header_value("Subject", &subject);
+
+        let lines: Vec<&str> = encoded.split("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!("Subject: ".len() + lines[0].len() <= HEADER_LINE_LENGTH);
+        assert!(lines[1..]
+            .iter()
+            .all(|line| line.starts_with(' ') && line.len() <= HEADER_LINE_LENGTH));
+        let decoded: String = lines
+            .iter()
+            .map(|line| {
+                let word = line
+                    .trim_start()
+                    .strip_prefix("=?UTF-8?B?")
+                    .and_then(|word| word.strip_suffix("?="))
+                    .unwrap();
+                String::from_utf8(base64::decode(word).unwrap()).unwrap()
+            })
+            .collect();
+        assert_eq!(decoded, subject);
+    }
+
+    #[test]
+    fn body_lines_are_at_most_76_characters_long() {
+        let body = encode_body(&"a".repeat(1000));
+
+        assert!(body.split("\r\n").all(|line| line.len() <= LINE_LENGTH));
+    }
+}
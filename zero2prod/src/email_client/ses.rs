@@ -0,0 +1,648 @@
+use super::{BatchMessage, EmailMessage, EmailSender, Recipient};
+use aws_sdk_sesv2 as sesv2;
+use aws_sdk_sesv2::primitives::Blob;
+use aws_sdk_sesv2::types::{
+    BulkEmailContent, BulkEmailEntry, BulkEmailStatus, Destination, EmailContent,
+    EmailTemplateContent, RawMessage, ReplacementEmailContent, ReplacementTemplate, Template,
+};
+use aws_sdk_sesv2::Client;
+
+// the most entries SendBulkEmail takes at once
+const MAX_BULK_ENTRIES: usize = 50;
+
+// every part comes from the replacement data of the recipient, the triple
+// braces keep SES from escaping it
+//
+// bump the version whenever the content changes: a stored template is not
+// updated
+const BULK_TEMPLATE_NAME: &str = "zero2prod-batch-v1";
+const BULK_TEMPLATE_SUBJECT: &str = "{{{subject}}}";
+const BULK_TEMPLATE_HTML: &str = "{{{html}}}";
+const BULK_TEMPLATE_TEXT: &str = "{{{text}}}";
+
+/// Sends emails through the SESv2 API.
+///
+/// An email goes out on its own as a raw message, so that it carries the
+/// `List-Unsubscribe` headers. With `bulk_sends` a batch goes out through
+/// `SendBulkEmail` instead, with a stored template filled in with the content
+/// of every recipient: far fewer calls, but the bulk API of the SDK we are
+/// pinned to cannot set headers, the unsubscribe link is only in the body.
+pub struct SesSender {
+    client: Client,
+    bulk_sends: bool,
+}
+
+impl SesSender {
+    pub fn new(config: &aws_config::SdkConfig) -> Self {
+        Self {
+            client: Client::new(config),
+            bulk_sends: false,
+        }
+    }
+
+    pub fn with_bulk_sends(self, bulk_sends: bool) -> Self {
+        Self { bulk_sends, ..self }
+    }
+
+    /// Store the template of bulk sends, unless it already is.
+    async fn create_bulk_template(&self) -> Result<(), anyhow::Error> {
+        let content = EmailTemplateContent::builder()
+            .subject(BULK_TEMPLATE_SUBJECT)
+            .html(BULK_TEMPLATE_HTML)
+            .text(BULK_TEMPLATE_TEXT)
+            .build();
+        let result = self
+            .client
+            .create_email_template()
+            .template_name(BULK_TEMPLATE_NAME)
+            .template_content(content)
+            .send()
+            .await;
+
+        match result.map_err(|e| e.into_service_error()) {
+            Ok(_) => Ok(()),
+            Err(e) if e.is_already_exists_exception() => Ok(()),
+            Err(e) => Err(sesv2::Error::from(e).into()),
+        }
+    }
+
+    /// One result per recipient, in order: at most `MAX_BULK_ENTRIES` of the
+    /// recipients of `batch`.
+    async fn send_bulk(
+        &self,
+        batch: &BatchMessage<'_>,
+        recipients: &[Recipient<'_>],
+    ) -> Vec<Result<(), anyhow::Error>> {
+        let template = Template::builder()
+            .template_name(BULK_TEMPLATE_NAME)
+            .template_data("{}")
+            .build();
+        let entries = recipients
+            .iter()
+            .map(|recipient| {
+                let data = serde_json::json!({
+                    "subject": batch.subject,
+                    "html": recipient.html_content,
+                    "text": recipient.text_content,
+                });
+                BulkEmailEntry::builder()
+                    .destination(
+                        Destination::builder()
+                            .to_addresses(recipient.email.as_ref())
+                            .build(),
+                    )
+                    .replacement_email_content(
+                        ReplacementEmailContent::builder()
+                            .replacement_template(
+                                ReplacementTemplate::builder()
+                                    .replacement_template_data(data.to_string())
+                                    .build(),
+                            )
+                            .build(),
+                    )
+                    .build()
+            })
+            .collect();
+
+        let output = match self
+            .client
+            .send_bulk_email()
+            .from_email_address(batch.sender.as_ref())
+            .default_content(BulkEmailContent::builder().template(template).build())
+            .set_bulk_email_entries(Some(entries))
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) => {
+                // the error cannot be cloned, every recipient gets its message
+                let e = anyhow::Error::from(sesv2::Error::from(e));
+                return recipients
+                    .iter()
+                    .map(|_| Err(anyhow::anyhow!("{:#}", e)))
+                    .collect();
+            }
+        };
+
+        let results = output.bulk_email_entry_results().unwrap_or_default();
+        (0..recipients.len())
+            .map(|i| match results.get(i) {
+                Some(result) if result.status() == Some(&BulkEmailStatus::Success) => Ok(()),
+                Some(result) => Err(anyhow::anyhow!(
+                    "SES did not accept the email: {} {}",
+                    result.status().map(|s| s.as_str()).unwrap_or("UNKNOWN"),
+                    result.error().unwrap_or_default()
+                )),
+                None => Err(anyhow::anyhow!("SES returned no result for the email")),
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SesSender {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error> {
+        let dest = Destination::builder()
+            .to_addresses(message.recipient.as_ref())
+            .build();
+        // simple content cannot carry the List-Unsubscribe headers
+        let raw_message = RawMessage::builder()
+            .data(Blob::new(message.to_mime()))
+            .build();
+        let email = EmailContent::builder().raw(raw_message).build();
+
+        self.client
+            .send_email()
+            .from_email_address(message.sender.as_ref())
+            .destination(dest)
+            .content(email)
+            .send()
+            .await
+            .map_err(sesv2::Error::from)?;
+
+        Ok(())
+    }
+
+    async fn send_batch(&self, batch: &BatchMessage<'_>) -> Vec<Result<(), anyhow::Error>> {
+        if !self.bulk_sends {
+            let mut results = Vec::with_capacity(batch.recipients.len());
+            for message in batch.messages() {
+                results.push(self.send(&message).await);
+            }
+            return results;
+        }
+
+        if let Err(e) = self.create_bulk_template().await {
+            let e = e.context("Failed to store the template of bulk sends.");
+            return batch
+                .recipients
+                .iter()
+                .map(|_| Err(anyhow::anyhow!("{:#}", e)))
+                .collect();
+        }
+        let mut results = Vec::with_capacity(batch.recipients.len());
+        for recipients in batch.recipients.chunks(MAX_BULK_ENTRIES) {
+            results.extend(self.send_bulk(batch, recipients).await);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, Recipient};
+    use aws_config::retry::RetryConfig;
+    use aws_config::timeout::TimeoutConfig;
+    use claim::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::Fake;
+    use std::time::Duration;
+    use wiremock::matchers::{any, header, method, path};
+    use wiremock::Request;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+    const SEND_BULK_EMAIL_END_POINT: &str = "/v2/email/outbound-bulk-emails";
+    const TEMPLATES_END_POINT: &str = "/v2/email/templates";
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn unsubscribe_link() -> String {
+        "https://example.com/subscriptions/unsubscribe?subscriber_id=1&token=abc".into()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(sender: SubscriberEmail, conf: aws_config::SdkConfig) -> EmailClient {
+        EmailClient::new(sender, SesSender::new(&conf))
+    }
+
+    fn bulk_email_client(sender: SubscriberEmail, conf: aws_config::SdkConfig) -> EmailClient {
+        EmailClient::new(sender, SesSender::new(&conf).with_bulk_sends(true))
+    }
+
+    fn recipients<'a>(
+        emails: &'a [SubscriberEmail],
+        content: &'a str,
+        link: &'a str,
+    ) -> Vec<Recipient<'a>> {
+        emails
+            .iter()
+            .map(|email| Recipient {
+                email,
+                html_content: content,
+                text_content: content,
+                unsubscribe_link: link,
+            })
+            .collect()
+    }
+
+    // accepts every entry of a SendBulkEmail request
+    struct AcceptEveryEntry;
+
+    impl wiremock::Respond for AcceptEveryEntry {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let n_entries = body["BulkEmailEntries"].as_array().unwrap().len();
+            let results: Vec<_> = (0..n_entries)
+                .map(|_| serde_json::json!({ "Status": "SUCCESS" }))
+                .collect();
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "BulkEmailEntryResults": results }))
+        }
+    }
+
+    // for validating the json body of a SendEmail request
+    struct SendEmailBodyMatcher {
+        sender: SubscriberEmail,
+        recipient: SubscriberEmail,
+    }
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("FromEmailAddress").and_then(|v| v.as_str()) == Some(self.sender.as_ref())
+                    && body
+                        .pointer("/Destination/ToAddresses/0")
+                        .and_then(|v| v.as_str())
+                        == Some(self.recipient.as_ref())
+                    && body.pointer("/Content/Raw/Data").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Setup
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .load()
+            .await;
+        let sender = email();
+        let recipient = email();
+        let email_client = email_client(sender.clone(), conf);
+
+        Mock::given(path(SEND_EMAIL_END_POINT))
+            .and(header("Content-Type", "application/json"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher {
+                sender,
+                recipient: recipient.clone(),
+            })
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content: String = content();
+
+        // Act
+        let outcome = email_client
+            .send_email(
+                &recipient,
+                &subject(),
+                &content,
+                &content,
+                &unsubscribe_link(),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_keeps_the_one_click_unsubscribe_headers() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .load()
+            .await;
+        let email_client = email_client(email(), conf);
+
+        Mock::given(path(SEND_EMAIL_END_POINT))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let link = unsubscribe_link();
+        email_client
+            .send_email(&email(), &subject(), &content, &content, &link)
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let raw = base64::decode(body["Content"]["Raw"]["Data"].as_str().unwrap()).unwrap();
+        let raw = String::from_utf8(raw).unwrap();
+        assert!(raw.contains(&format!("List-Unsubscribe: <{}>\r\n", link)));
+        assert!(raw.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_one_raw_email_per_recipient() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .load()
+            .await;
+        let sender = email();
+        let emails = [email(), email()];
+        let email_client = email_client(sender.clone(), conf);
+
+        for recipient in &emails {
+            Mock::given(path(SEND_EMAIL_END_POINT))
+                .and(method("POST"))
+                .and(SendEmailBodyMatcher {
+                    sender: sender.clone(),
+                    recipient: recipient.clone(),
+                })
+                .respond_with(ResponseTemplate::new(200))
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let content = content();
+        let link = unsubscribe_link();
+        let results = email_client
+            .send_batch(&subject(), &recipients(&emails, &content, &link))
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn bulk_sends_fill_in_the_stored_template_for_every_recipient() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .load()
+            .await;
+        let sender = email();
+        let emails = [email(), email()];
+        let email_client = bulk_email_client(sender.clone(), conf);
+
+        Mock::given(path(TEMPLATES_END_POINT))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path(SEND_BULK_EMAIL_END_POINT))
+            .and(method("POST"))
+            .respond_with(AcceptEveryEntry)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let subject = subject();
+        let content = content();
+        let link = unsubscribe_link();
+        let results = email_client
+            .send_batch(&subject, &recipients(&emails, &content, &link))
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_ok()));
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body["FromEmailAddress"], sender.as_ref());
+        assert_eq!(
+            body["DefaultContent"]["Template"]["TemplateName"],
+            BULK_TEMPLATE_NAME
+        );
+        for (entry, recipient) in body["BulkEmailEntries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(&emails)
+        {
+            assert_eq!(entry["Destination"]["ToAddresses"][0], recipient.as_ref());
+            let data = entry["ReplacementEmailContent"]["ReplacementTemplate"]
+                ["ReplacementTemplateData"]
+                .as_str()
+                .unwrap();
+            let data: serde_json::Value = serde_json::from_str(data).unwrap();
+            assert_eq!(data["subject"], subject.as_str());
+            assert_eq!(data["html"], content.as_str());
+            assert_eq!(data["text"], content.as_str());
+        }
+    }
+
+    #[tokio::test]
+    async fn bulk_sends_reuse_a_template_that_is_already_stored() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .retry_config(RetryConfig::standard().with_max_attempts(1))
+            .load()
+            .await;
+        let email_client = bulk_email_client(email(), conf);
+
+        Mock::given(path(TEMPLATES_END_POINT))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .insert_header("x-amzn-errortype", "AlreadyExistsException")
+                    .set_body_json(serde_json::json!({ "message": "already exists" })),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path(SEND_BULK_EMAIL_END_POINT))
+            .respond_with(AcceptEveryEntry)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let emails = [email()];
+        let content = content();
+        let link = unsubscribe_link();
+        let results = email_client
+            .send_batch(&subject(), &recipients(&emails, &content, &link))
+            .await;
+
+        assert_ok!(&results[0]);
+    }
+
+    #[tokio::test]
+    async fn bulk_sends_take_at_most_50_recipients_at_once() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .load()
+            .await;
+        let email_client = bulk_email_client(email(), conf);
+
+        Mock::given(path(TEMPLATES_END_POINT))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(path(SEND_BULK_EMAIL_END_POINT))
+            .respond_with(AcceptEveryEntry)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let emails: Vec<_> = (0..51).map(|_| email()).collect();
+        let content = content();
+        let link = unsubscribe_link();
+        let results = email_client
+            .send_batch(&subject(), &recipients(&emails, &content, &link))
+            .await;
+
+        assert_eq!(results.len(), 51);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn bulk_sends_report_the_outcome_of_every_recipient() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .load()
+            .await;
+        let email_client = bulk_email_client(email(), conf);
+
+        Mock::given(path(TEMPLATES_END_POINT))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(path(SEND_BULK_EMAIL_END_POINT))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "BulkEmailEntryResults": [
+                    { "Status": "SUCCESS", "MessageId": "1" },
+                    { "Status": "MESSAGE_REJECTED", "Error": "Rejected" },
+                    { "Status": "SUCCESS", "MessageId": "3" },
+                ]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let emails = [email(), email(), email()];
+        let content = content();
+        let link = unsubscribe_link();
+        let results = email_client
+            .send_batch(&subject(), &recipients(&emails, &content, &link))
+            .await;
+
+        assert_ok!(&results[0]);
+        assert_err!(&results[1]);
+        assert_ok!(&results[2]);
+    }
+
+    #[tokio::test]
+    async fn bulk_sends_fail_every_recipient_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .retry_config(RetryConfig::standard().with_max_attempts(1))
+            .load()
+            .await;
+        let email_client = bulk_email_client(email(), conf);
+
+        Mock::given(path(TEMPLATES_END_POINT))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(path(SEND_BULK_EMAIL_END_POINT))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let emails = [email(), email()];
+        let content = content();
+        let link = unsubscribe_link();
+        let results = email_client
+            .send_batch(&subject(), &recipients(&emails, &content, &link))
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_err()));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .retry_config(RetryConfig::standard().with_max_attempts(1))
+            .load()
+            .await;
+        let email_client = email_client(email(), conf);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let outcome = email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content,
+                &content,
+                &unsubscribe_link(),
+            )
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let conf = aws_config::from_env()
+            .endpoint_url(mock_server.uri())
+            .timeout_config(
+                TimeoutConfig::builder()
+                    .operation_timeout(Duration::from_secs(2))
+                    .build(),
+            )
+            .load()
+            .await;
+        let email_client = email_client(email(), conf);
+
+        let reponse = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(reponse)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let content = content();
+        let outcome = email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content,
+                &content,
+                &unsubscribe_link(),
+            )
+            .await;
+
+        assert_err!(outcome);
+    }
+}
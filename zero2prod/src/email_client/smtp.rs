@@ -0,0 +1,181 @@
+use super::{EmailMessage, EmailSender};
+use anyhow::Context;
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+/// Sends emails through a plain SMTP relay.
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpSender {
+    pub fn new(
+        host: &str,
+        port: u16,
+        starttls: bool,
+        credentials: Option<(String, Secret<String>)>,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = if starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("Failed to set up a STARTTLS connection to the SMTP relay.")?
+        } else {
+            // no encryption at all, only meant for relays on the same host
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder =
+                builder.credentials(Credentials::new(username, password.expose_secret().clone()));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpSender {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), anyhow::Error> {
+        let from: Address = message
+            .sender
+            .as_ref()
+            .parse()
+            .context("The sender is not a valid SMTP address.")?;
+        let to: Address = message
+            .recipient
+            .as_ref()
+            .parse()
+            .context("The recipient is not a valid SMTP address.")?;
+        let envelope = Envelope::new(Some(from), vec![to]).context("Invalid SMTP envelope.")?;
+
+        self.transport
+            .send_raw(&envelope, &message.to_mime())
+            .await
+            .context("The SMTP relay rejected the email.")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailClient;
+    use claim::{assert_err, assert_ok};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// What the stand-in relay received for a single email.
+    #[derive(Default)]
+    struct ReceivedEmail {
+        mail_from: String,
+        rcpt_to: String,
+        data: String,
+    }
+
+    /// A minimal SMTP relay that accepts, or rejects, every email it is sent.
+    async fn smtp_stand_in(accept: bool) -> (u16, mpsc::UnboundedReceiver<ReceivedEmail>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let mut email = ReceivedEmail::default();
+
+                writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let command = line.to_uppercase();
+                    let reply: &[u8] = if command.starts_with("EHLO") {
+                        b"250 localhost\r\n"
+                    } else if command.starts_with("MAIL FROM:") {
+                        email.mail_from = line[10..].to_owned();
+                        b"250 OK\r\n"
+                    } else if command.starts_with("RCPT TO:") {
+                        email.rcpt_to = line[8..].to_owned();
+                        if accept {
+                            b"250 OK\r\n"
+                        } else {
+                            b"550 mailbox unavailable\r\n"
+                        }
+                    } else if command == "DATA" {
+                        writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line == "." {
+                                break;
+                            }
+                            email.data.push_str(&line);
+                            email.data.push_str("\r\n");
+                        }
+                        let _ = tx.send(std::mem::take(&mut email));
+                        b"250 OK\r\n"
+                    } else if command == "QUIT" {
+                        writer.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    } else {
+                        b"250 OK\r\n"
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            }
+        });
+
+        (port, rx)
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    fn email_client(port: u16) -> EmailClient {
+        let sender =
+            SmtpSender::new("127.0.0.1", port, false, None, Duration::from_secs(2)).unwrap();
+        EmailClient::new(email("newsletter@example.com"), sender)
+    }
+
+    async fn send(email_client: &EmailClient) -> Result<(), anyhow::Error> {
+        email_client
+            .send_email(
+                &email("ursula@example.com"),
+                "Welcome!",
+                "<p>Hello</p>",
+                "Hello",
+                "https://example.com/subscriptions/unsubscribe?token=abc",
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn send_email_hands_the_message_to_the_relay() {
+        let (port, mut received) = smtp_stand_in(true).await;
+
+        let outcome = send(&email_client(port)).await;
+
+        assert_ok!(outcome);
+        let email = received.recv().await.unwrap();
+        assert_eq!(email.mail_from, "<newsletter@example.com>");
+        assert_eq!(email.rcpt_to, "<ursula@example.com>");
+        assert!(email
+            .data
+            .contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_rejects_the_recipient() {
+        let (port, _received) = smtp_stand_in(false).await;
+
+        let outcome = send(&email_client(port)).await;
+
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::{backoff, ExecutionOutcome};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::field::display;
+use tracing::Span;
+use uuid::Uuid;
+
+// give up on an email after this many failed attempts
+const MAX_RETRIES: i16 = 5;
+// the longest a queued email waits for the worker to notice it
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An email to send once the transaction it is queued in commits.
+pub struct QueuedEmail<'a> {
+    // the default sender without one, e.g. for a list without its own address
+    pub sender: Option<&'a str>,
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    // none for emails that are not about a subscription
+    pub unsubscribe_link: Option<&'a str>,
+}
+
+struct OutboxEmail {
+    email_id: Uuid,
+    sender: Option<String>,
+    recipient: String,
+    subject: String,
+    html_content: String,
+    text_content: String,
+    unsubscribe_link: Option<String>,
+    n_retries: i16,
+}
+
+/// Queue an email, it goes out only if `transaction` commits.
+#[tracing::instrument(skip_all, fields(recipient = %email.recipient))]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &QueuedEmail<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"insert into email_outbox (
+               email_id,
+               sender,
+               recipient,
+               subject,
+               html_content,
+               text_content,
+               unsubscribe_link
+           )
+           values ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        email.sender,
+        email.recipient.as_ref(),
+        email.subject,
+        email.html_content,
+        email.text_content,
+        email.unsubscribe_link
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn run_outbox_worker_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_send_queued_email(&pool, &email_client).await {
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => tokio::time::sleep(POLL_INTERVAL).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Send the oldest due email of the outbox, if any.
+///
+/// The row stays locked until the email is out and the row is deleted, or
+/// rescheduled: another worker skips it, and a crash sends it again at most
+/// once.
+#[tracing::instrument(skip_all, fields(email_id = tracing::field::Empty), err)]
+pub async fn try_send_queued_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let email = sqlx::query_as!(
+        OutboxEmail,
+        r#"select
+               email_id,
+               sender,
+               recipient,
+               subject,
+               html_content,
+               text_content,
+               unsubscribe_link,
+               n_retries
+           from email_outbox
+           where execute_after <= now()
+           order by execute_after
+           for update
+           skip locked
+           limit 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let email = match email {
+        Some(email) => email,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("email_id", display(email.email_id));
+
+    match send(email_client, &email).await {
+        Ok(()) => delete_email(&mut transaction, email.email_id).await?,
+        Err(e) if email.n_retries + 1 >= MAX_RETRIES => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                recipient = %email.recipient,
+                "Failed to send a queued email. Giving up after {} attempts.",
+                MAX_RETRIES,
+            );
+            delete_email(&mut transaction, email.email_id).await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                recipient = %email.recipient,
+                "Failed to send a queued email. It will be retried.",
+            );
+            reschedule_email(&mut transaction, &email).await?;
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn send(email_client: &EmailClient, email: &OutboxEmail) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(email.recipient.clone())
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("The recipient of the email is invalid.")?;
+    let email_client = match &email.sender {
+        Some(sender) => {
+            let sender = SubscriberEmail::parse(sender.clone())
+                .map_err(|e| anyhow::anyhow!(e))
+                .context("The sender of the email is invalid.")?;
+            email_client.with_sender(sender)
+        }
+        None => email_client.clone(),
+    };
+
+    match &email.unsubscribe_link {
+        Some(unsubscribe_link) => {
+            email_client
+                .send_email(
+                    &recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    unsubscribe_link,
+                )
+                .await
+        }
+        None => {
+            email_client
+                .send_transactional_email(
+                    &recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                )
+                .await
+        }
+    }
+}
+
+async fn delete_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(r#"delete from email_outbox where email_id = $1"#, email_id)
+        .execute(&mut **transaction)
+        .await?;
+
+    Ok(())
+}
+
+async fn reschedule_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &OutboxEmail,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update email_outbox
+           set n_retries = n_retries + 1, execute_after = $2
+           where email_id = $1
+        "#,
+        email.email_id,
+        Utc::now() + backoff(email.n_retries)
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use crate::helpers::error_chain_fmt;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use tera::Tera;
+
+// wrap width of the generated text alternative
+const TEXT_WIDTH: usize = 78;
+
+// tera only auto-escapes templates whose name ends in `.html`
+const HTML_TEMPLATE: &str = "email.html";
+const TEXT_TEMPLATE: &str = "email.txt";
+
+#[derive(thiserror::Error)]
+pub enum TemplateError {
+    #[error("There is no template named `{0}`.")]
+    NotFound(String),
+
+    #[error("The template is invalid.")]
+    Invalid(#[source] tera::Error),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for TemplateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// The email templates, from the `templates` table or from the files in a
+/// directory. A row in the table overrides the file with the same name.
+///
+/// Every template is made of `<name>.html` and an optional `<name>.txt`,
+/// the text alternative is generated from the html when it is missing.
+#[derive(Clone)]
+pub struct EmailTemplates {
+    pool: PgPool,
+    directory: PathBuf,
+}
+
+/// The variables every email template can use.
+#[derive(serde::Serialize)]
+pub struct TemplateVariables<'a> {
+    pub subscriber_name: &'a str,
+    // the list the email is sent on behalf of
+    pub list_name: &'a str,
+    pub unsubscribe_link: &'a str,
+    pub confirmation_link: Option<&'a str>,
+    // a one-time link to export or erase the subscriber's data
+    pub data_request_link: Option<&'a str>,
+    pub issue_title: Option<&'a str>,
+    // the body of an issue, as written by an admin
+    pub html_content: Option<&'a str>,
+    pub text_content: Option<&'a str>,
+}
+
+impl<'a> TemplateVariables<'a> {
+    /// Made-up values, for previewing a template.
+    pub fn sample() -> Self {
+        Self {
+            subscriber_name: "Ursula Le Guin",
+            list_name: "Sample list",
+            unsubscribe_link: "https://example.com/subscriptions/unsubscribe",
+            confirmation_link: Some("https://example.com/subscriptions/confirm"),
+            data_request_link: Some("https://example.com/subscriptions/data"),
+            issue_title: Some("Sample issue"),
+            html_content: Some("<p>The body of a sample issue.</p>"),
+            text_content: Some("The body of a sample issue."),
+        }
+    }
+}
+
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+/// A parsed template, ready to be rendered for any number of subscribers.
+pub struct EmailTemplate {
+    tera: Tera,
+    has_text: bool,
+}
+
+impl EmailTemplates {
+    pub fn new(pool: PgPool, directory: impl Into<PathBuf>) -> Self {
+        Self {
+            pool,
+            directory: directory.into(),
+        }
+    }
+
+    #[tracing::instrument(name = "Load email template", skip(self))]
+    pub async fn get(&self, name: &str) -> Result<EmailTemplate, TemplateError> {
+        let row = sqlx::query!(
+            r#"select html_body, text_body from templates where name = $1"#,
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to look up the template in the database.")?;
+
+        let (html, text) = match row {
+            Some(row) => (row.html_body, row.text_body),
+            None => self.read_files(name).await?,
+        };
+
+        EmailTemplate::parse(&html, text.as_deref())
+    }
+
+    async fn read_files(&self, name: &str) -> Result<(String, Option<String>), TemplateError> {
+        // names end up in a path, keep them from walking out of the directory
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(TemplateError::NotFound(name.to_owned()));
+        }
+
+        let html =
+            match tokio::fs::read_to_string(self.directory.join(format!("{}.html", name))).await {
+                Ok(html) => html,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(TemplateError::NotFound(name.to_owned()))
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context("Failed to read the html template.")
+                        .into())
+                }
+            };
+        let text =
+            match tokio::fs::read_to_string(self.directory.join(format!("{}.txt", name))).await {
+                Ok(text) => Some(text),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context("Failed to read the text template.")
+                        .into())
+                }
+            };
+
+        Ok((html, text))
+    }
+}
+
+impl EmailTemplate {
+    pub fn parse(html: &str, text: Option<&str>) -> Result<Self, TemplateError> {
+        let mut tera = Tera::default();
+        tera.add_raw_template(HTML_TEMPLATE, html)
+            .map_err(TemplateError::Invalid)?;
+        if let Some(text) = text {
+            tera.add_raw_template(TEXT_TEMPLATE, text)
+                .map_err(TemplateError::Invalid)?;
+        }
+
+        Ok(Self {
+            tera,
+            has_text: text.is_some(),
+        })
+    }
+
+    pub fn render(&self, variables: &TemplateVariables) -> Result<RenderedEmail, TemplateError> {
+        let context = tera::Context::from_serialize(variables).map_err(TemplateError::Invalid)?;
+
+        let html = self
+            .tera
+            .render(HTML_TEMPLATE, &context)
+            .map_err(TemplateError::Invalid)?;
+        let text = if self.has_text {
+            self.tera
+                .render(TEXT_TEMPLATE, &context)
+                .map_err(TemplateError::Invalid)?
+        } else {
+            html2text::from_read(html.as_bytes(), TEXT_WIDTH)
+        };
+
+        Ok(RenderedEmail { html, text })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables<'a>(subscriber_name: &'a str) -> TemplateVariables<'a> {
+        TemplateVariables {
+            subscriber_name,
+            ..TemplateVariables::sample()
+        }
+    }
+
+    #[test]
+    fn variables_are_escaped_in_html() {
+        let template = EmailTemplate::parse("<p>Hi {{ subscriber_name }}</p>", None).unwrap();
+
+        let email = template.render(&variables("<script>")).unwrap();
+
+        assert_eq!(email.html, "<p>Hi &lt;script&gt;</p>");
+    }
+
+    #[test]
+    fn variables_are_not_escaped_in_text() {
+        let template = EmailTemplate::parse("<p>Hi</p>", Some("Hi {{ subscriber_name }}")).unwrap();
+
+        let email = template.render(&variables("Tom & Jerry")).unwrap();
+
+        assert_eq!(email.text, "Hi Tom & Jerry");
+    }
+
+    #[test]
+    fn a_text_alternative_is_generated_from_the_html() {
+        let template = EmailTemplate::parse(
+            r#"<h1>{{ issue_title }}</h1><p>Visit <a href="{{ unsubscribe_link }}">this link</a></p>"#,
+            None,
+        )
+        .unwrap();
+
+        let email = template.render(&TemplateVariables::sample()).unwrap();
+
+        assert!(email.text.contains("Sample issue"));
+        assert!(email
+            .text
+            .contains("https://example.com/subscriptions/unsubscribe"));
+        assert!(!email.text.contains("<p>"));
+    }
+
+    #[test]
+    fn an_invalid_template_is_rejected() {
+        assert!(EmailTemplate::parse("{{ subscriber_name", None).is_err());
+    }
+
+    #[test]
+    fn an_unknown_variable_fails_the_rendering() {
+        let template = EmailTemplate::parse("{{ favourite_colour }}", None).unwrap();
+
+        assert!(template.render(&TemplateVariables::sample()).is_err());
+    }
+}
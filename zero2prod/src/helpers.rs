@@ -1,3 +1,5 @@
+use actix_web::http::header::LOCATION;
+use actix_web::HttpResponse;
 use std::error::Error;
 use std::fmt::{Formatter, Result};
 
@@ -11,3 +13,25 @@ pub fn error_chain_fmt(e: &impl Error, f: &mut Formatter<'_>) -> Result {
 
     Ok(())
 }
+
+/// Return an opaque 500 while preserving the error root's cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+/// Return a 400 with the error's message as body.
+pub fn e400<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
@@ -0,0 +1,63 @@
+use ammonia::{Builder, Url};
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::{
+    BufferQueue, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
+use std::collections::HashSet;
+
+/// The tags and url schemes an html fragment uses, as the html5ever tokenizer
+/// sees them.
+///
+/// ammonia has no setting to keep whatever it is given, this lets it keep
+/// everything a given fragment uses.
+#[derive(Default)]
+pub struct HtmlUsage {
+    tags: HashSet<String>,
+    url_schemes: HashSet<String>,
+}
+
+impl HtmlUsage {
+    pub fn of(html: &str) -> Self {
+        let mut input = BufferQueue::new();
+        input.push_back(StrTendril::from(html));
+        let mut tokenizer = Tokenizer::new(Self::default(), TokenizerOpts::default());
+        let _ = tokenizer.feed(&mut input);
+        tokenizer.end();
+
+        let mut usage = tokenizer.sink;
+        // ammonia refuses to allow SVG animations, they are removed either way
+        usage.tags.remove("animate");
+        usage.tags.remove("set");
+        usage
+    }
+
+    /// Let `builder` keep every tag, attribute and url of the fragment, and
+    /// the content of `<script>` and `<style>`.
+    pub fn keep_all<'a, 'b>(&'a self, builder: &'b mut Builder<'a>) -> &'b mut Builder<'a> {
+        builder
+            .add_tags(self.tags.iter().map(String::as_str))
+            .generic_attribute_prefixes(HashSet::from([""]))
+            .add_url_schemes(self.url_schemes.iter().map(String::as_str))
+            .clean_content_tags(HashSet::new())
+    }
+}
+
+impl TokenSink for HtmlUsage {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        if let Token::TagToken(tag) = token {
+            if tag.kind == TagKind::StartTag {
+                self.tags.insert(tag.name.to_string());
+                // whatever the attribute, ammonia only checks the scheme of
+                // those that hold urls
+                for attribute in &tag.attrs {
+                    if let Ok(url) = Url::parse(&attribute.value) {
+                        self.url_schemes.insert(url.scheme().to_owned());
+                    }
+                }
+            }
+        }
+        TokenSinkResult::Continue
+    }
+}
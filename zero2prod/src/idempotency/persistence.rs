@@ -0,0 +1,163 @@
+use crate::idempotency::IdempotencyKey;
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    // this request owns the key and must produce the response, within the
+    // transaction that claimed it
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+    // another request with the same key has not finished yet
+    InFlight,
+}
+
+/// Claim an idempotency key for `user_id`.
+///
+/// The key is claimed in a transaction, along with a lock on the key that a
+/// concurrent request with the same key fails to take: it is told the key is
+/// still in flight. Nothing is left behind if the request fails before
+/// `save_response` commits, the client can retry with the same key.
+#[tracing::instrument(name = "Claim idempotency key.", skip(pool, idempotency_key))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // released when the transaction ends
+    let locked = sqlx::query_scalar!(
+        r#"select pg_try_advisory_xact_lock(hashtextextended($1 || ':' || $2, 0)) as "locked!""#,
+        user_id.to_string(),
+        idempotency_key.as_ref()
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    if !locked {
+        return Ok(NextAction::InFlight);
+    }
+
+    let n_inserted_rows = sqlx::query!(
+        r#"insert into idempotency (user_id, idempotency_key, created_at)
+           values ($1, $2, now())
+           on conflict do nothing
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        return Ok(NextAction::StartProcessing(transaction));
+    }
+
+    match get_saved_response(pool, idempotency_key, user_id).await? {
+        Some(saved_response) => Ok(NextAction::ReturnSavedResponse(saved_response)),
+        None => Ok(NextAction::InFlight),
+    }
+}
+
+pub async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<HttpResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"select
+               response_status_code,
+               response_headers as "response_headers: Vec<HeaderPairRecord>",
+               response_body
+           from idempotency
+           where user_id = $1 and idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    // a row without a status code belongs to a request that is still running
+    let (status_code, headers, body) = match saved_response {
+        Some(r) => match (r.response_status_code, r.response_headers, r.response_body) {
+            (Some(status_code), Some(headers), Some(body)) => (status_code, headers, body),
+            _ => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+
+    let status_code = StatusCode::from_u16(status_code.try_into()?)?;
+    let mut response = HttpResponse::build(status_code);
+    for HeaderPairRecord { name, value } in headers {
+        response.append_header((name, value));
+    }
+
+    Ok(Some(response.body(body)))
+}
+
+/// Store the response produced for a claimed key so retries can replay it,
+/// and commit the transaction that claimed it.
+#[tracing::instrument(
+    name = "Save idempotent response.",
+    skip(transaction, idempotency_key, http_response)
+)]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    // `MessageBody::Error` is not `Send` + `Sync`, so it does not play well with anyhow
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = {
+        let mut h = Vec::with_capacity(response_head.headers().len());
+        for (name, value) in response_head.headers().iter() {
+            let name = name.as_str().to_owned();
+            let value = value.as_bytes().to_owned();
+            h.push(HeaderPairRecord { name, value });
+        }
+        h
+    };
+
+    sqlx::query_unchecked!(
+        r#"update idempotency
+           set
+               response_status_code = $3,
+               response_headers = $4,
+               response_body = $5
+           where user_id = $1 and idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    // the body was consumed above, rebuild the response from its parts
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
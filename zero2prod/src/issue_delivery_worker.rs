@@ -0,0 +1,438 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, Recipient};
+use crate::email_templates::{EmailTemplates, RenderedEmail, TemplateVariables};
+use crate::lists::get_list;
+use crate::tracking::{Tracking, TrackingLinks};
+use crate::unsubscribe::UnsubscribeLinks;
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::field::display;
+use tracing::Span;
+use uuid::Uuid;
+
+// give up on an address after this many failed attempts
+const MAX_RETRIES: i16 = 5;
+// the first retry waits this long, every following one waits twice as long
+const BASE_BACKOFF_SECONDS: i64 = 30;
+// tasks claimed at once
+const BATCH_SIZE: i64 = 50;
+// a claimed task is retried after this long if its worker did not get to it,
+// e.g. because it crashed
+const CLAIM_SECONDS: f64 = 600.0;
+
+const NEWSLETTER_TEMPLATE: &str = "newsletter";
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+// a delivery rendered and waiting for the rest of its batch
+struct RenderedDelivery<'a> {
+    task: &'a Task,
+    delivery_id: Uuid,
+    email: SubscriberEmail,
+    unsubscribe_link: String,
+    content: RenderedEmail,
+}
+
+struct NewsletterIssue {
+    list_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    track_opens: bool,
+    track_clicks: bool,
+}
+
+/// Everything the worker needs to render and send deliveries.
+#[derive(Clone)]
+pub struct DeliveryContext {
+    pub pool: PgPool,
+    pub email_client: EmailClient,
+    pub templates: EmailTemplates,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub tracking_links: TrackingLinks,
+}
+
+pub async fn run_worker_until_stopped(context: DeliveryContext) -> Result<(), anyhow::Error> {
+    worker_loop(context).await
+}
+
+async fn worker_loop(context: DeliveryContext) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&context).await {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Claim a batch of delivery tasks and try to send their emails.
+///
+/// Claimed tasks are hidden from other workers for `CLAIM_SECONDS`, no
+/// transaction is held while the emails go out. The emails of an issue go out
+/// as one batch, so that the backend can send them in bulk, and every
+/// recipient is then marked as sent, or rescheduled, on its own: a crash
+/// before that sends again at most the batch that was in flight.
+///
+/// Every attempt is recorded in `deliveries`, along with its outcome.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    context: &DeliveryContext,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let tasks = claim_tasks(&context.pool).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", display(tasks.len()));
+
+    // the tasks of an issue share its content, they are not returned next to
+    // each other
+    let mut issues: BTreeMap<Uuid, Vec<&Task>> = BTreeMap::new();
+    for task in &tasks {
+        issues
+            .entry(task.newsletter_issue_id)
+            .or_default()
+            .push(task);
+    }
+    // an issue that cannot be sent, e.g. because its list is misconfigured,
+    // must not hold up the others: its tasks stay claimed until they are due
+    // again
+    for (issue_id, issue_tasks) in issues {
+        if let Err(e) = execute_issue_tasks(context, issue_id, &issue_tasks).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                newsletter_issue_id = %issue_id,
+                "Failed to deliver the claimed tasks of an issue. \
+                They will be retried once their claim expires.",
+            );
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = %issue_id))]
+async fn execute_issue_tasks(
+    context: &DeliveryContext,
+    issue_id: Uuid,
+    tasks: &[&Task],
+) -> Result<(), anyhow::Error> {
+    let pool = &context.pool;
+    let issue = get_issue(pool, issue_id).await?;
+    let list = get_list(pool, issue.list_id).await?;
+    let email_client = list.email_client(&context.email_client)?;
+    let template = context.templates.get(NEWSLETTER_TEMPLATE).await?;
+    let tracking = Tracking {
+        opens: issue.track_opens,
+        clicks: issue.track_clicks,
+    };
+
+    let mut deliveries = Vec::with_capacity(tasks.len());
+    for &task in tasks {
+        let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => email,
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+                delete_task(pool, task).await?;
+                continue;
+            }
+        };
+
+        // the subscriber may have unsubscribed since the issue was published
+        let (subscriber_id, subscriber_name) =
+            match get_confirmed_subscriber(pool, issue.list_id, &email).await? {
+                Some(subscriber) => subscriber,
+                None => {
+                    tracing::info!(
+                        subscriber_email = %task.subscriber_email,
+                        "Skipping a subscriber that is no longer confirmed."
+                    );
+                    delete_task(pool, task).await?;
+                    continue;
+                }
+            };
+
+        let delivery_id = start_delivery(pool, issue_id, subscriber_id).await?;
+        let unsubscribe_link = context.unsubscribe_links.link_for(subscriber_id);
+        let html_content = context
+            .tracking_links
+            .track(&issue.html_content, delivery_id, tracking);
+        match template.render(&TemplateVariables {
+            subscriber_name: &subscriber_name,
+            list_name: &list.name,
+            unsubscribe_link: &unsubscribe_link,
+            confirmation_link: None,
+            data_request_link: None,
+            issue_title: Some(&issue.title),
+            html_content: Some(&html_content),
+            text_content: Some(&issue.text_content),
+        }) {
+            Ok(content) => deliveries.push(RenderedDelivery {
+                task,
+                delivery_id,
+                email,
+                unsubscribe_link,
+                content,
+            }),
+            Err(e) => {
+                let e = anyhow::Error::new(e).context("Failed to render the issue.");
+                complete_task(pool, task, delivery_id, Err(e)).await?;
+            }
+        }
+    }
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+
+    let recipients: Vec<Recipient> = deliveries
+        .iter()
+        .map(|delivery| Recipient {
+            email: &delivery.email,
+            html_content: &delivery.content.html,
+            text_content: &delivery.content.text,
+            unsubscribe_link: &delivery.unsubscribe_link,
+        })
+        .collect();
+    let results = email_client.send_batch(&issue.title, &recipients).await;
+    for (delivery, result) in deliveries.iter().zip(results) {
+        complete_task(pool, delivery.task, delivery.delivery_id, result).await?;
+    }
+
+    Ok(())
+}
+
+/// Record the outcome of an attempt and take the task off the queue, or put
+/// it back for a retry, in one go.
+#[tracing::instrument(skip_all)]
+async fn complete_task(
+    pool: &PgPool,
+    task: &Task,
+    delivery_id: Uuid,
+    result: Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    match result {
+        Ok(_) => {
+            record_attempt(&mut transaction, delivery_id, "sent", None).await?;
+            delete_task(&mut *transaction, task).await?;
+        }
+        Err(e) if task.n_retries + 1 >= MAX_RETRIES => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Giving up after {} attempts.",
+                MAX_RETRIES,
+            );
+            record_attempt(&mut transaction, delivery_id, "failed", Some(&e)).await?;
+            delete_task(&mut *transaction, task).await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "Failed to deliver issue to a confirmed subscriber. \
+                The delivery will be retried.",
+            );
+            record_attempt(&mut transaction, delivery_id, "retrying", Some(&e)).await?;
+            reschedule_task(&mut transaction, task).await?;
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Take up to `BATCH_SIZE` due tasks, and push them back by `CLAIM_SECONDS`
+/// so that no other worker picks them up meanwhile.
+#[tracing::instrument(skip_all)]
+async fn claim_tasks(pool: &PgPool) -> Result<Vec<Task>, anyhow::Error> {
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"update issue_delivery_queue
+           set execute_after = now() + make_interval(secs => $2)
+           where (newsletter_issue_id, subscriber_email) in (
+               select newsletter_issue_id, subscriber_email
+               from issue_delivery_queue
+               where execute_after <= now()
+               order by newsletter_issue_id
+               for update
+               skip locked
+               limit $1
+           )
+           returning newsletter_issue_id, subscriber_email, n_retries
+        "#,
+        BATCH_SIZE,
+        CLAIM_SECONDS
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tasks)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(executor: impl PgExecutor<'_>, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"delete from issue_delivery_queue
+           where newsletter_issue_id = $1 and subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + backoff(task.n_retries);
+    sqlx::query!(
+        r#"update issue_delivery_queue
+           set n_retries = n_retries + 1, execute_after = $3
+           where newsletter_issue_id = $1 and subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// The delivery of an issue to a subscriber, created on the first attempt.
+///
+/// Its id is what the tracking links of the email refer to.
+#[tracing::instrument(skip_all)]
+async fn start_delivery(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Uuid, anyhow::Error> {
+    let delivery_id = sqlx::query!(
+        r#"insert into deliveries (
+               delivery_id,
+               newsletter_issue_id,
+               subscriber_id,
+               status,
+               attempted_at
+           )
+           values ($1, $2, $3, 'pending', now())
+           on conflict (newsletter_issue_id, subscriber_id)
+           do update set status = excluded.status, attempted_at = excluded.attempted_at
+           returning delivery_id
+        "#,
+        Uuid::new_v4(),
+        issue_id,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await?
+    .delivery_id;
+
+    Ok(delivery_id)
+}
+
+#[tracing::instrument(skip(transaction, error))]
+async fn record_attempt(
+    transaction: &mut PgTransaction,
+    delivery_id: Uuid,
+    status: &str,
+    error: Option<&anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"update deliveries
+           set status = $2, attempted_at = now(), error = $3
+           where delivery_id = $1
+        "#,
+        delivery_id,
+        status,
+        error.map(|e| format!("{:#}", e))
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// How long to wait before the next attempt, given the number of failed
+/// attempts before the current one.
+pub(crate) fn backoff(n_retries: i16) -> chrono::Duration {
+    chrono::Duration::seconds(BASE_BACKOFF_SECONDS * 2_i64.pow(n_retries.max(0) as u32))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    list_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<Option<(Uuid, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"select id, name from subscriptions
+           where list_id = $1 and email = $2 and status = 'confirmed'
+        "#,
+        list_id,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| (r.id, r.name)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"select list_id, title, text_content, html_content, track_opens, track_clicks
+           from newsletter_issues
+           where newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_after_every_failed_attempt() {
+        assert_eq!(backoff(0), chrono::Duration::seconds(30));
+        assert_eq!(backoff(1), chrono::Duration::seconds(60));
+        assert_eq!(backoff(4), chrono::Duration::seconds(480));
+    }
+}
@@ -0,0 +1,295 @@
+use crate::domain::{Segment, SubscriberEmail};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder, Row, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+// the longest the scheduler sleeps, an issue may have been scheduled or
+// rescheduled by another instance in the meantime
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+pub enum SchedulerOutcome {
+    IssuePublished,
+    NothingDue { next_send_at: Option<DateTime<Utc>> },
+}
+
+pub async fn run_scheduler_until_stopped(pool: PgPool) -> Result<(), anyhow::Error> {
+    scheduler_loop(pool).await
+}
+
+async fn scheduler_loop(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        match try_publish_due_issue(&pool).await {
+            Ok(SchedulerOutcome::IssuePublished) => {}
+            Ok(SchedulerOutcome::NothingDue { next_send_at }) => {
+                tokio::time::sleep(time_until(next_send_at, Utc::now())).await
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// How long to sleep before looking for due issues again.
+fn time_until(next_send_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Duration {
+    next_send_at
+        .map(|send_at| (send_at - now).to_std().unwrap_or(Duration::ZERO))
+        .unwrap_or(POLL_INTERVAL)
+        .min(POLL_INTERVAL)
+}
+
+/// Publish the scheduled issue that is the most overdue, if any.
+///
+/// The issue row stays locked until its deliveries are enqueued and its status
+/// is updated, in the same transaction: another instance skips it, and a crash
+/// leaves it scheduled for the next attempt.
+#[tracing::instrument(skip_all, err)]
+pub async fn try_publish_due_issue(pool: &PgPool) -> Result<SchedulerOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = sqlx::query!(
+        r#"select newsletter_issue_id
+           from newsletter_issues
+           where status = 'scheduled' and send_at <= now()
+           order by send_at
+           for update
+           skip locked
+           limit 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let issue = match issue {
+        Some(issue) => issue,
+        None => {
+            transaction.rollback().await?;
+            let next_send_at = sqlx::query!(
+                r#"select min(send_at) as next_send_at
+                   from newsletter_issues
+                   where status = 'scheduled'
+                "#
+            )
+            .fetch_one(pool)
+            .await?
+            .next_send_at;
+
+            return Ok(SchedulerOutcome::NothingDue { next_send_at });
+        }
+    };
+
+    publish_issue(&mut transaction, issue.newsletter_issue_id).await?;
+    transaction.commit().await?;
+
+    Ok(SchedulerOutcome::IssuePublished)
+}
+
+/// Queue one delivery task per confirmed subscriber of the list of the issue,
+/// in its segment, and mark the issue as published.
+#[tracing::instrument(skip(transaction))]
+pub(crate) async fn publish_issue(
+    transaction: &mut PgTransaction,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"select list_id, segment from newsletter_issues where newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("Failed to get the segment of the newsletter issue.")?;
+    let segment = issue
+        .segment
+        .map(Segment::parse)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("The segment of the newsletter issue is invalid.")?;
+
+    let subscriber_emails: Vec<String> =
+        get_confirmed_subscribers(transaction, issue.list_id, segment.as_ref())
+            .await
+            .context("Failed to get confirmed subscribers from database.")?
+            .into_iter()
+            .filter_map(|subscriber| match subscriber {
+                Ok(email) => Some(email.as_ref().to_owned()),
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        "Skipping a confirmed subscriber. \
+                        Their stored contact details are invalid",
+                    );
+                    None
+                }
+            })
+            .collect();
+
+    enqueue_delivery_tasks(transaction, newsletter_issue_id, &subscriber_emails)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+
+    sqlx::query!(
+        r#"update newsletter_issues
+           set status = 'published', published_at = now()
+           where newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark the newsletter issue as published.")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut PgTransaction,
+    newsletter_issue_id: Uuid,
+    subscriber_emails: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"insert into issue_delivery_queue (newsletter_issue_id, subscriber_email)
+           select $1, email from unnest($2::text[]) as email
+        "#,
+        newsletter_issue_id,
+        subscriber_emails
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscribers(
+    transaction: &mut PgTransaction,
+    list_id: Uuid,
+    segment: Option<&Segment>,
+) -> Result<Vec<Result<SubscriberEmail, anyhow::Error>>, anyhow::Error> {
+    let confirmed_subscribers = confirmed_subscribers_query(list_id, segment)
+        .build()
+        .fetch_all(&mut **transaction)
+        .await?
+        .into_iter()
+        .map(|r| {
+            let email: String = r.try_get("email")?;
+            SubscriberEmail::parse(email).map_err(|e| anyhow::anyhow!(e))
+        })
+        .collect();
+
+    Ok(confirmed_subscribers)
+}
+
+fn confirmed_subscribers_query(
+    list_id: Uuid,
+    segment: Option<&Segment>,
+) -> QueryBuilder<'static, Postgres> {
+    let mut query = QueryBuilder::new(
+        "select email from subscriptions where status = 'confirmed' and list_id = ",
+    );
+    query.push_bind(list_id);
+    if let Some(segment) = segment {
+        query.push(" and ");
+        push_segment(&mut query, segment);
+    }
+    query
+}
+
+/// Translate a segment into a condition on `subscriptions`, binding every tag.
+fn push_segment(query: &mut QueryBuilder<'static, Postgres>, segment: &Segment) {
+    match segment {
+        Segment::Tag(tag) => {
+            query.push(
+                "exists (select 1 from subscriber_tags \
+                 where subscriber_tags.subscriber_id = subscriptions.id \
+                 and subscriber_tags.tag = ",
+            );
+            query.push_bind(tag.as_ref().to_owned());
+            query.push(")");
+        }
+        Segment::Not(segment) => {
+            query.push("not (");
+            push_segment(query, segment);
+            query.push(")");
+        }
+        Segment::And(left, right) => {
+            query.push("(");
+            push_segment(query, left);
+            query.push(" and ");
+            push_segment(query, right);
+            query.push(")");
+        }
+        Segment::Or(left, right) => {
+            query.push("(");
+            push_segment(query, left);
+            query.push(" or ");
+            push_segment(query, right);
+            query.push(")");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HAS_TAG: &str = "exists (select 1 from subscriber_tags \
+                           where subscriber_tags.subscriber_id = subscriptions.id \
+                           and subscriber_tags.tag = ";
+
+    #[test]
+    fn without_a_segment_every_confirmed_subscriber_of_the_list_is_selected() {
+        let query = confirmed_subscribers_query(Uuid::new_v4(), None);
+
+        assert_eq!(
+            query.sql(),
+            "select email from subscriptions where status = 'confirmed' and list_id = $1"
+        );
+    }
+
+    #[test]
+    fn the_segment_is_translated_into_bound_conditions() {
+        let segment = Segment::parse("tag:rust AND NOT tag:beta".to_string()).unwrap();
+
+        let query = confirmed_subscribers_query(Uuid::new_v4(), Some(&segment));
+
+        assert_eq!(
+            query.sql(),
+            format!(
+                "select email from subscriptions where status = 'confirmed' \
+                 and list_id = $1 and ({}$2) and not ({}$3)))",
+                HAS_TAG, HAS_TAG
+            )
+        );
+    }
+
+    #[test]
+    fn the_scheduler_wakes_up_when_the_next_issue_is_due() {
+        let now = Utc::now();
+
+        let sleep = time_until(Some(now + chrono::Duration::seconds(3)), now);
+
+        assert_eq!(sleep, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn the_scheduler_does_not_sleep_past_the_poll_interval() {
+        let now = Utc::now();
+
+        assert_eq!(
+            time_until(Some(now + chrono::Duration::hours(1)), now),
+            POLL_INTERVAL
+        );
+        assert_eq!(time_until(None, now), POLL_INTERVAL);
+    }
+
+    #[test]
+    fn an_overdue_issue_is_published_right_away() {
+        let now = Utc::now();
+
+        let sleep = time_until(Some(now - chrono::Duration::seconds(3)), now);
+
+        assert_eq!(sleep, Duration::ZERO);
+    }
+}
@@ -0,0 +1,87 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a MAC is for, prefixed to what it signs.
+///
+/// The secret also signs other things, e.g. the session cookies: a MAC made
+/// for one of them must not be valid for another.
+#[derive(Clone, Copy, Debug)]
+pub enum MacPurpose {
+    Unsubscribe,
+    // the click links of a delivery
+    Click,
+    // when the subscription form was rendered
+    FormToken,
+    // the email kept in the tombstone of an erased subscriber
+    Erasure,
+}
+
+impl MacPurpose {
+    fn prefix(&self) -> &'static [u8] {
+        match self {
+            MacPurpose::Unsubscribe => b"unsubscribe:",
+            MacPurpose::Click => b"click:",
+            MacPurpose::FormToken => b"form:",
+            MacPurpose::Erasure => b"erasure:",
+        }
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `parts`, one after the other.
+pub fn sign(hmac_secret: &Secret<String>, purpose: MacPurpose, parts: &[&[u8]]) -> String {
+    hex::encode(mac(hmac_secret, purpose, parts).finalize().into_bytes())
+}
+
+/// Whether `signature` is what `sign` returns for the same arguments.
+pub fn verify(
+    hmac_secret: &Secret<String>,
+    purpose: MacPurpose,
+    parts: &[&[u8]],
+    signature: &str,
+) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    // constant time comparison
+    mac(hmac_secret, purpose, parts)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn mac(hmac_secret: &Secret<String>, purpose: MacPurpose, parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(purpose.prefix());
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-secret-used-only-in-tests".into())
+    }
+
+    #[test]
+    fn a_signature_is_only_valid_for_its_purpose() {
+        let parts: &[&[u8]] = &[b"00000000-0000-0000-0000-000000000001"];
+        let signature = sign(&secret(), MacPurpose::Unsubscribe, parts);
+
+        assert!(verify(
+            &secret(),
+            MacPurpose::Unsubscribe,
+            parts,
+            &signature
+        ));
+        assert!(!verify(&secret(), MacPurpose::Click, parts, &signature));
+    }
+}
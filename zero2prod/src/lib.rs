@@ -3,10 +3,30 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
+pub mod authentication;
+pub mod bot_check;
+pub mod clock;
 pub mod configuration;
+pub mod consent;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
+pub mod email_templates;
 pub mod helpers;
+pub mod html_usage;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod keyed_mac;
+pub mod lists;
+pub mod markdown;
+pub mod rate_limit;
+pub mod request_origin;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
+pub mod sns;
 pub mod startup;
 pub mod telemetry;
+pub mod tracking;
+pub mod unsubscribe;
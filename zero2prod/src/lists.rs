@@ -0,0 +1,70 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The list of forms and API calls that do not name one, the only list there
+/// was before there were several.
+pub const DEFAULT_LIST: &str = "default";
+
+pub struct List {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    // the sender of the email client when unset
+    pub sender_email: Option<String>,
+}
+
+impl List {
+    /// The email client, sending on behalf of the list.
+    pub fn email_client(&self, email_client: &EmailClient) -> Result<EmailClient, anyhow::Error> {
+        match &self.sender_email {
+            Some(sender_email) => {
+                let sender = SubscriberEmail::parse(sender_email.clone())
+                    .map_err(|e| anyhow::anyhow!(e))
+                    .context("The sender address of the list is invalid.")?;
+                Ok(email_client.with_sender(sender))
+            }
+            None => Ok(email_client.clone()),
+        }
+    }
+}
+
+/// The list named `slug`, or the default list without one.
+pub async fn get_list_by_slug(
+    pool: &PgPool,
+    slug: Option<&str>,
+) -> Result<Option<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"select list_id, slug, name, sender_email from lists where slug = $1"#,
+        slug.unwrap_or(DEFAULT_LIST)
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_list(pool: &PgPool, list_id: Uuid) -> Result<List, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"select list_id, slug, name, sender_email from lists where list_id = $1"#,
+        list_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Every list, the oldest first.
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        r#"select list_id, slug, name, sender_email from lists order by created_at, slug"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub fn unknown_list(slug: Option<&str>) -> String {
+    format!("There is no list named {}.", slug.unwrap_or(DEFAULT_LIST))
+}
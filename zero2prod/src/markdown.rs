@@ -0,0 +1,268 @@
+use crate::helpers::error_chain_fmt;
+use crate::html_usage::HtmlUsage;
+use ammonia::{Url, UrlRelative};
+use anyhow::Context;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use std::fmt::{Debug, Formatter};
+
+// wrap width of the plain text version
+const TEXT_WIDTH: usize = 78;
+
+// the html an editor may write inline, other tags are stripped
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "div",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "s",
+    "span",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+#[derive(thiserror::Error)]
+pub enum MarkdownError {
+    #[error("The markdown contains html that is not allowed.")]
+    UnsafeHtml,
+
+    #[error("The markdown links to an unsafe url: {0}")]
+    UnsafeUrl(String),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for MarkdownError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+/// Render an issue written in Markdown to sanitized html and plain text.
+///
+/// Relative links and images are resolved against `base_url`. Inline html is
+/// accepted as long as the sanitizer keeps all of it: we would rather tell the
+/// editor than silently drop part of their issue.
+pub fn render_markdown(markdown: &str, base_url: &str) -> Result<RenderedMarkdown, MarkdownError> {
+    let base_url = Url::parse(base_url).context("The application base url is invalid.")?;
+
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+
+    let events = Parser::new_ext(markdown, options)
+        .map(|event| {
+            if let Event::Start(Tag::Link(_, url, _)) | Event::Start(Tag::Image(_, url, _)) = &event
+            {
+                check_url(url)?;
+            }
+            Ok(event)
+        })
+        .collect::<Result<Vec<_>, MarkdownError>>()?;
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+
+    let html = sanitizer(&base_url).clean(&unsafe_html).to_string();
+    // the same policy, except it keeps every tag, attribute and url scheme
+    // and the content of `<script>`/`<style>`: if the two disagree, the
+    // sanitizer removed something the editor wrote
+    let usage = HtmlUsage::of(&unsafe_html);
+    let mut kept_everything = sanitizer(&base_url);
+    let kept_everything = usage
+        .keep_all(&mut kept_everything)
+        .clean(&unsafe_html)
+        .to_string();
+    if html != kept_everything {
+        return Err(MarkdownError::UnsafeHtml);
+    }
+    let text = html2text::from_read(html.as_bytes(), TEXT_WIDTH);
+
+    Ok(RenderedMarkdown { html, text })
+}
+
+fn sanitizer(base_url: &Url) -> ammonia::Builder<'static> {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .tags(ALLOWED_TAGS.iter().copied().collect())
+        .url_schemes(ALLOWED_SCHEMES.iter().copied().collect())
+        .url_relative(UrlRelative::RewriteWithBase(base_url.clone()));
+    builder
+}
+
+fn check_url(url: &str) -> Result<(), MarkdownError> {
+    // a relative url has no scheme, a `:` after the first `/`, `?` or `#` is
+    // part of the path
+    let scheme = url
+        .split(['/', '?', '#'])
+        .next()
+        .and_then(|start| start.split_once(':'))
+        .map(|(scheme, _)| scheme.trim().to_lowercase());
+
+    match scheme {
+        Some(scheme) if !ALLOWED_SCHEMES.contains(&scheme.as_str()) => {
+            Err(MarkdownError::UnsafeUrl(url.to_owned()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::assert_ok;
+
+    const BASE_URL: &str = "https://newsletter.example.com";
+
+    fn render(markdown: &str) -> Result<RenderedMarkdown, MarkdownError> {
+        render_markdown(markdown, BASE_URL)
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html_and_text() {
+        let rendered =
+            render("# Title\n\nSome *emphasis* and a [link](https://example.com).").unwrap();
+
+        assert!(rendered.html.contains("<h1>Title</h1>"));
+        assert!(rendered.html.contains("<em>emphasis</em>"));
+        assert!(rendered.text.contains("Title"));
+        assert!(rendered.text.contains("https://example.com"));
+        assert!(!rendered.text.contains("<em>"));
+    }
+
+    #[test]
+    fn relative_links_are_resolved_against_the_base_url() {
+        let rendered = render("[archive](/issues/42) ![logo](logo.png)").unwrap();
+
+        assert!(rendered
+            .html
+            .contains(r#"href="https://newsletter.example.com/issues/42""#));
+        assert!(rendered
+            .html
+            .contains(r#"src="https://newsletter.example.com/logo.png""#));
+    }
+
+    #[test]
+    fn safe_inline_html_is_kept() {
+        let rendered = render("Some <sup>superscript</sup> text").unwrap();
+
+        assert!(rendered.html.contains("<sup>superscript</sup>"));
+    }
+
+    #[test]
+    fn script_tags_are_rejected() {
+        let outcome = render("Hello\n\n<script>alert('hi')</script>");
+
+        assert!(matches!(outcome, Err(MarkdownError::UnsafeHtml)));
+    }
+
+    #[test]
+    fn event_handlers_are_rejected() {
+        let outcome = render(r#"<img src="cat.png" onerror="alert('hi')">"#);
+
+        assert!(matches!(outcome, Err(MarkdownError::UnsafeHtml)));
+    }
+
+    #[test]
+    fn inline_styles_are_rejected() {
+        let outcome = render(r#"<span style="position: fixed">covering everything</span>"#);
+
+        assert!(matches!(outcome, Err(MarkdownError::UnsafeHtml)));
+    }
+
+    #[test]
+    fn attribute_values_are_not_mistaken_for_attributes() {
+        let rendered = render(r#"<abbr title="online style guide">OSG</abbr>"#).unwrap();
+
+        assert!(rendered.html.contains("<abbr"));
+    }
+
+    #[test]
+    fn javascript_urls_are_rejected() {
+        let outcome = render("[click me](javascript:alert('hi'))");
+
+        assert!(matches!(outcome, Err(MarkdownError::UnsafeUrl(_))));
+    }
+
+    #[test]
+    fn javascript_urls_in_inline_html_are_rejected() {
+        for markdown in [
+            r#"<a href="javascript:alert('hi')">click me</a>"#,
+            "<a href=javascript:alert(1)>click me</a>",
+            "<a href=' JavaScript:alert(1)'>click me</a>",
+        ] {
+            let outcome = render(markdown);
+
+            assert!(
+                matches!(outcome, Err(MarkdownError::UnsafeHtml)),
+                "{} was accepted",
+                markdown
+            );
+        }
+    }
+
+    #[test]
+    fn tags_outside_the_allow_list_are_rejected() {
+        for markdown in [
+            r#"<iframe src="https://example.com"></iframe>Hi"#,
+            r#"<object data="movie.swf"></object>"#,
+            r#"<form action="https://example.com"><input name="password"></form>"#,
+            "<custom-element>Hi</custom-element>",
+        ] {
+            let outcome = render(markdown);
+
+            assert!(
+                matches!(outcome, Err(MarkdownError::UnsafeHtml)),
+                "{} was accepted",
+                markdown
+            );
+        }
+    }
+
+    #[test]
+    fn quotes_and_entities_in_text_are_accepted() {
+        let rendered = render(r#"She said "hi" &amp; left&nbsp;early, <br> then 1 < 2"#).unwrap();
+
+        assert!(rendered.text.contains("hi"));
+    }
+
+    #[test]
+    fn mailto_links_and_colons_in_paths_are_accepted() {
+        assert_ok!(render("[write to us](mailto:editor@example.com)").map(|_| ()));
+        assert_ok!(render("[time](/at/12:30)").map(|_| ()));
+    }
+}
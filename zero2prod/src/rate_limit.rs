@@ -0,0 +1,202 @@
+use crate::request_origin::ClientIpHeader;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::web::{Bytes, Data};
+use actix_web::{FromRequest, HttpResponse};
+use actix_web_lab::middleware::Next;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// past this many keys, full buckets are dropped: they are the same as no
+// bucket at all
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct RateLimitSettings {
+    // requests allowed in a burst
+    pub capacity: u32,
+    pub refill_per_minute: u32,
+}
+
+/// One token bucket per key, e.g. per client ip.
+pub struct TokenBuckets {
+    capacity: f64,
+    refill_per_second: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBuckets {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            capacity: settings.capacity as f64,
+            refill_per_second: settings.refill_per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the bucket of `key`, or tell how long until the
+    /// next one.
+    pub fn try_acquire(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.capacity);
+        }
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if self.refill_per_second <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.refill_per_second,
+        ))
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_second).min(self.capacity)
+    }
+}
+
+/// Limits subscription and data requests per client ip and per email
+/// address: every one of them may send an email.
+#[derive(Clone)]
+pub struct SubscribeRateLimiter {
+    per_ip: Arc<TokenBuckets>,
+    per_email: Arc<TokenBuckets>,
+}
+
+#[derive(serde::Deserialize)]
+struct EmailField {
+    email: Option<String>,
+}
+
+impl SubscribeRateLimiter {
+    pub fn new(per_ip: RateLimitSettings, per_email: RateLimitSettings) -> Self {
+        Self {
+            per_ip: Arc::new(TokenBuckets::new(per_ip)),
+            per_email: Arc::new(TokenBuckets::new(per_email)),
+        }
+    }
+}
+
+/// Answer with a 429 once the client ip or the email in the form is over
+/// its limit.
+///
+/// The form is read here to find the email, and handed back to the handler.
+pub async fn rate_limit_subscriptions(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<Data<SubscribeRateLimiter>>()
+        .expect("The subscription rate limiter is not registered")
+        .clone();
+    let client_ip = req
+        .app_data::<Data<ClientIpHeader>>()
+        .expect("The client ip header is not registered")
+        .client_ip(req.request());
+    let now = Instant::now();
+
+    if let Some(ip) = client_ip {
+        limiter
+            .per_ip
+            .try_acquire(&ip, now)
+            .map_err(|retry_after| too_many_requests("ip", &ip, retry_after))?;
+    }
+
+    let body = {
+        let (http_request, payload) = req.parts_mut();
+        Bytes::from_request(http_request, payload).await
+    }?;
+    // a form without a valid email is rejected by the handler
+    if let Ok(EmailField { email: Some(email) }) = serde_urlencoded::from_bytes(&body) {
+        let email = email.trim().to_lowercase();
+        limiter
+            .per_email
+            .try_acquire(&email, now)
+            .map_err(|retry_after| too_many_requests("email", &email, retry_after))?;
+    }
+    req.set_payload(Payload::from(body));
+
+    next.call(req).await
+}
+
+fn too_many_requests(kind: &str, key: &str, retry_after: Duration) -> actix_web::Error {
+    let retry_after = retry_after.as_secs_f64().ceil().min(u32::MAX as f64) as u64;
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.to_string()))
+        .finish();
+    let e = anyhow::anyhow!("Too many subscription requests for {} {}", kind, key);
+    InternalError::from_response(e, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buckets(capacity: u32, refill_per_minute: u32) -> TokenBuckets {
+        TokenBuckets::new(RateLimitSettings {
+            capacity,
+            refill_per_minute,
+        })
+    }
+
+    // to the closest second, the float arithmetic is not exact
+    fn retry_after(result: Result<(), Duration>) -> u64 {
+        result.unwrap_err().as_secs_f64().round() as u64
+    }
+
+    #[test]
+    fn a_burst_up_to_the_capacity_is_allowed() {
+        let buckets = buckets(3, 1);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(buckets.try_acquire("key", now).is_ok());
+        }
+        assert_eq!(retry_after(buckets.try_acquire("key", now)), 60);
+    }
+
+    #[test]
+    fn tokens_come_back_over_time() {
+        let buckets = buckets(1, 2);
+        let now = Instant::now();
+
+        assert!(buckets.try_acquire("key", now).is_ok());
+        assert_eq!(
+            retry_after(buckets.try_acquire("key", now + Duration::from_secs(10))),
+            20
+        );
+        assert!(buckets
+            .try_acquire("key", now + Duration::from_secs(31))
+            .is_ok());
+    }
+
+    #[test]
+    fn keys_have_their_own_bucket() {
+        let buckets = buckets(1, 1);
+        let now = Instant::now();
+
+        assert!(buckets.try_acquire("a", now).is_ok());
+        assert!(buckets.try_acquire("b", now).is_ok());
+        assert!(buckets.try_acquire("a", now).is_err());
+    }
+}
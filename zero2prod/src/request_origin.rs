@@ -0,0 +1,84 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::USER_AGENT;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+
+/// The header the proxy in front of us puts the client ip in, the peer
+/// address is used without it or when a request does not carry it.
+#[derive(Clone, Debug, Default)]
+pub struct ClientIpHeader(pub Option<String>);
+
+impl ClientIpHeader {
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<String> {
+        self.0
+            .as_ref()
+            .and_then(|name| request.headers().get(name.as_str()))
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            // a request that reached us without the proxy, e.g. a health
+            // check, would otherwise escape the per ip limits
+            .or_else(|| request.peer_addr().map(|addr| addr.ip().to_string()))
+    }
+}
+
+/// Who sent a request, as far as we can tell.
+#[derive(Debug, Default)]
+pub struct RequestOrigin {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestOrigin {
+    pub fn of(request: &HttpRequest) -> Self {
+        let client_ip_header = request
+            .app_data::<Data<ClientIpHeader>>()
+            .expect("The client ip header is not registered");
+
+        Self {
+            ip: client_ip_header.client_ip(request),
+            user_agent: request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+        }
+    }
+}
+
+impl FromRequest for RequestOrigin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(Self::of(req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn the_configured_header_is_preferred_to_the_peer_address() {
+        let header = ClientIpHeader(Some("X-Forwarded-For".into()));
+        let request = TestRequest::default()
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .peer_addr("127.0.0.1:8000".parse().unwrap())
+            .to_http_request();
+
+        assert_eq!(header.client_ip(&request).as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_peer_address_is_used_when_the_configured_header_is_missing() {
+        let header = ClientIpHeader(Some("X-Forwarded-For".into()));
+        let request = TestRequest::default()
+            .peer_addr("127.0.0.1:8000".parse().unwrap())
+            .to_http_request();
+
+        assert_eq!(header.client_ip(&request).as_deref(), Some("127.0.0.1"));
+    }
+}
@@ -1,12 +1,30 @@
+mod admin;
 mod health_check;
 mod home;
+mod invitations;
+mod login;
 mod newsletter;
+mod newsletter_scheduled;
+mod ses_webhook;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_resend;
+mod subscriptions_unsubscribe;
+mod tracking;
 
 // re-export sub modules
+pub use admin::*;
 pub use health_check::*;
 pub use home::*;
+pub use invitations::*;
+pub use login::*;
 pub use newsletter::*;
+pub use newsletter_scheduled::*;
+pub use ses_webhook::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_data::*;
+pub use subscriptions_resend::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
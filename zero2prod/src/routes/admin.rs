@@ -0,0 +1,21 @@
+mod api_tokens;
+mod dashboard;
+mod lists;
+mod logout;
+mod newsletter;
+mod password;
+mod subscribers;
+mod templates;
+mod two_factor;
+mod users;
+
+pub use api_tokens::*;
+pub use dashboard::*;
+pub use lists::*;
+pub use logout::*;
+pub use newsletter::*;
+pub use password::*;
+pub use subscribers::*;
+pub use templates::*;
+pub use two_factor::*;
+pub use users::*;
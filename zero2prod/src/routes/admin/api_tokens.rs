@@ -0,0 +1,145 @@
+use crate::authentication::{generate_api_token, hash_api_token, ApiScope, AuthenticatedUser};
+use crate::helpers::{e400, e500};
+use actix_web::web::{Data, Json, Path, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Serialize)]
+struct ApiTokenSummary {
+    api_token_id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Without `expires_at` the token is valid until it is revoked.
+#[derive(serde::Deserialize)]
+pub struct NewApiTokenData {
+    name: String,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// The tokens of the logged in user, the newest first. The tokens themselves
+/// are only shown when created.
+#[tracing::instrument(name = "List API tokens", skip(pool, user))]
+pub async fn list_api_tokens(
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let api_tokens = sqlx::query_as!(
+        ApiTokenSummary,
+        r#"select api_token_id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+           from api_tokens
+           where user_id = $1
+           order by created_at desc
+        "#,
+        user.user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the API tokens.")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "api_tokens": api_tokens })))
+}
+
+/// Create a token acting on behalf of the logged in user, with their role,
+/// for the requests in `scopes` only.
+#[tracing::instrument(name = "Create an API token", skip(body, pool, user))]
+pub async fn create_api_token(
+    body: Json<NewApiTokenData>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let NewApiTokenData {
+        name,
+        scopes,
+        expires_at,
+    } = body.0;
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(e400(format!(
+            "The name of a token must be between 1 and {} characters long.",
+            MAX_NAME_LENGTH
+        )));
+    }
+    if scopes.is_empty() {
+        return Err(e400("A token needs at least one scope."));
+    }
+    let mut scopes = scopes
+        .iter()
+        .map(|s| ApiScope::parse(s).map(|scope| scope.as_str().to_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(e400)?;
+    scopes.sort();
+    scopes.dedup();
+    if matches!(expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+        return Err(e400("A token cannot expire in the past."));
+    }
+
+    let token = generate_api_token();
+    let api_token_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"insert into api_tokens (api_token_id, user_id, name, token_hash, scopes, expires_at)
+           values ($1, $2, $3, $4, $5, $6)
+        "#,
+        api_token_id,
+        user.user_id,
+        name,
+        hash_api_token(&token),
+        &scopes,
+        expires_at
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the API token.")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "api_token_id": api_token_id,
+        "name": name,
+        "scopes": scopes,
+        "expires_at": expires_at,
+        // the only time it is shown
+        "token": token.expose_secret(),
+    })))
+}
+
+/// Revoke a token of the logged in user, for good.
+#[tracing::instrument(name = "Revoke an API token", skip(pool, user))]
+pub async fn revoke_api_token(
+    api_token_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // keep the original date if it had already been revoked
+    let result = sqlx::query!(
+        r#"update api_tokens
+           set revoked_at = coalesce(revoked_at, now())
+           where api_token_id = $1 and user_id = $2
+        "#,
+        *api_token_id,
+        user.user_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to revoke the API token.")
+    .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        return Err(actix_web::error::ErrorNotFound(
+            "You have no API token with this id.",
+        ));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,57 @@
+use crate::authentication::UserId;
+use crate::helpers::e500;
+use actix_web::http::header::ContentType;
+use actix_web::web::{Data, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub async fn admin_dashboard(
+    user_id: ReqData<UserId>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Admin dashboard</title>
+</head>
+<body>
+    <p>Welcome {}!</p>
+    <p>Available actions:</p>
+    <ol>
+        <li><a href="/admin/newsletters">Send a newsletter issue</a></li>
+        <li><a href="/admin/password">Change password</a></li>
+        <li>
+            Preview the
+            <a href="/admin/templates/confirmation/preview">confirmation</a> and
+            <a href="/admin/templates/newsletter/preview">newsletter</a> emails
+        </li>
+        <li>
+            <form name="logoutForm" action="/admin/logout" method="post">
+                <input type="submit" value="Logout">
+            </form>
+        </li>
+    </ol>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&username)
+        )))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(r#"select username from users where user_id = $1"#, user_id,)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")?;
+
+    Ok(row.username)
+}
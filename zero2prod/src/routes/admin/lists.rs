@@ -0,0 +1,164 @@
+use crate::authentication::AuthenticatedUser;
+use crate::domain::{ListSlug, SubscriberEmail};
+use crate::helpers::{e400, e500};
+use actix_web::web::{Data, Json, Path, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+struct ListSummary {
+    slug: String,
+    name: String,
+    // the sender of the email client when `None`
+    sender_email: Option<String>,
+    confirmed_subscribers: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewListData {
+    slug: String,
+    name: String,
+    sender_email: String,
+}
+
+/// Fields left out are not changed.
+#[derive(serde::Deserialize)]
+pub struct ListChangesData {
+    name: Option<String>,
+    sender_email: Option<String>,
+}
+
+#[tracing::instrument(name = "List lists", skip(pool))]
+pub async fn list_lists(pool: Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let lists = sqlx::query_as!(
+        ListSummary,
+        r#"select
+               l.slug,
+               l.name,
+               l.sender_email,
+               (select count(*) from subscriptions s
+                where s.list_id = l.list_id and s.status = 'confirmed') as "confirmed_subscribers!"
+           from lists l
+           order by l.created_at, l.slug
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the lists.")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "lists": lists })))
+}
+
+/// Create a list, with the address its emails are sent from.
+#[tracing::instrument(name = "Create a list", skip(body, pool, user), fields(slug = %body.slug))]
+pub async fn create_list(
+    body: Json<NewListData>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let NewListData {
+        slug,
+        name,
+        sender_email,
+    } = body.0;
+    let slug = ListSlug::parse(slug).map_err(e400)?;
+    let name = parse_name(name)?;
+    let sender_email = SubscriberEmail::parse(sender_email).map_err(e400)?;
+
+    let n_inserted_rows = sqlx::query!(
+        r#"insert into lists (list_id, slug, name, sender_email)
+           values ($1, $2, $3, $4)
+           on conflict (slug) do nothing
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        name,
+        sender_email.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the list.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_inserted_rows == 0 {
+        return Err(actix_web::error::ErrorConflict(
+            "There is already a list with this slug.",
+        ));
+    }
+
+    let list = fetch_list_summary(&pool, slug.as_ref()).await?;
+    Ok(HttpResponse::Created().json(list))
+}
+
+#[tracing::instrument(name = "Update a list", skip(body, pool, user))]
+pub async fn update_list(
+    slug: Path<String>,
+    body: Json<ListChangesData>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let ListChangesData { name, sender_email } = body.0;
+    let name = name.map(parse_name).transpose()?;
+    let sender_email = sender_email
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(e400)?;
+
+    let result = sqlx::query!(
+        r#"update lists
+           set name = coalesce($2, name), sender_email = coalesce($3, sender_email)
+           where slug = $1
+        "#,
+        slug.as_str(),
+        name,
+        sender_email.as_ref().map(|email| email.as_ref())
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the list.")
+    .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        return Err(list_not_found());
+    }
+
+    let list = fetch_list_summary(&pool, &slug).await?;
+    Ok(HttpResponse::Ok().json(list))
+}
+
+fn parse_name(name: String) -> Result<String, actix_web::Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(e400("The name of a list cannot be empty."));
+    }
+    Ok(name.to_owned())
+}
+
+async fn fetch_list_summary(pool: &PgPool, slug: &str) -> Result<ListSummary, actix_web::Error> {
+    sqlx::query_as!(
+        ListSummary,
+        r#"select
+               l.slug,
+               l.name,
+               l.sender_email,
+               (select count(*) from subscriptions s
+                where s.list_id = l.list_id and s.status = 'confirmed') as "confirmed_subscribers!"
+           from lists l
+           where l.slug = $1
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the list.")
+    .map_err(e500)?
+    .ok_or_else(list_not_found)
+}
+
+fn list_not_found() -> actix_web::Error {
+    actix_web::error::ErrorNotFound("There is no list with this slug.")
+}
@@ -0,0 +1,12 @@
+use crate::helpers::see_other;
+use crate::session_state::TypedSession;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+
+pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+    // the route is behind `reject_anonymous_users`, there is always a user to log out
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+
+    Ok(see_other("/login"))
+}
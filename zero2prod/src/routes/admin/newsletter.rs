@@ -0,0 +1,7 @@
+mod get;
+mod post;
+mod stats;
+
+pub use get::publish_newsletter_form;
+pub use post::publish_newsletter_issue;
+pub use stats::newsletter_stats;
@@ -0,0 +1,99 @@
+use crate::authentication::AuthenticatedUser;
+use crate::helpers::e500;
+use crate::lists::get_lists;
+use actix_web::http::header::ContentType;
+use actix_web::web::{Data, ReqData};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+pub async fn publish_newsletter_form(
+    flash_messages: IncomingFlashMessages,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    let mut list_options = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        // only the lists the user may publish to
+        if !user
+            .may_publish_to(&pool, list.list_id)
+            .await
+            .map_err(e500)?
+        {
+            continue;
+        }
+        writeln!(
+            list_options,
+            r#"<option value="{}">{}</option>"#,
+            htmlescape::encode_attribute(&list.slug),
+            htmlescape::encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+
+    // a fresh key per rendered form, so resubmitting the same form is a no-op
+    let idempotency_key = uuid::Uuid::new_v4();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Publish Newsletter Issue</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/newsletters" method="post">
+        <label>List:<br>
+            <select name="list">
+                {list_options}
+            </select>
+        </label>
+        <br>
+        <label>Title:<br>
+            <input
+                type="text"
+                placeholder="Enter the issue title"
+                name="title"
+            >
+        </label>
+        <br>
+        <label>Plain text content:<br>
+            <textarea
+                placeholder="Enter the content in plain text"
+                name="text_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea
+                placeholder="Enter the content in HTML format"
+                name="html_content"
+                rows="20"
+                cols="50"
+            ></textarea>
+        </label>
+        <br>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Publish</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,93 @@
+use crate::authentication::AuthenticatedUser;
+use crate::helpers::{e400, e500, see_other};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::lists::{get_list_by_slug, unknown_list};
+use crate::routes::{schedule_newsletter_delivery, NewIssue};
+use crate::tracking::Tracking;
+use actix_web::web::{Data, Form, ReqData};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    // the default list without one
+    list: Option<String>,
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the admin form.",
+    skip(form, pool, user),
+    fields(user_id = %user.user_id)
+)]
+pub async fn publish_newsletter_issue(
+    form: Form<FormData>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user = user.into_inner();
+    let FormData {
+        list,
+        title,
+        text_content,
+        html_content,
+        idempotency_key,
+    } = form.0;
+    let idempotency_key = IdempotencyKey::parse(idempotency_key).map_err(e400)?;
+    let list = get_list_by_slug(&pool, list.as_deref())
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e400(unknown_list(list.as_deref())))?;
+    if !user
+        .may_publish_to(&pool, list.list_id)
+        .await
+        .map_err(e500)?
+    {
+        return Err(actix_web::error::ErrorForbidden(
+            "You are not allowed to publish to this list.",
+        ));
+    }
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, user.user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message().send();
+            return Ok(saved_response);
+        }
+        NextAction::InFlight => {
+            FlashMessage::warning("The newsletter issue is still being published.").send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+    };
+
+    let issue = NewIssue {
+        title: &title,
+        text_content: &text_content,
+        html_content: &html_content,
+        segment: None,
+        send_at: None,
+        tracking: Tracking::default(),
+    };
+    schedule_newsletter_delivery(&mut transaction, list.list_id, &issue)
+        .await
+        .map_err(e500)?;
+
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, user.user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message().send();
+
+    Ok(response)
+}
+
+fn success_message() -> FlashMessage {
+    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
+}
@@ -0,0 +1,157 @@
+use crate::helpers::e500;
+use actix_web::web::{Data, Path};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// the most clicked links listed in the stats
+const MAX_LINKS: i64 = 20;
+
+#[derive(serde::Serialize)]
+struct IssueStats {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    published_at: Option<DateTime<Utc>>,
+    deliveries: DeliveryStats,
+    // `None` unless the issue tracks them
+    opens: Option<OpenStats>,
+    clicks: Option<ClickStats>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryStats {
+    // not attempted yet
+    queued: i64,
+    sent: i64,
+    retrying: i64,
+    failed: i64,
+}
+
+#[derive(serde::Serialize)]
+struct OpenStats {
+    // deliveries opened at least once
+    unique: i64,
+    total: i64,
+}
+
+#[derive(serde::Serialize)]
+struct ClickStats {
+    // deliveries with at least one click
+    unique: i64,
+    total: i64,
+    links: Vec<LinkStats>,
+}
+
+#[derive(serde::Serialize)]
+struct LinkStats {
+    url: String,
+    clicks: i64,
+}
+
+/// How the deliveries of an issue went, and how readers engaged with it.
+#[tracing::instrument(name = "Get newsletter issue stats", skip(pool))]
+pub async fn newsletter_stats(
+    newsletter_issue_id: Path<Uuid>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = sqlx::query!(
+        r#"select title, status, published_at, track_opens, track_clicks
+           from newsletter_issues
+           where newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter issue.")
+    .map_err(e500)?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("There is no newsletter issue with this id."))?;
+
+    let counts = sqlx::query!(
+        r#"select
+               (select count(*) from issue_delivery_queue q
+                where q.newsletter_issue_id = $1 and q.n_retries = 0) as "queued!",
+               count(*) filter (where d.status = 'sent') as "sent!",
+               count(*) filter (where d.status in ('pending', 'retrying')) as "retrying!",
+               count(*) filter (where d.status = 'failed') as "failed!",
+               count(d.opened_at) as "unique_opens!",
+               coalesce(sum(d.n_opens), 0) as "total_opens!"
+           from deliveries d
+           where d.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to count the deliveries of the newsletter issue.")
+    .map_err(e500)?;
+
+    let clicks = if issue.track_clicks {
+        Some(
+            click_stats(&pool, newsletter_issue_id)
+                .await
+                .context("Failed to count the clicks of the newsletter issue.")
+                .map_err(e500)?,
+        )
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(IssueStats {
+        newsletter_issue_id,
+        title: issue.title,
+        status: issue.status,
+        published_at: issue.published_at,
+        deliveries: DeliveryStats {
+            queued: counts.queued,
+            sent: counts.sent,
+            retrying: counts.retrying,
+            failed: counts.failed,
+        },
+        opens: issue.track_opens.then_some(OpenStats {
+            unique: counts.unique_opens,
+            total: counts.total_opens,
+        }),
+        clicks,
+    }))
+}
+
+async fn click_stats(pool: &PgPool, newsletter_issue_id: Uuid) -> Result<ClickStats, sqlx::Error> {
+    let totals = sqlx::query!(
+        r#"select
+               count(distinct c.delivery_id) as "unique!",
+               count(*) as "total!"
+           from delivery_clicks c
+           join deliveries d using (delivery_id)
+           where d.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let links = sqlx::query_as!(
+        LinkStats,
+        r#"select c.url, count(*) as "clicks!"
+           from delivery_clicks c
+           join deliveries d using (delivery_id)
+           where d.newsletter_issue_id = $1
+           group by c.url
+           order by count(*) desc, c.url
+           limit $2
+        "#,
+        newsletter_issue_id,
+        MAX_LINKS
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ClickStats {
+        unique: totals.unique,
+        total: totals.total,
+        links,
+    })
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::change_password_form;
+pub use post::{change_password, check_password_length};
@@ -0,0 +1,90 @@
+use crate::authentication::{validate_credentials, AuthError, Credentials, UserId};
+use crate::helpers::{e500, see_other};
+use crate::routes::get_username;
+use actix_web::web::{Data, Form, ReqData};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Change password.",
+    skip(form, pool, user_id),
+    fields(user_id = %*user_id)
+)]
+pub async fn change_password(
+    form: Form<FormData>,
+    pool: Data<PgPool>,
+    user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    if let Err(e) = check_password_length(&form.new_password) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    if form.new_password.expose_secret() == form.current_password.expose_secret() {
+        FlashMessage::error("The new password must be different from the current one.").send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+
+    if let Err(e) = validate_credentials(credentials, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("Your password has been changed.").send();
+
+    Ok(see_other("/admin/password"))
+}
+
+pub fn check_password_length(password: &Secret<String>) -> Result<(), String> {
+    let length = password.expose_secret().chars().count();
+
+    if length < MIN_PASSWORD_LENGTH {
+        Err(format!(
+            "The new password must be at least {} characters long.",
+            MIN_PASSWORD_LENGTH
+        ))
+    } else if length > MAX_PASSWORD_LENGTH {
+        Err(format!(
+            "The new password must be at most {} characters long.",
+            MAX_PASSWORD_LENGTH
+        ))
+    } else {
+        Ok(())
+    }
+}
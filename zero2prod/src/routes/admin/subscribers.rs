@@ -0,0 +1,15 @@
+mod consent;
+mod delete;
+mod export;
+mod get;
+mod import;
+mod status;
+mod tags;
+
+pub use consent::consent_history;
+pub use delete::delete_subscriber;
+pub use export::export_subscribers;
+pub use get::{get_subscriber, list_subscribers};
+pub use import::{import_subscribers, MAX_IMPORT_SIZE};
+pub use status::{confirm_subscriber, unsubscribe_subscriber};
+pub use tags::set_subscriber_tags;
@@ -0,0 +1,82 @@
+use crate::helpers::e500;
+use crate::lists::DEFAULT_LIST;
+use actix_web::web::{Data, Query};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ConsentParameters {
+    email: String,
+    // the default list without one
+    list: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ConsentHistory {
+    subscriber_id: Uuid,
+    email: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    // oldest first
+    events: Vec<ConsentEventRecord>,
+}
+
+#[derive(serde::Serialize)]
+struct ConsentEventRecord {
+    kind: String,
+    occurred_at: DateTime<Utc>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    source: Option<String>,
+    consent_text_version: Option<String>,
+}
+
+/// How and when the subscriber with an email consented to a list, e.g. to
+/// answer an auditor.
+#[tracing::instrument(name = "Get consent history", skip(parameters, pool))]
+pub async fn consent_history(
+    parameters: Query<ConsentParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = sqlx::query!(
+        r#"select s.id, s.email, s.status, s.subscribed_at
+           from subscriptions s
+           join lists l using (list_id)
+           where lower(s.email) = lower($1) and l.slug = $2
+        "#,
+        parameters.email.trim(),
+        parameters.list.as_deref().unwrap_or(DEFAULT_LIST)
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the subscriber.")
+    .map_err(e500)?
+    .ok_or_else(|| {
+        actix_web::error::ErrorNotFound("The list has no subscriber with this email.")
+    })?;
+
+    let events = sqlx::query_as!(
+        ConsentEventRecord,
+        r#"select kind, occurred_at, ip, user_agent, source, consent_text_version
+           from consent_events
+           where subscriber_id = $1
+           order by occurred_at, consent_event_id
+        "#,
+        subscriber.id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the consent events of the subscriber.")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(ConsentHistory {
+        subscriber_id: subscriber.id,
+        email: subscriber.email,
+        status: subscriber.status,
+        subscribed_at: subscriber.subscribed_at,
+        events,
+    }))
+}
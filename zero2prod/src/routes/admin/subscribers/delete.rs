@@ -0,0 +1,60 @@
+use super::get::subscriber_not_found;
+use crate::authentication::AuthenticatedUser;
+use crate::helpers::e500;
+use actix_web::web::{Data, Path, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Delete a subscriber for good, along with their tokens, tags and pending
+/// deliveries.
+#[tracing::instrument(name = "Delete a subscriber", skip(pool, user))]
+pub async fn delete_subscriber(
+    subscriber_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+
+    // tokens and tags go with the row, see the `on delete cascade`s
+    let deleted = sqlx::query!(
+        r#"delete from subscriptions where id = $1 returning email, list_id"#,
+        *subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete the subscriber.")
+    .map_err(e500)?
+    .ok_or_else(subscriber_not_found)?;
+
+    // the delivery queue refers to subscribers by email, the same email may
+    // be on other lists
+    sqlx::query!(
+        r#"delete from issue_delivery_queue q
+           using newsletter_issues i
+           where q.subscriber_email = $1
+             and q.newsletter_issue_id = i.newsletter_issue_id
+             and i.list_id = $2
+        "#,
+        deleted.email,
+        deleted.list_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the pending deliveries of the subscriber.")
+    .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,195 @@
+use super::get::StatusFilter;
+use crate::helpers::{e400, e500};
+use crate::lists::{get_list_by_slug, unknown_list};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::{Bytes, Data, Query};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// rows fetched from the database per chunk of the response
+const BATCH_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize)]
+pub struct ExportParameters {
+    status: Option<StatusFilter>,
+    // the default list without one
+    list: Option<String>,
+}
+
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    unsubscribed_at: Option<DateTime<Utc>>,
+    tags: Vec<String>,
+}
+
+enum ExportState {
+    Header,
+    // the last row sent, `None` before the first batch
+    Rows(Option<(DateTime<Utc>, Uuid)>),
+    Done,
+}
+
+/// Stream every subscriber of a list as CSV, oldest first, in the columns the
+/// import expects plus their status and dates.
+///
+/// The rows are fetched in batches, a large list is never held in memory.
+#[tracing::instrument(name = "Export subscribers", skip(parameters, pool))]
+pub async fn export_subscribers(
+    parameters: Query<ExportParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let status = parameters.status.map(|status| status.as_str());
+    let list_id = get_list_by_slug(&pool, parameters.list.as_deref())
+        .await
+        .context("Failed to look up the list.")
+        .map_err(e500)?
+        .ok_or_else(|| e400(unknown_list(parameters.list.as_deref())))?
+        .list_id;
+
+    let body = stream::unfold(ExportState::Header, move |state| {
+        let pool = pool.clone();
+        async move {
+            match state {
+                ExportState::Header => Some((
+                    write_csv([[
+                        "email",
+                        "name",
+                        "status",
+                        "subscribed_at",
+                        "unsubscribed_at",
+                        "tags",
+                    ]]),
+                    ExportState::Rows(None),
+                )),
+                ExportState::Rows(after) => {
+                    let batch = match fetch_batch(&pool, list_id, status, after).await {
+                        Ok(batch) => batch,
+                        // the status line is long gone, all we can do is
+                        // cut the file short
+                        Err(e) => {
+                            tracing::error!(
+                                error.cause_chain = ?e,
+                                "Failed to export subscribers."
+                            );
+                            return Some((Err(e), ExportState::Done));
+                        }
+                    };
+                    let last = batch.last().map(|s| (s.subscribed_at, s.id))?;
+                    let next = if (batch.len() as i64) < BATCH_SIZE {
+                        ExportState::Done
+                    } else {
+                        ExportState::Rows(Some(last))
+                    };
+                    Some((write_csv(batch.into_iter().map(to_record)), next))
+                }
+                ExportState::Done => None,
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .streaming(body))
+}
+
+async fn fetch_batch(
+    pool: &PgPool,
+    list_id: Uuid,
+    status: Option<&str>,
+    after: Option<(DateTime<Utc>, Uuid)>,
+) -> Result<Vec<ExportedSubscriber>, anyhow::Error> {
+    sqlx::query_as!(
+        ExportedSubscriber,
+        r#"select
+               s.id,
+               s.email,
+               s.name,
+               s.status,
+               s.subscribed_at,
+               s.unsubscribed_at,
+               array(
+                   select t.tag from subscriber_tags t
+                   where t.subscriber_id = s.id
+                   order by t.tag
+               ) as "tags!"
+           from subscriptions s
+           where s.list_id = $1
+             and ($2::text is null or s.status = $2)
+             and ($3::timestamptz is null or (s.subscribed_at, s.id) > ($3, $4::uuid))
+           order by s.subscribed_at, s.id
+           limit $5
+        "#,
+        list_id,
+        status,
+        after.map(|(subscribed_at, _)| subscribed_at),
+        after.map(|(_, id)| id),
+        BATCH_SIZE
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch a batch of subscribers to export.")
+}
+
+fn to_record(subscriber: ExportedSubscriber) -> [String; 6] {
+    [
+        subscriber.email,
+        subscriber.name,
+        subscriber.status,
+        subscriber.subscribed_at.to_rfc3339(),
+        subscriber
+            .unsubscribed_at
+            .map(|at| at.to_rfc3339())
+            .unwrap_or_default(),
+        subscriber.tags.join(","),
+    ]
+}
+
+fn write_csv<R, F>(records: impl IntoIterator<Item = R>) -> Result<Bytes, anyhow::Error>
+where
+    R: IntoIterator<Item = F>,
+    F: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer
+            .write_record(record)
+            .context("Failed to write a CSV record.")?;
+    }
+    let buffer = writer
+        .into_inner()
+        .context("Failed to flush the CSV writer.")?;
+
+    Ok(Bytes::from(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        let csv = write_csv([[
+            "ursula@example.com".to_string(),
+            "Le Guin, Ursula".to_string(),
+            "say \"hi\"".to_string(),
+        ]])
+        .unwrap();
+
+        assert_eq!(
+            csv.as_ref(),
+            b"ursula@example.com,\"Le Guin, Ursula\",\"say \"\"hi\"\"\"\n"
+        );
+    }
+}
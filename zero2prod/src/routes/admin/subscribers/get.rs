@@ -0,0 +1,242 @@
+use crate::helpers::{e400, e500};
+use actix_web::web::{Data, Path, Query};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(serde::Serialize)]
+pub(super) struct Subscriber {
+    id: Uuid,
+    // the slug of the list
+    list: String,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    unsubscribed_at: Option<DateTime<Utc>>,
+    tags: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusFilter {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+}
+
+impl StatusFilter {
+    pub(super) fn as_str(&self) -> &'static str {
+        match self {
+            StatusFilter::PendingConfirmation => "pending_confirmation",
+            StatusFilter::Confirmed => "confirmed",
+            StatusFilter::Unsubscribed => "unsubscribed",
+            StatusFilter::Bounced => "bounced",
+            StatusFilter::Complained => "complained",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListParameters {
+    // the slug of a list, every list without one
+    list: Option<String>,
+    status: Option<StatusFilter>,
+    email_prefix: Option<String>,
+    // `next` of the previous page
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberPage {
+    subscribers: Vec<Subscriber>,
+    // `None` on the last page
+    next: Option<String>,
+}
+
+/// Where a page ends, the newest subscribers come first.
+///
+/// `subscribed_at` alone is not unique, the id breaks the ties.
+#[derive(Debug, PartialEq, Eq)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        base64::encode_config(
+            format!("{}|{}", self.subscribed_at.to_rfc3339(), self.id),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    fn decode(s: &str) -> Result<Self, anyhow::Error> {
+        let decoded = base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+            .context("The cursor is not valid base64.")?;
+        let decoded = String::from_utf8(decoded).context("The cursor is not valid utf8.")?;
+        let (subscribed_at, id) = decoded
+            .split_once('|')
+            .context("The cursor is malformed.")?;
+
+        Ok(Self {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .context("The cursor has an invalid timestamp.")?
+                .with_timezone(&Utc),
+            id: id.parse().context("The cursor has an invalid id.")?,
+        })
+    }
+}
+
+#[tracing::instrument(name = "List subscribers", skip(parameters, pool))]
+pub async fn list_subscribers(
+    parameters: Query<ListParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let after = parameters
+        .after
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(e400)?;
+    let limit = parameters
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // one more row than asked for tells whether there is a next page
+    let mut subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"select
+               s.id,
+               l.slug as list,
+               s.email,
+               s.name,
+               s.status,
+               s.subscribed_at,
+               s.unsubscribed_at,
+               array(
+                   select t.tag from subscriber_tags t
+                   where t.subscriber_id = s.id
+                   order by t.tag
+               ) as "tags!"
+           from subscriptions s
+           join lists l using (list_id)
+           where ($1::text is null or s.status = $1)
+             and ($2::text is null or starts_with(lower(s.email), lower($2)))
+             and ($3::timestamptz is null or (s.subscribed_at, s.id) < ($3, $4::uuid))
+             and ($6::text is null or l.slug = $6)
+           order by s.subscribed_at desc, s.id desc
+           limit $5
+        "#,
+        parameters.status.map(|status| status.as_str()),
+        parameters.email_prefix,
+        after.as_ref().map(|cursor| cursor.subscribed_at),
+        after.as_ref().map(|cursor| cursor.id),
+        limit + 1,
+        parameters.list
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to list subscribers.")
+    .map_err(e500)?;
+
+    let next = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(SubscriberPage { subscribers, next }))
+}
+
+#[tracing::instrument(name = "Get a subscriber", skip(pool))]
+pub async fn get_subscriber(
+    subscriber_id: Path<Uuid>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match fetch_subscriber(&pool, *subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber.")
+        .map_err(e500)?
+    {
+        Some(subscriber) => Ok(HttpResponse::Ok().json(subscriber)),
+        None => Err(subscriber_not_found()),
+    }
+}
+
+pub(super) async fn fetch_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"select
+               s.id,
+               l.slug as list,
+               s.email,
+               s.name,
+               s.status,
+               s.subscribed_at,
+               s.unsubscribed_at,
+               array(
+                   select t.tag from subscriber_tags t
+                   where t.subscriber_id = s.id
+                   order by t.tag
+               ) as "tags!"
+           from subscriptions s
+           join lists l using (list_id)
+           where s.id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub(super) fn subscriber_not_found() -> actix_web::Error {
+    actix_web::error::ErrorNotFound("There is no subscriber with this id.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_cursor_survives_a_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc::now(),
+            id: Uuid::new_v4(),
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        let no_separator = base64::encode_config("garbage", base64::URL_SAFE_NO_PAD);
+        let bad_id = base64::encode_config(
+            format!("{}|not-a-uuid", Utc::now().to_rfc3339()),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        for cursor in ["not base64!", &no_separator, &bad_id] {
+            assert!(Cursor::decode(cursor).is_err(), "{} was accepted", cursor);
+        }
+    }
+}
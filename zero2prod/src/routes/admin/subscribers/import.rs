@@ -0,0 +1,414 @@
+use crate::authentication::AuthenticatedUser;
+use crate::consent::{record_consent_event, ConsentEvent, ConsentEventKind};
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTag};
+use crate::email_outbox::{enqueue_email, QueuedEmail};
+use crate::email_templates::EmailTemplates;
+use crate::helpers::{e400, e500};
+use crate::lists::{get_list_by_slug, unknown_list};
+use crate::request_origin::RequestOrigin;
+use crate::routes::{
+    add_tags, confirmation_subject, generate_subscription_token, render_confirmation_email,
+    store_token, CONFIRMATION_TEMPLATE,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::unsubscribe::UnsubscribeLinks;
+use actix_web::web::{Bytes, Data, Query, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// The largest CSV file accepted by the import.
+pub const MAX_IMPORT_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    PendingConfirmation,
+    Confirmed,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportParameters {
+    #[serde(default)]
+    dry_run: bool,
+    // imported subscribers have to confirm unless told otherwise
+    status: Option<ImportStatus>,
+    // the default list without one
+    list: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+    // comma separated, like the subscription form
+    #[serde(default)]
+    tags: Option<String>,
+}
+
+struct ValidRow {
+    line: u64,
+    subscriber: NewSubscriber,
+    tags: Vec<SubscriberTag>,
+}
+
+#[derive(serde::Serialize)]
+struct LineReport {
+    line: u64,
+    message: String,
+}
+
+#[derive(serde::Serialize, Default)]
+struct ImportReport {
+    dry_run: bool,
+    // what would have been imported, on a dry run
+    imported: usize,
+    // already subscribed, left untouched
+    skipped: Vec<LineReport>,
+    // nothing is imported unless this is empty
+    errors: Vec<LineReport>,
+}
+
+/// Import subscribers into a list from a CSV file with an `email`, a `name`
+/// and an optional `tags` column.
+///
+/// Either every row is valid and imported, or nothing is and the report lists
+/// the rows to fix. Lines are numbered as in the file, the header is line 1.
+/// Confirmation emails are queued along with the subscribers.
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(body, parameters, pool, templates, app_base_url, unsubscribe_links, user),
+    fields(dry_run = parameters.dry_run)
+)]
+pub async fn import_subscribers(
+    parameters: Query<ImportParameters>,
+    body: Bytes,
+    pool: Data<PgPool>,
+    templates: Data<EmailTemplates>,
+    app_base_url: Data<ApplicationBaseUrl>,
+    unsubscribe_links: Data<UnsubscribeLinks>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let status = parameters
+        .status
+        .unwrap_or(ImportStatus::PendingConfirmation);
+    let mut report = ImportReport {
+        dry_run: parameters.dry_run,
+        ..ImportReport::default()
+    };
+
+    let list = get_list_by_slug(&pool, parameters.list.as_deref())
+        .await
+        .context("Failed to look up the list.")
+        .map_err(e500)?
+        .ok_or_else(|| e400(unknown_list(parameters.list.as_deref())))?;
+
+    let rows = parse_csv(&body, &mut report.errors).map_err(e400)?;
+    let existing = get_existing_emails(&pool, list.list_id, &rows)
+        .await
+        .context("Failed to look up existing subscribers.")
+        .map_err(e500)?;
+    let (rows, skipped): (Vec<ValidRow>, Vec<ValidRow>) = rows
+        .into_iter()
+        .partition(|row| !existing.contains(&row.subscriber.email.as_ref().to_lowercase()));
+    report.skipped = skipped
+        .into_iter()
+        .map(|row| LineReport {
+            line: row.line,
+            message: format!("{} is already subscribed.", row.subscriber.email),
+        })
+        .collect();
+
+    if !report.errors.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(report));
+    }
+    if parameters.dry_run {
+        report.imported = rows.len();
+        return Ok(HttpResponse::Ok().json(report));
+    }
+
+    let template = match status {
+        ImportStatus::PendingConfirmation => Some(
+            templates
+                .get(CONFIRMATION_TEMPLATE)
+                .await
+                .context("Failed to load the confirmation email template.")
+                .map_err(e500)?,
+        ),
+        ImportStatus::Confirmed => None,
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    for row in &rows {
+        // someone may have subscribed since we looked
+        let subscriber_id =
+            match insert_imported_subscriber(&mut transaction, list.list_id, row, status)
+                .await
+                .context("Failed to insert an imported subscriber.")
+                .map_err(e500)?
+            {
+                Some(subscriber_id) => subscriber_id,
+                None => {
+                    report.skipped.push(LineReport {
+                        line: row.line,
+                        message: format!("{} is already subscribed.", row.subscriber.email),
+                    });
+                    continue;
+                }
+            };
+        report.imported += 1;
+
+        add_tags(&mut transaction, subscriber_id, &row.tags)
+            .await
+            .context("Failed to store the tags of an imported subscriber.")
+            .map_err(e500)?;
+        record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEvent {
+                kind: ConsentEventKind::Import,
+                origin: &RequestOrigin::default(),
+                source: Some("admin"),
+                consent_text_version: None,
+            },
+        )
+        .await
+        .context("Failed to record the import of a subscriber.")
+        .map_err(e500)?;
+        if let Some(template) = &template {
+            let subscription_token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .context("Failed to store the confirmation token of an imported subscriber.")
+                .map_err(e500)?;
+            let unsubscribe_link = unsubscribe_links.link_for(subscriber_id);
+            let email = render_confirmation_email(
+                template,
+                &list,
+                &row.subscriber,
+                &app_base_url.0,
+                &subscription_token,
+                &unsubscribe_link,
+            )
+            .map_err(e500)?;
+            enqueue_email(
+                &mut transaction,
+                &QueuedEmail {
+                    sender: list.sender_email.as_deref(),
+                    recipient: &row.subscriber.email,
+                    subject: &confirmation_subject(&list),
+                    html_content: &email.html,
+                    text_content: &email.text,
+                    unsubscribe_link: Some(&unsubscribe_link),
+                },
+            )
+            .await
+            .context("Failed to queue the confirmation email of an imported subscriber.")
+            .map_err(e500)?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// The valid rows of the file, invalid ones end up in `errors`.
+///
+/// Fails if the file itself cannot be read as CSV.
+fn parse_csv(body: &[u8], errors: &mut Vec<LineReport>) -> Result<Vec<ValidRow>, anyhow::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body);
+    let headers = reader
+        .headers()
+        .context("Failed to read the CSV header.")?
+        .clone();
+    for column in ["email", "name"] {
+        if !headers.iter().any(|header| header == column) {
+            anyhow::bail!("The CSV file has no `{}` column.", column);
+        }
+    }
+
+    let mut rows = Vec::new();
+    // first line of each email, to point at duplicates
+    let mut seen: HashMap<String, u64> = HashMap::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {}
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                errors.push(LineReport {
+                    line,
+                    message: e.to_string(),
+                });
+                // a malformed record does not stop us from reading the next one
+                if e.is_io_error() {
+                    break;
+                }
+                continue;
+            }
+        }
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+
+        match parse_row(&record, &headers) {
+            Ok(row) => match seen.get(row.subscriber.email.as_ref()) {
+                Some(first_line) => errors.push(LineReport {
+                    line,
+                    message: format!(
+                        "{} already appears on line {}.",
+                        row.subscriber.email, first_line
+                    ),
+                }),
+                None => {
+                    seen.insert(row.subscriber.email.as_ref().to_owned(), line);
+                    rows.push(ValidRow { line, ..row });
+                }
+            },
+            Err(message) => errors.push(LineReport { line, message }),
+        }
+    }
+
+    Ok(rows)
+}
+
+fn parse_row(record: &csv::StringRecord, headers: &csv::StringRecord) -> Result<ValidRow, String> {
+    let row: CsvRow = record
+        .deserialize(Some(headers))
+        .map_err(|e| e.to_string())?;
+
+    Ok(ValidRow {
+        line: 0,
+        subscriber: NewSubscriber {
+            email: SubscriberEmail::parse(row.email)?,
+            name: SubscriberName::parse(row.name)?,
+        },
+        tags: SubscriberTag::parse_list(row.tags.as_deref().unwrap_or_default())?,
+    })
+}
+
+/// The lower-cased emails of `rows` the list already has, in any case.
+async fn get_existing_emails(
+    pool: &PgPool,
+    list_id: Uuid,
+    rows: &[ValidRow],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let emails: Vec<String> = rows
+        .iter()
+        .map(|row| row.subscriber.email.as_ref().to_lowercase())
+        .collect();
+    let existing = sqlx::query!(
+        r#"select lower(email) as "email!" from subscriptions
+           where list_id = $1 and lower(email) = any($2)
+        "#,
+        list_id,
+        &emails
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.email)
+    .collect();
+
+    Ok(existing)
+}
+
+/// Returns `None` if the list already has a subscriber with the same email.
+async fn insert_imported_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    row: &ValidRow,
+    status: ImportStatus,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let status = match status {
+        ImportStatus::PendingConfirmation => "pending_confirmation",
+        ImportStatus::Confirmed => "confirmed",
+    };
+    let row = sqlx::query!(
+        r#"insert into subscriptions (id, list_id, email, name, subscribed_at, status)
+           values ($1, $2, $3, $4, $5, $6)
+           on conflict (list_id, lower(email)) do nothing
+           returning id
+        "#,
+        Uuid::new_v4(),
+        list_id,
+        row.subscriber.email.as_ref(),
+        row.subscriber.name.as_ref(),
+        Utc::now(),
+        status
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(row.map(|r| r.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(csv: &str) -> (Vec<ValidRow>, Vec<LineReport>) {
+        let mut errors = Vec::new();
+        let rows = parse_csv(csv.as_bytes(), &mut errors).unwrap();
+        (rows, errors)
+    }
+
+    #[test]
+    fn valid_rows_are_parsed_with_their_line_and_tags() {
+        let (rows, errors) = parse(
+            "email,name,tags\n\
+             ursula@example.com,Ursula Le Guin,\"scifi, fantasy\"\n\
+             octavia@example.com, Octavia Butler ,\n",
+        );
+
+        assert!(errors.is_empty());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].tags.len(), 2);
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(rows[1].subscriber.name.as_ref(), "Octavia Butler");
+    }
+
+    #[test]
+    fn the_tags_column_is_optional() {
+        let (rows, errors) = parse("name,email\nUrsula,ursula@example.com\n");
+
+        assert!(errors.is_empty());
+        assert!(rows[0].tags.is_empty());
+    }
+
+    #[test]
+    fn every_invalid_row_is_reported() {
+        let (rows, errors) = parse(
+            "email,name\n\
+             not-an-email,Ursula\n\
+             octavia@example.com,\n\
+             ursula@example.com,Ursula\n\
+             ursula@example.com,Ursula again\n",
+        );
+
+        assert_eq!(rows.len(), 1);
+        let lines: Vec<u64> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+        assert!(errors[2].message.contains("line 4"));
+    }
+
+    #[test]
+    fn a_file_without_the_required_columns_is_rejected() {
+        let mut errors = Vec::new();
+
+        assert!(parse_csv(b"email\nursula@example.com\n", &mut errors).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use super::get::{fetch_subscriber, subscriber_not_found};
+use crate::authentication::AuthenticatedUser;
+use crate::consent::{record_consent_event, ConsentEvent, ConsentEventKind};
+use crate::helpers::e500;
+use crate::request_origin::RequestOrigin;
+use actix_web::web::{Data, Path, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Confirm a subscriber on their behalf, e.g. when the confirmation email
+/// never reached them or after a bounce was fixed.
+#[tracing::instrument(name = "Confirm a subscriber manually", skip(pool, user))]
+pub async fn confirm_subscriber(
+    subscriber_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let result = sqlx::query!(
+        r#"update subscriptions
+           set status = 'confirmed', unsubscribed_at = null, suppressed_at = null
+           where id = $1
+        "#,
+        *subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to confirm the subscriber.")
+    .map_err(e500)?;
+    if result.rows_affected() > 0 {
+        record_admin_change(
+            &mut transaction,
+            *subscriber_id,
+            ConsentEventKind::Confirmation,
+        )
+        .await
+        .context("Failed to record the confirmation of the subscriber.")
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")
+        .map_err(e500)?;
+
+    updated_subscriber(&pool, *subscriber_id).await
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber manually", skip(pool, user))]
+pub async fn unsubscribe_subscriber(
+    subscriber_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    // keep the original date if they had already unsubscribed, and leave
+    // suppressed addresses alone: unsubscribed ones may subscribe again
+    let result = sqlx::query!(
+        r#"update subscriptions
+           set status = 'unsubscribed', unsubscribed_at = coalesce(unsubscribed_at, now())
+           where id = $1 and status not in ('bounced', 'complained')
+        "#,
+        *subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to unsubscribe the subscriber.")
+    .map_err(e500)?;
+    if result.rows_affected() > 0 {
+        record_admin_change(
+            &mut transaction,
+            *subscriber_id,
+            ConsentEventKind::Unsubscription,
+        )
+        .await
+        .context("Failed to record the unsubscription of the subscriber.")
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")
+        .map_err(e500)?;
+
+    updated_subscriber(&pool, *subscriber_id).await
+}
+
+async fn updated_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber = fetch_subscriber(pool, subscriber_id)
+        .await
+        .context("Failed to fetch the updated subscriber.")
+        .map_err(e500)?
+        .ok_or_else(subscriber_not_found)?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Consent changed by an admin, the admin's own ip and browser are not the
+/// subscriber's.
+async fn record_admin_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kind: ConsentEventKind,
+) -> Result<(), sqlx::Error> {
+    record_consent_event(
+        transaction,
+        subscriber_id,
+        ConsentEvent {
+            kind,
+            origin: &RequestOrigin::default(),
+            source: Some("admin"),
+            consent_text_version: None,
+        },
+    )
+    .await
+}
@@ -0,0 +1,73 @@
+use super::get::subscriber_not_found;
+use crate::authentication::AuthenticatedUser;
+use crate::domain::SubscriberTag;
+use crate::helpers::{e400, e500};
+use crate::routes::add_tags;
+use actix_web::web::{Data, Json, Path, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct TagsData {
+    tags: Vec<String>,
+}
+
+/// Replace the tags of a subscriber.
+#[tracing::instrument(name = "Set the tags of a subscriber", skip(body, pool, user))]
+pub async fn set_subscriber_tags(
+    subscriber_id: Path<Uuid>,
+    body: Json<TagsData>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let subscriber_id = subscriber_id.into_inner();
+    let tags = body
+        .0
+        .tags
+        .into_iter()
+        .map(SubscriberTag::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(e400)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+
+    let subscriber = sqlx::query!(
+        r#"select id from subscriptions where id = $1 for update"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber.")
+    .map_err(e500)?;
+    if subscriber.is_none() {
+        return Err(subscriber_not_found());
+    }
+
+    sqlx::query!(
+        r#"delete from subscriber_tags where subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the tags of the subscriber.")
+    .map_err(e500)?;
+    add_tags(&mut transaction, subscriber_id, &tags)
+        .await
+        .context("Failed to store the tags of the subscriber.")
+        .map_err(e500)?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store the tags of a subscriber.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,45 @@
+use crate::email_templates::{EmailTemplates, TemplateError, TemplateVariables};
+use crate::helpers::{e400, e500};
+use actix_web::http::header::ContentType;
+use actix_web::web::{Data, Path, Query};
+use actix_web::HttpResponse;
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    format: Option<PreviewFormat>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    Html,
+    Text,
+}
+
+/// Render an email template against a sample subscriber.
+#[tracing::instrument(name = "Preview email template", skip(parameters, templates))]
+pub async fn preview_template(
+    name: Path<String>,
+    parameters: Query<PreviewParameters>,
+    templates: Data<EmailTemplates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = templates
+        .get(&name)
+        .await
+        .and_then(|template| template.render(&TemplateVariables::sample()))
+        .map_err(|e| match e {
+            TemplateError::NotFound(_) => actix_web::error::ErrorNotFound(e),
+            // the admin needs the details to fix the template
+            TemplateError::Invalid(_) => e400(format!("{:?}", e)),
+            TemplateError::UnexpectedError(_) => e500(e),
+        })?;
+
+    Ok(match parameters.format.unwrap_or(PreviewFormat::Html) {
+        PreviewFormat::Html => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(email.html),
+        PreviewFormat::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(email.text),
+    })
+}
@@ -0,0 +1,263 @@
+use crate::authentication::{
+    generate_recovery_codes, store_recovery_codes, verify_second_factor, AuthError,
+    AuthenticatedUser, TotpSecret,
+};
+use crate::clock::Clock;
+use crate::helpers::{e400, e500};
+use actix_web::web::{Data, Json, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct CodeData {
+    // from the authenticator app, or a recovery code
+    code: Secret<String>,
+}
+
+#[tracing::instrument(name = "Get two-factor status", skip(pool, user))]
+pub async fn two_factor_status(
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let status = sqlx::query!(
+        r#"select
+               u.totp_enabled_at,
+               (
+                   select count(*) from recovery_codes r
+                   where r.user_id = u.user_id and r.used_at is null
+               ) as "recovery_codes_left!"
+           from users u
+           where u.user_id = $1
+        "#,
+        user.user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to look up the second factor of the user.")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "enabled": status.totp_enabled_at.is_some(),
+        "enabled_at": status.totp_enabled_at,
+        "recovery_codes_left": status.recovery_codes_left,
+    })))
+}
+
+/// Give the logged in user a new TOTP secret, to add to their authenticator
+/// app. It is only required once confirmed, see `confirm_two_factor`.
+#[tracing::instrument(name = "Start two-factor enrollment", skip(pool, user))]
+pub async fn enroll_two_factor(
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let secret = TotpSecret::generate();
+    let secret_base32 = secret.to_base32();
+    // replaces the secret of an unfinished enrollment
+    let username = sqlx::query_scalar!(
+        r#"update users
+           set totp_secret = $2, totp_last_step = null, totp_failed_attempts = 0
+           where user_id = $1 and totp_enabled_at is null
+           returning username
+        "#,
+        user.user_id,
+        secret_base32.expose_secret()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to store the TOTP secret.")
+    .map_err(e500)?
+    .ok_or_else(|| {
+        actix_web::error::ErrorConflict("Two-factor authentication is already enabled.")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "secret": secret_base32.expose_secret(),
+        "provisioning_uri": secret.provisioning_uri(&username),
+    })))
+}
+
+/// Require the second factor from now on, once the user shows a code from
+/// their app. Returns the recovery codes, the only time they are shown.
+///
+/// API tokens skip the second factor, the ones created before it was enabled
+/// are revoked: they were handed out on the strength of the password alone.
+#[tracing::instrument(name = "Confirm two-factor enrollment", skip(body, pool, clock, user))]
+pub async fn confirm_two_factor(
+    body: Json<CodeData>,
+    pool: Data<PgPool>,
+    clock: Data<Clock>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let row = sqlx::query!(
+        "select totp_secret, totp_enabled_at from users where user_id = $1",
+        user.user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .context("Failed to look up the second factor of the user.")
+    .map_err(e500)?;
+    if row.totp_enabled_at.is_some() {
+        return Err(actix_web::error::ErrorConflict(
+            "Two-factor authentication is already enabled.",
+        ));
+    }
+    let secret = row
+        .totp_secret
+        .ok_or_else(|| e400("Start the enrollment before confirming it."))?;
+    let secret = TotpSecret::parse(&secret).map_err(e500)?;
+    let now = clock.now();
+    let step = secret
+        .verify(body.code.expose_secret(), now, None)
+        .ok_or_else(|| e400("The code does not match, check the clock of your device."))?;
+
+    let recovery_codes = generate_recovery_codes();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"update users
+           set totp_enabled_at = $2, totp_last_step = $3, totp_failed_attempts = 0
+           where user_id = $1
+        "#,
+        user.user_id,
+        now,
+        step
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enable the second factor.")
+    .map_err(e500)?;
+    sqlx::query!(
+        r#"update api_tokens
+           set revoked_at = $2
+           where user_id = $1 and revoked_at is null
+        "#,
+        user.user_id,
+        now
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to revoke the API tokens created without a second factor.")
+    .map_err(e500)?;
+    store_recovery_codes(&mut transaction, user.user_id, &recovery_codes)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable a second factor.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "recovery_codes": recovery_codes
+            .iter()
+            .map(|code| code.expose_secret())
+            .collect::<Vec<_>>(),
+    })))
+}
+
+/// Replace the recovery codes of the logged in user, e.g. once they used
+/// most of them.
+#[tracing::instrument(name = "Regenerate recovery codes", skip(body, pool, clock, user))]
+pub async fn regenerate_recovery_codes(
+    body: Json<CodeData>,
+    pool: Data<PgPool>,
+    clock: Data<Clock>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    check_code(&body.code, &pool, &clock, user.user_id).await?;
+
+    let recovery_codes = generate_recovery_codes();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    store_recovery_codes(&mut transaction, user.user_id, &recovery_codes)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to replace recovery codes.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "recovery_codes": recovery_codes
+            .iter()
+            .map(|code| code.expose_secret())
+            .collect::<Vec<_>>(),
+    })))
+}
+
+/// Stop requiring a second factor from the logged in user, which takes a
+/// current code.
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(body, pool, clock, user)
+)]
+pub async fn disable_two_factor(
+    body: Json<CodeData>,
+    pool: Data<PgPool>,
+    clock: Data<Clock>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    check_code(&body.code, &pool, &clock, user.user_id).await?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"update users
+           set totp_secret = null,
+               totp_enabled_at = null,
+               totp_last_step = null,
+               totp_failed_attempts = 0,
+               totp_last_failure_at = null
+           where user_id = $1
+        "#,
+        user.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to disable the second factor.")
+    .map_err(e500)?;
+    sqlx::query!(
+        "delete from recovery_codes where user_id = $1",
+        user.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the recovery codes.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable a second factor.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Changes to an enabled second factor take a code, so that a stolen session
+// is not enough to turn it off.
+async fn check_code(
+    code: &Secret<String>,
+    pool: &PgPool,
+    clock: &Clock,
+    user_id: Uuid,
+) -> Result<(), actix_web::Error> {
+    verify_second_factor(user_id, code, clock.now(), pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => e400(e),
+            AuthError::UnexpectedError(_) => e500(e),
+        })
+}
@@ -0,0 +1,271 @@
+use crate::authentication::{AuthenticatedUser, Role};
+use crate::domain::SubscriberEmail;
+use crate::email_outbox::{enqueue_email, QueuedEmail};
+use crate::helpers::{e400, e500};
+use crate::lists::unknown_list;
+use crate::routes::{generate_subscription_token, INVITATION_TTL_DAYS};
+use crate::startup::ApplicationBaseUrl;
+use actix_web::web::{Data, Json, Path, ReqData};
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+struct UserSummary {
+    user_id: Uuid,
+    username: String,
+    role: String,
+    deactivated_at: Option<DateTime<Utc>>,
+    // the slugs of the lists an editor may publish to
+    lists: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct InvitationData {
+    email: String,
+    role: String,
+    #[serde(default)]
+    lists: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PermissionsData {
+    lists: Vec<String>,
+}
+
+#[tracing::instrument(name = "List users", skip(pool, user))]
+pub async fn list_users(
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let users = sqlx::query_as!(
+        UserSummary,
+        r#"select
+               u.user_id,
+               u.username,
+               u.role,
+               u.deactivated_at,
+               array(
+                   select l.slug from list_permissions p join lists l using (list_id)
+                   where p.user_id = u.user_id
+                   order by l.slug
+               ) as "lists!"
+           from users u
+           order by u.username
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the users.")
+    .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "users": users })))
+}
+
+/// Email a one-time link to pick a username and password, joining with
+/// `role`. Editors may publish to `lists` once they joined.
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(body, pool, app_base_url, user),
+    fields(role = %body.role)
+)]
+pub async fn invite_user(
+    body: Json<InvitationData>,
+    pool: Data<PgPool>,
+    app_base_url: Data<ApplicationBaseUrl>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let InvitationData { email, role, lists } = body.0;
+    let email = SubscriberEmail::parse(email).map_err(e400)?;
+    let role = Role::parse(&role).map_err(e400)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let list_ids = get_list_ids(&mut transaction, &lists).await?;
+    let invitation_token = generate_subscription_token();
+    sqlx::query!(
+        r#"insert into user_invitations (invitation_token, email, role, list_ids, invited_by)
+           values ($1, $2, $3, $4, $5)
+        "#,
+        invitation_token,
+        email.as_ref(),
+        role.as_str(),
+        &list_ids,
+        user.user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the invitation.")
+    .map_err(e500)?;
+
+    let invitation_link = format!(
+        "{}/invitations/accept?token={}",
+        app_base_url.0, invitation_token
+    );
+    let text_content = format!(
+        "You have been invited to join the newsletter as {}.\n\
+        Pick a username and password within {} days: {}",
+        role.as_str(),
+        INVITATION_TTL_DAYS,
+        invitation_link
+    );
+    let html_content = format!(
+        r#"<p>You have been invited to join the newsletter as {}.</p>
+<p><a href="{}">Pick a username and password</a> within {} days.</p>"#,
+        role.as_str(),
+        htmlescape::encode_attribute(&invitation_link),
+        INVITATION_TTL_DAYS
+    );
+    enqueue_email(
+        &mut transaction,
+        &QueuedEmail {
+            sender: None,
+            recipient: &email,
+            subject: "You are invited to the newsletter",
+            html_content: &html_content,
+            text_content: &text_content,
+            unsubscribe_link: None,
+        },
+    )
+    .await
+    .context("Failed to queue the invitation email.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store an invitation.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "email": email.as_ref(),
+        "role": role.as_str(),
+        "lists": lists,
+    })))
+}
+
+/// Replace the lists an editor may publish to.
+#[tracing::instrument(name = "Set the lists of a user", skip(body, pool, user))]
+pub async fn set_user_lists(
+    user_id: Path<Uuid>,
+    body: Json<PermissionsData>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    let user_id = user_id.into_inner();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let exists = sqlx::query!(r#"select user_id from users where user_id = $1"#, user_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to look up the user.")
+        .map_err(e500)?
+        .is_some();
+    if !exists {
+        return Err(user_not_found());
+    }
+    let list_ids = get_list_ids(&mut transaction, &body.lists).await?;
+
+    sqlx::query!(
+        r#"delete from list_permissions where user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the lists of the user.")
+    .map_err(e500)?;
+    sqlx::query!(
+        r#"insert into list_permissions (user_id, list_id)
+           select $1, unnest($2::uuid[])
+        "#,
+        user_id,
+        &list_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the lists of the user.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to set the lists of a user.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Stop a user from logging in and publishing, ending their sessions.
+#[tracing::instrument(name = "Deactivate a user", skip(pool, user))]
+pub async fn deactivate_user(
+    user_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    user: ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, actix_web::Error> {
+    user.require_admin()?;
+    // or nobody may be left to manage the users
+    if *user_id == user.user_id {
+        return Err(e400("You cannot deactivate yourself."));
+    }
+
+    // keep the original date if they had already been deactivated
+    let result = sqlx::query!(
+        r#"update users
+           set deactivated_at = coalesce(deactivated_at, now())
+           where user_id = $1
+        "#,
+        *user_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to deactivate the user.")
+    .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        return Err(user_not_found());
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// the ids of the lists named by `slugs`, a 400 if one of them does not exist
+async fn get_list_ids(
+    transaction: &mut Transaction<'_, Postgres>,
+    slugs: &[String],
+) -> Result<Vec<Uuid>, actix_web::Error> {
+    let rows = sqlx::query!(
+        r#"select slug, list_id from lists where slug = any($1)"#,
+        slugs
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to look up the lists.")
+    .map_err(e500)?;
+
+    let mut list_ids = slugs
+        .iter()
+        .map(|slug| {
+            rows.iter()
+                .find(|row| &row.slug == slug)
+                .map(|row| row.list_id)
+                .ok_or_else(|| e400(unknown_list(Some(slug))))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    list_ids.sort();
+    list_ids.dedup();
+
+    Ok(list_ids)
+}
+
+fn user_not_found() -> actix_web::Error {
+    actix_web::error::ErrorNotFound("There is no user with this id.")
+}
@@ -0,0 +1,228 @@
+use crate::authentication::{compute_password_hash, Role};
+use crate::helpers::{error_chain_fmt, see_other};
+use crate::routes::check_password_length;
+use crate::telemetry::spawn_blocking_with_tracing;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::{Debug, Formatter};
+use uuid::Uuid;
+
+/// How many days an invitation link can be used.
+pub const INVITATION_TTL_DAYS: i64 = 7;
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptanceFormData {
+    token: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum InvitationError {
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("The link is invalid or has already been used.")]
+    InvalidLinkError,
+
+    #[error("The link has expired.")]
+    LinkExpiredError,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for InvitationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            InvitationError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            InvitationError::InvalidLinkError => StatusCode::UNAUTHORIZED,
+            InvitationError::LinkExpiredError => StatusCode::GONE,
+            InvitationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Debug for InvitationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+struct Invitation {
+    email: String,
+    role: String,
+    list_ids: Vec<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Invitation form", skip(parameters, pool))]
+pub async fn accept_invitation_form(
+    parameters: web::Query<InvitationParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InvitationError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let invitation = get_valid_invitation(&mut transaction, &parameters.token).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Join the newsletter</title>
+</head>
+<body>
+    <p>You have been invited as {} with {}. Pick a username and password to join.</p>
+    <form action="/invitations/accept" method="post">
+        <input hidden type="text" name="token" value="{}">
+        <label>Username
+            <input type="text" name="username">
+        </label>
+        <br>
+        <label>Password
+            <input type="password" name="password">
+        </label>
+        <br>
+        <label>Confirm password
+            <input type="password" name="password_check">
+        </label>
+        <br>
+        <button type="submit">Join</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&invitation.role),
+            htmlescape::encode_minimal(&invitation.email),
+            htmlescape::encode_attribute(&parameters.token)
+        )))
+}
+
+/// Create the invited user, with the role and lists of the invitation.
+#[tracing::instrument(
+    name = "Accept an invitation",
+    skip(form, pool),
+    fields(username = %form.username)
+)]
+pub async fn accept_invitation(
+    form: web::Form<AcceptanceFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InvitationError> {
+    let AcceptanceFormData {
+        token,
+        username,
+        password,
+        password_check,
+    } = form.0;
+    let username = username.trim().to_owned();
+    if username.is_empty() {
+        return Err(InvitationError::ValidationError(
+            "The username cannot be empty.".into(),
+        ));
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        return Err(InvitationError::ValidationError(
+            "You entered two different passwords - the field values must match.".into(),
+        ));
+    }
+    check_password_length(&password).map_err(InvitationError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let invitation = get_valid_invitation(&mut transaction, &token).await?;
+    let role = Role::parse(&invitation.role).map_err(|e| anyhow::anyhow!(e))?;
+
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await
+        .context("Failed to spawn blocking task.")?
+        .context("Failed to hash password.")?;
+    let user_id = Uuid::new_v4();
+    let n_inserted_rows = sqlx::query!(
+        r#"insert into users (user_id, username, password_hash, role)
+           values ($1, $2, $3, $4)
+           on conflict (username) do nothing
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        role.as_str()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the new user.")?
+    .rows_affected();
+    if n_inserted_rows == 0 {
+        return Err(InvitationError::ValidationError(
+            "This username is already taken.".into(),
+        ));
+    }
+
+    // lists deleted since the invitation are left out
+    sqlx::query!(
+        r#"insert into list_permissions (user_id, list_id)
+           select $1, list_id from lists where list_id = any($2)
+        "#,
+        user_id,
+        &invitation.list_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the lists of the new user.")?;
+    sqlx::query!(
+        r#"update user_invitations set accepted_at = now() where invitation_token = $1"#,
+        token
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark the invitation as accepted.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to accept an invitation.")?;
+
+    FlashMessage::info("Welcome! You can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+// the invitation is locked, so it is only accepted once
+async fn get_valid_invitation(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+) -> Result<Invitation, InvitationError> {
+    let invitation = sqlx::query_as!(
+        Invitation,
+        r#"select email, role, list_ids, created_at from user_invitations
+           where invitation_token = $1 and accepted_at is null
+           for update
+        "#,
+        token
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to look up the invitation.")?
+    .ok_or(InvitationError::InvalidLinkError)?;
+
+    if invitation.created_at + chrono::Duration::days(INVITATION_TTL_DAYS) < Utc::now() {
+        return Err(InvitationError::LinkExpiredError);
+    }
+
+    Ok(invitation)
+}
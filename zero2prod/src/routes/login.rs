@@ -0,0 +1,7 @@
+mod get;
+mod post;
+mod two_factor;
+
+pub use get::login_form;
+pub use post::login;
+pub use two_factor::{login_two_factor, login_two_factor_form};
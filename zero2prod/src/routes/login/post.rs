@@ -0,0 +1,85 @@
+use crate::authentication::{second_factor_enabled, validate_credentials, AuthError, Credentials};
+use crate::helpers::{error_chain_fmt, see_other};
+use crate::session_state::TypedSession;
+use actix_web::error::InternalError;
+use actix_web::web::{Data, Form};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::fmt::{Debug, Formatter};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+
+    #[error("Something went wrong.")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for LoginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(
+    skip(form, pool, session),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    form: Form<FormData>,
+    pool: Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            let second_factor = second_factor_enabled(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            // a new session key on every login prevents session fixation
+            session.renew();
+            if second_factor {
+                // logged in once the code is checked, see `login_two_factor`
+                session
+                    .insert_pending_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(see_other("/login/two_factor"));
+            }
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(e) => {
+            let e = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+            };
+
+            Err(login_redirect(e))
+        }
+    }
+}
+
+// Redirect to the login page with an error message.
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    let response = see_other("/login");
+    InternalError::from_response(e, response)
+}
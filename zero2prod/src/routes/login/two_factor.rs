@@ -0,0 +1,113 @@
+use super::post::LoginError;
+use crate::authentication::{verify_second_factor, AuthError};
+use crate::clock::Clock;
+use crate::helpers::{e500, see_other};
+use crate::session_state::TypedSession;
+use actix_web::error::InternalError;
+use actix_web::http::header::ContentType;
+use actix_web::web::{Data, Form};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    // from the authenticator app, or a recovery code
+    code: Secret<String>,
+}
+
+/// The second step of logging in, for users who enrolled a second factor.
+pub async fn login_two_factor_form(
+    flash_messages: IncomingFlashMessages,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let mut error_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            error_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login/two_factor" method="post">
+        <label>Code
+            <input
+                type="text"
+                placeholder="Code from your app or a recovery code"
+                name="code"
+                autocomplete="one-time-code"
+            >
+        </label>
+        <button type="submit">Verify</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    skip(form, pool, clock, session),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn login_two_factor(
+    form: Form<FormData>,
+    pool: Data<PgPool>,
+    clock: Data<Clock>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let user_id = match session
+        .get_pending_user_id()
+        .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e.into())))?
+    {
+        Some(user_id) => user_id,
+        // the password comes first
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    match verify_second_factor(user_id, &form.0.code, clock.now(), &pool).await {
+        Ok(()) => {
+            session.renew();
+            session.remove_pending_user_id();
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e.into())))?;
+
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(e) => {
+            let e = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+            };
+
+            Err(two_factor_redirect(e))
+        }
+    }
+}
+
+// Redirect back to the code form with an error message.
+fn two_factor_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    let response = see_other("/login/two_factor");
+    InternalError::from_response(e, response)
+}
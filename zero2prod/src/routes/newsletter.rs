@@ -1,20 +1,49 @@
-use sha3::Digest;
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::authentication::{
+    get_active_user, second_factor_enabled, validate_api_token, validate_credentials,
+    verify_second_factor, ApiScope, AuthError, AuthenticatedUser, Credentials,
+};
+use crate::clock::Clock;
+use crate::domain::Segment;
 use crate::helpers::error_chain_fmt;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_scheduler::publish_issue;
+use crate::lists::{get_list_by_slug, unknown_list};
+use crate::markdown::{render_markdown, MarkdownError};
+use crate::startup::ApplicationBaseUrl;
+use crate::tracking::Tracking;
 use actix_web::http::header::{HeaderMap, HeaderValue};
 use actix_web::http::{header, StatusCode};
 use actix_web::web::{Data, Json};
 use actix_web::{HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
 use std::fmt::{Debug, Display, Formatter};
-
+use uuid::Uuid;
+
+// carries the second factor of users who enrolled one, next to 'Basic'
+// credentials
+const SECOND_FACTOR_HEADER: &str = "X-TOTP-Code";
+
+/// An issue comes either as ready-made `content` or as `markdown`, which we
+/// render to html and text ourselves. Without `send_at` it goes out right away,
+/// without `segment` to every confirmed subscriber of the list, the default
+/// list without `list`.
+///
+/// Opens and clicks are only tracked when `tracking` asks for it.
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
-    content: Content,
+    // the slug of the list
+    list: Option<String>,
+    content: Option<Content>,
+    markdown: Option<String>,
+    send_at: Option<DateTime<Utc>>,
+    // e.g. `tag:rust AND NOT tag:beta`, see `Segment`
+    segment: Option<String>,
+    #[serde(default)]
+    tracking: Tracking,
 }
 
 #[derive(serde::Deserialize)]
@@ -28,6 +57,21 @@ pub enum PublishError {
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
 
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("The user is not allowed to publish to this list.")]
+    Forbidden,
+
+    #[error("The API token does not have the `{}` scope.", .0.as_str())]
+    ScopeError(ApiScope),
+
+    #[error("A request with the same idempotency key is still being processed.")]
+    InFlightError,
+
+    #[error("There is no pending newsletter issue with this id.")]
+    NotFound,
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -37,14 +81,21 @@ impl ResponseError for PublishError {
         match self {
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::Forbidden | PublishError::ScopeError(_) => StatusCode::FORBIDDEN,
+            PublishError::InFlightError => StatusCode::CONFLICT,
+            PublishError::NotFound => StatusCode::NOT_FOUND,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         match self {
-            PublishError::UnexpectedError(_) => {
-                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
-            }
+            PublishError::UnexpectedError(_)
+            | PublishError::ValidationError(_)
+            | PublishError::Forbidden
+            | PublishError::ScopeError(_)
+            | PublishError::InFlightError
+            | PublishError::NotFound => HttpResponse::new(self.status_code()),
             PublishError::AuthError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
                 let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
@@ -64,9 +115,27 @@ impl Debug for PublishError {
     }
 }
 
-struct Credentials {
-    username: String,
-    password: Secret<String>,
+fn publish_auth_error(e: AuthError) -> PublishError {
+    match e {
+        AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+        AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+    }
+}
+
+// `None` without a 'Bearer' token, the request may carry 'Basic' credentials
+fn bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    let token = headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?
+        .trim();
+    Some(Secret::new(token.to_owned()))
+}
+
+fn second_factor_code(headers: &HeaderMap) -> Option<Secret<String>> {
+    let code = headers.get(SECOND_FACTOR_HEADER)?.to_str().ok()?.trim();
+    Some(Secret::new(code.to_owned()))
 }
 
 fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
@@ -103,124 +172,252 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Erro
     })
 }
 
-async fn validate_credenticals(
-    credentials: &Credentials,
-    pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-    let password_hash = sha3::Sha3_256::digest(
-        credentials.password.expose_secret().as_bytes()
-    );
-    let password_hash = format!("{:x}", password_hash);
-
-    let user_id: Option<_> = sqlx::query!(
-        r#"select user_id from users
-           where username = $1 and password_hash = $2
-        "#,
-        credentials.username,
-        password_hash
-    )
-    .fetch_optional(pool)
-    .await
-    .context("Failed to perform a query to validate auth credentials.")
-    .map_err(PublishError::UnexpectedError)?;
+impl BodyData {
+    fn into_content(self, base_url: &str) -> Result<(String, Content), PublishError> {
+        let content = match (self.content, self.markdown) {
+            (Some(content), None) => content,
+            (None, Some(markdown)) => {
+                let rendered = render_markdown(&markdown, base_url).map_err(|e| match e {
+                    MarkdownError::UnsafeHtml | MarkdownError::UnsafeUrl(_) => {
+                        PublishError::ValidationError(e.to_string())
+                    }
+                    MarkdownError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+                })?;
+                Content {
+                    html: rendered.html,
+                    text: rendered.text,
+                }
+            }
+            _ => {
+                return Err(PublishError::ValidationError(
+                    "Either `content` or `markdown` must be provided, not both.".into(),
+                ))
+            }
+        };
 
-    user_id
-        .map(|r| r.user_id)
-        .ok_or_else(|| anyhow::anyhow!("Invalid username or password."))
-        .map_err(PublishError::AuthError)
+        Ok((self.title, content))
+    }
 }
 
 #[tracing::instrument(
     name = "Publish email to confirmed subscriber.",
-    skip(body, pool, email_client, request)
+    skip(body, pool, base_url, clock, request)
 )]
 pub async fn publish_newsletter(
     body: Json<BodyData>,
     pool: Data<PgPool>,
-    email_client: Data<EmailClient>,
+    base_url: Data<ApplicationBaseUrl>,
+    clock: Data<Clock>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    // log who is making the request
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let user = authenticate(&request, &pool, &clock, ApiScope::Publish).await?;
 
-    let user_id = validate_credenticals(&credentials, &pool).await?;
-    // log who is making the request
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-    
-
-    let subscribers = get_confirmed_subscriber(&pool)
+    let idempotency_key = get_idempotency_key(request.headers())?;
+    let send_at = body.send_at;
+    let tracking = body.tracking;
+    let list = get_list_by_slug(&pool, body.list.as_deref())
         .await
-        .context("Failed to get confirmed subscribers from database.")?;
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send email to subscriber: {}.", subscriber.email)
-                    })?;
-            }
+        .context("Failed to look up the list.")?
+        .ok_or_else(|| PublishError::ValidationError(unknown_list(body.list.as_deref())))?;
+    authorize_publishing(&pool, &user, list.list_id).await?;
+    let segment = body
+        .segment
+        .clone()
+        .map(Segment::parse)
+        .transpose()
+        .map_err(PublishError::ValidationError)?;
+    let (title, content) = body.0.into_content(&base_url.0)?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, user.user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        NextAction::InFlight => return Err(PublishError::InFlightError),
+    };
+
+    let issue = NewIssue {
+        title: &title,
+        text_content: &content.text,
+        html_content: &content.html,
+        segment: segment.as_ref(),
+        send_at,
+        tracking,
+    };
+    // the key is released if anything fails before the response is saved
+    let newsletter_issue_id =
+        schedule_newsletter_delivery(&mut transaction, list.list_id, &issue).await?;
+
+    // delivery happens in the background, see `issue_scheduler` and
+    // `issue_delivery_worker`
+    let response = HttpResponse::Accepted().json(serde_json::json!({
+        "newsletter_issue_id": newsletter_issue_id
+    }));
+    let response = save_response(transaction, &idempotency_key, user.user_id, response)
+        .await
+        .context("Failed to save the response for the idempotency key.")?;
+
+    Ok(response)
+}
 
-            Err(error) => {
-                tracing::warn!(
-                    error.cause_chain = ?error,
-                    "Skipping a confirmed subcriber. \
-                    Their stored contact details are invalid",
-                );
+/// Check the 'Bearer' API token or the 'Basic' credentials of an API request,
+/// returning the user. A token must have the `scope` of the request, 'Basic'
+/// credentials the second factor of the user if they enrolled one.
+pub(crate) async fn authenticate(
+    request: &HttpRequest,
+    pool: &PgPool,
+    clock: &Clock,
+    scope: ApiScope,
+) -> Result<AuthenticatedUser, PublishError> {
+    let user_id = match bearer_token(request.headers()) {
+        Some(token) => {
+            let token = validate_api_token(token, pool)
+                .await
+                .map_err(publish_auth_error)?;
+            // log which token is used
+            tracing::Span::current()
+                .record("api_token_id", tracing::field::display(&token.api_token_id));
+            if !token.allows(scope) {
+                return Err(PublishError::ScopeError(scope));
             }
+            token.user_id
         }
+        None => {
+            let credentials =
+                basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+            // log who is making the request
+            tracing::Span::current()
+                .record("username", tracing::field::display(&credentials.username));
+
+            let user_id = validate_credentials(credentials, pool)
+                .await
+                .map_err(publish_auth_error)?;
+            // unlike tokens, which were created after logging in with it
+            if second_factor_enabled(user_id, pool).await? {
+                let code = second_factor_code(request.headers()).ok_or_else(|| {
+                    PublishError::AuthError(anyhow::anyhow!(
+                        "The '{}' header is missing.",
+                        SECOND_FACTOR_HEADER
+                    ))
+                })?;
+                verify_second_factor(user_id, &code, clock.now(), pool)
+                    .await
+                    .map_err(publish_auth_error)?;
+            }
+            user_id
+        }
+    };
+    // log who is making the request
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    // the user may have been deactivated since their credentials were checked
+    get_active_user(user_id, pool)
+        .await?
+        .ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("The user was deactivated.")))
+}
+
+/// Reject users who may not publish to the list.
+pub(crate) async fn authorize_publishing(
+    pool: &PgPool,
+    user: &AuthenticatedUser,
+    list_id: Uuid,
+) -> Result<(), PublishError> {
+    let allowed = user
+        .may_publish_to(pool, list_id)
+        .await
+        .context("Failed to check the publishing permissions of the user.")?;
+    if !allowed {
+        return Err(PublishError::Forbidden);
     }
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
+}
+
+fn get_idempotency_key(headers: &HeaderMap) -> Result<IdempotencyKey, PublishError> {
+    let header_value = headers
+        .get("Idempotency-Key")
+        .ok_or_else(|| {
+            PublishError::ValidationError("The 'Idempotency-Key' header is missing.".into())
+        })?
+        .to_str()
+        .map_err(|_| {
+            PublishError::ValidationError(
+                "The 'Idempotency-Key' header was not a valid utf8 string.".into(),
+            )
+        })?;
+
+    IdempotencyKey::parse(header_value.to_owned()).map_err(PublishError::ValidationError)
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+/// An issue as the editor wrote it, before it is stored.
+pub(crate) struct NewIssue<'a> {
+    pub title: &'a str,
+    pub text_content: &'a str,
+    pub html_content: &'a str,
+    // every confirmed subscriber of the list without one
+    pub segment: Option<&'a Segment>,
+    // right away without one
+    pub send_at: Option<DateTime<Utc>>,
+    pub tracking: Tracking,
 }
 
-//#[tracing::instrument(name = "Get confirmed subscriber", skip(pool))]
-async fn get_confirmed_subscriber(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    // Demostrate how query_as is used, when return multiple fields
-
-    // struct Row {
-    //     email: String
-    // }
-
-    // let rows = sqlx::query_as!(
-    //     Row,
-    //     r#"select email from subscriptions where status = 'confirmed'"#
-    // )
-    // .fetch_all(pool)
-    // .await?;
-
-    // let confirmed_subscribers = rows
-    //     .into_iter()
-    //     .map(|r| match SubscriberEmail::parse(r.email) {
-    //         Ok(email) => Ok(ConfirmedSubscriber { email }),
-    //         Err(error) => Err(anyhow::anyhow!(error))
-    //     })
-    //     .collect();
-
-    let confirmed_subscribers =
-        sqlx::query!(r#"select email from subscriptions where status = 'confirmed'"#)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .map(|r| match SubscriberEmail::parse(r.email) {
-                Ok(email) => Ok(ConfirmedSubscriber { email }),
-                Err(error) => Err(anyhow::anyhow!(error)),
-            })
-            .collect();
-
-    Ok(confirmed_subscribers)
+/// Store the issue and, unless it is meant to go out later, queue one
+/// delivery task per confirmed subscriber of the list in its segment.
+///
+/// Everything happens in `transaction`, the one that claimed the idempotency
+/// key of the request. Issues sent later are published by the
+/// `issue_scheduler`.
+pub(crate) async fn schedule_newsletter_delivery(
+    transaction: &mut Transaction<'static, Postgres>,
+    list_id: Uuid,
+    issue: &NewIssue<'_>,
+) -> Result<Uuid, anyhow::Error> {
+    let now = Utc::now();
+    let send_at = issue.send_at.unwrap_or(now);
+
+    let newsletter_issue_id = insert_newsletter_issue(transaction, list_id, issue, send_at)
+        .await
+        .context("Failed to store newsletter issue details.")?;
+
+    if send_at <= now {
+        publish_issue(transaction, newsletter_issue_id).await?;
+    }
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    issue: &NewIssue<'_>,
+    send_at: DateTime<Utc>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"insert into newsletter_issues (
+               newsletter_issue_id,
+               list_id,
+               title,
+               text_content,
+               html_content,
+               status,
+               send_at,
+               segment,
+               track_opens,
+               track_clicks
+           )
+           values ($1, $2, $3, $4, $5, 'scheduled', $6, $7, $8, $9)
+        "#,
+        newsletter_issue_id,
+        list_id,
+        issue.title,
+        issue.text_content,
+        issue.html_content,
+        send_at,
+        issue.segment.map(|segment| segment.to_string()),
+        issue.tracking.opens,
+        issue.tracking.clicks
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(newsletter_issue_id)
 }
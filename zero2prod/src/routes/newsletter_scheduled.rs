@@ -0,0 +1,135 @@
+use crate::authentication::{ApiScope, AuthenticatedUser, Role};
+use crate::clock::Clock;
+use crate::routes::{authenticate, authorize_publishing, PublishError};
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// An issue waiting for its `send_at`, see `issue_scheduler`.
+#[derive(serde::Serialize)]
+pub struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    send_at: DateTime<Utc>,
+    segment: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleData {
+    send_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List scheduled newsletter issues.", skip(pool, clock, request))]
+pub async fn list_scheduled_newsletters(
+    pool: Data<PgPool>,
+    clock: Data<Clock>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user = authenticate(&request, &pool, &clock, ApiScope::Schedule).await?;
+
+    // only the issues of the lists the user may publish to, see
+    // `authorize_changing`
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"select newsletter_issue_id, title, send_at, segment
+           from newsletter_issues
+           where status = 'scheduled'
+             and ($1 or list_id in (
+                 select list_id from list_permissions where user_id = $2
+             ))
+           order by send_at
+        "#,
+        user.role == Role::Admin,
+        user.user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to fetch the scheduled newsletter issues.")?;
+
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue.",
+    skip(body, pool, clock, request),
+    fields(send_at = %body.send_at)
+)]
+pub async fn reschedule_newsletter(
+    newsletter_issue_id: Path<Uuid>,
+    body: Json<RescheduleData>,
+    pool: Data<PgPool>,
+    clock: Data<Clock>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user = authenticate(&request, &pool, &clock, ApiScope::Schedule).await?;
+    authorize_changing(&pool, &user, *newsletter_issue_id).await?;
+
+    // the row is locked while the scheduler publishes it, by then it is no
+    // longer 'scheduled'
+    let result = sqlx::query!(
+        r#"update newsletter_issues
+           set send_at = $2
+           where newsletter_issue_id = $1 and status = 'scheduled'
+        "#,
+        *newsletter_issue_id,
+        body.send_at
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to reschedule the newsletter issue.")?;
+
+    if result.rows_affected() == 0 {
+        return Err(PublishError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Cancel a newsletter issue.", skip(pool, clock, request))]
+pub async fn cancel_newsletter(
+    newsletter_issue_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    clock: Data<Clock>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user = authenticate(&request, &pool, &clock, ApiScope::Schedule).await?;
+    authorize_changing(&pool, &user, *newsletter_issue_id).await?;
+
+    let result = sqlx::query!(
+        r#"update newsletter_issues
+           set status = 'cancelled'
+           where newsletter_issue_id = $1 and status = 'scheduled'
+        "#,
+        *newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to cancel the newsletter issue.")?;
+
+    if result.rows_affected() == 0 {
+        return Err(PublishError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// an issue is changed by those who may publish to its list
+async fn authorize_changing(
+    pool: &PgPool,
+    user: &AuthenticatedUser,
+    newsletter_issue_id: Uuid,
+) -> Result<(), PublishError> {
+    let issue = sqlx::query!(
+        r#"select list_id from newsletter_issues where newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the newsletter issue.")?
+    .ok_or(PublishError::NotFound)?;
+
+    authorize_publishing(pool, user, issue.list_id).await
+}
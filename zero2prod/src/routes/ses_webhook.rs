@@ -0,0 +1,163 @@
+use crate::helpers::error_chain_fmt;
+use crate::sns::{SnsClient, SnsError, SnsMessage, SnsMessageType};
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::{Debug, Formatter};
+
+/// The part of an SES notification we act on, everything else is ignored.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SesNotification {
+    // `eventType` when the notification comes from a configuration set
+    #[serde(alias = "eventType")]
+    notification_type: String,
+    bounce: Option<Bounce>,
+    complaint: Option<Complaint>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bounce {
+    bounce_type: String,
+    bounced_recipients: Vec<Recipient>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Complaint {
+    complained_recipients: Vec<Recipient>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Recipient {
+    email_address: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum SesWebhookError {
+    #[error("{0}")]
+    MalformedMessage(String),
+
+    #[error(transparent)]
+    VerificationError(SnsError),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<SnsError> for SesWebhookError {
+    fn from(e: SnsError) -> Self {
+        match e {
+            SnsError::UnexpectedError(e) => SesWebhookError::UnexpectedError(e),
+            e => SesWebhookError::VerificationError(e),
+        }
+    }
+}
+
+impl ResponseError for SesWebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SesWebhookError::MalformedMessage(_) => StatusCode::BAD_REQUEST,
+            SesWebhookError::VerificationError(_) => StatusCode::FORBIDDEN,
+            SesWebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Debug for SesWebhookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Receive the bounces and complaints SES publishes to our SNS topic.
+///
+/// Permanently bounced addresses are marked `bounced`, addresses that
+/// complained are marked `complained`, neither receives issues any more.
+/// Transient bounces are left to the retries of the delivery worker.
+#[tracing::instrument(name = "Handle SES notification", skip(body, pool, sns_client))]
+pub async fn handle_ses_notification(
+    // SNS posts JSON as `text/plain`
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    sns_client: web::Data<SnsClient>,
+) -> Result<HttpResponse, SesWebhookError> {
+    let message: SnsMessage = serde_json::from_slice(&body)
+        .map_err(|e| SesWebhookError::MalformedMessage(e.to_string()))?;
+    sns_client.verify(&message).await?;
+
+    match message.message_type {
+        SnsMessageType::SubscriptionConfirmation => {
+            sns_client.confirm_subscription(&message).await?;
+        }
+        SnsMessageType::UnsubscribeConfirmation => {
+            tracing::warn!(
+                topic_arn = %message.topic_arn,
+                "The endpoint was unsubscribed from the SNS topic."
+            );
+        }
+        SnsMessageType::Notification => {
+            let notification: SesNotification = serde_json::from_str(&message.message)
+                .map_err(|e| SesWebhookError::MalformedMessage(e.to_string()))?;
+            handle_notification(&pool, notification).await?;
+        }
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn handle_notification(
+    pool: &PgPool,
+    notification: SesNotification,
+) -> Result<(), SesWebhookError> {
+    let SesNotification {
+        notification_type,
+        bounce,
+        complaint,
+    } = notification;
+    let (status, recipients) = match (notification_type.as_str(), bounce, complaint) {
+        ("Bounce", Some(bounce), _) => {
+            if bounce.bounce_type != "Permanent" {
+                tracing::info!(bounce_type = %bounce.bounce_type, "Ignoring a transient bounce.");
+                return Ok(());
+            }
+            ("bounced", bounce.bounced_recipients)
+        }
+        ("Complaint", _, Some(complaint)) => ("complained", complaint.complained_recipients),
+        ("Bounce" | "Complaint", _, _) => {
+            return Err(SesWebhookError::MalformedMessage(
+                "The notification has no details.".into(),
+            ))
+        }
+        (notification_type, _, _) => {
+            tracing::info!(%notification_type, "Ignoring an SES notification.");
+            return Ok(());
+        }
+    };
+
+    // matches `subscriptions_lower_email_idx`
+    let emails: Vec<String> = recipients
+        .into_iter()
+        .map(|r| r.email_address.to_lowercase())
+        .collect();
+    let result = sqlx::query!(
+        r#"update subscriptions
+           set status = $2, suppressed_at = now()
+           where lower(email) = any($1) and status <> $2
+        "#,
+        &emails,
+        status
+    )
+    .execute(pool)
+    .await
+    .context("Failed to suppress the subscribers.")?;
+    tracing::info!(
+        n_subscribers = result.rows_affected(),
+        %status,
+        "Suppressed subscribers."
+    );
+
+    Ok(())
+}
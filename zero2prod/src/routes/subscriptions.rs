@@ -1,22 +1,38 @@
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
+use crate::bot_check::FormTokens;
+use crate::clock::Clock;
+use crate::consent::{record_consent_event, ConsentEvent, ConsentEventKind};
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTag};
+use crate::email_outbox::{enqueue_email, QueuedEmail};
+use crate::email_templates::{EmailTemplate, EmailTemplates, RenderedEmail, TemplateVariables};
 use crate::helpers::error_chain_fmt;
+use crate::lists::{get_list_by_slug, unknown_list, List};
+use crate::request_origin::RequestOrigin;
 use crate::startup::ApplicationBaseUrl;
+use crate::unsubscribe::UnsubscribeLinks;
+use actix_web::http::header::ContentType;
 use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
 use anyhow::Context;
-use aws_sdk_sesv2 as sesv2;
-use aws_sdk_sesv2::operation::send_email::SendEmailOutput;
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use sqlx::{PgPool, Postgres, Transaction};
 use std::fmt::{Debug, Display, Formatter};
 use uuid::Uuid;
 
+pub const CONFIRMATION_TEMPLATE: &str = "confirmation";
+
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    // comma separated
+    tags: Option<String>,
+    // the slug of the list, the default list without one
+    list: Option<String>,
+    // which form this is, e.g. `homepage-footer`
+    source: Option<String>,
+    // the version of the consent text next to the form
+    consent_text_version: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -29,9 +45,69 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
+#[derive(serde::Deserialize)]
+pub struct SubscribeFormParameters {
+    list: Option<String>,
+}
+
+/// The subscription form, with the honeypot and the signed time it was
+/// rendered at that `reject_automated_subscriptions` checks.
+pub async fn subscribe_form(
+    parameters: web::Query<SubscribeFormParameters>,
+    form_tokens: web::Data<FormTokens>,
+    clock: web::Data<Clock>,
+) -> HttpResponse {
+    let list = match &parameters.list {
+        Some(list) => format!(
+            r#"<input hidden type="text" name="list" value="{}">"#,
+            htmlescape::encode_attribute(list)
+        ),
+        None => String::new(),
+    };
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribe</title>
+</head>
+<body>
+    <form action="/subscriptions" method="post">
+        <label>Name
+            <input type="text" name="name" required>
+        </label>
+        <label>Email
+            <input type="email" name="email" required>
+        </label>
+        <div style="display: none" aria-hidden="true">
+            <label>Leave this empty
+                <input type="text" name="website" value="" tabindex="-1" autocomplete="off">
+            </label>
+        </div>
+        {}
+        <input hidden type="text" name="form_token" value="{}">
+        <button type="submit">Subscribe</button>
+    </form>
+</body>
+</html>"#,
+            list,
+            form_tokens.issue(clock.now())
+        ))
+}
+
 #[tracing::instrument(
     name = "Adding new subscriber",
-    skip(form, pool, email_client, app_base_url),
+    skip(
+        form,
+        pool,
+        templates,
+        app_base_url,
+        unsubscribe_links,
+        origin
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -40,32 +116,78 @@ impl TryFrom<FormData> for NewSubscriber {
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    templates: web::Data<EmailTemplates>,
     app_base_url: web::Data<ApplicationBaseUrl>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+    origin: RequestOrigin,
 ) -> Result<HttpResponse, SubscribeError> {
+    let tags = SubscriberTag::parse_list(form.tags.as_deref().unwrap_or_default())
+        .map_err(SubscribeError::ValidationError)?;
+    let list = get_list_by_slug(&pool, form.list.as_deref())
+        .await
+        .context("Failed to look up the list.")?
+        .ok_or_else(|| SubscribeError::ValidationError(unknown_list(form.list.as_deref())))?;
+    let source = form.source.clone();
+    let consent_text_version = form.consent_text_version.clone();
     let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
 
-    //let transaction = pool.begin().await.context("Failed to acquire a Postgres connection from the pool.")?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
 
-    let subscriber_id = insert_subscriber(&pool, &new_subscriber)
+    let subscriber_id = match insert_subscriber(&mut transaction, list.list_id, &new_subscriber)
+        .await
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        // the email is already known, send a new link if it was never
+        // confirmed or has unsubscribed since
+        None => match resubscribe(&mut transaction, list.list_id, &new_subscriber)
+            .await
+            .context("Failed to look up an existing subscriber in the database.")?
+        {
+            Some(subscriber_id) => subscriber_id,
+            None => return Ok(HttpResponse::Ok().finish()),
+        },
+    };
+
+    add_tags(&mut transaction, subscriber_id, &tags)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to store the tags of a new subscriber.")?;
+    // every signup is recorded, the text may have changed since the last one
+    record_consent_event(
+        &mut transaction,
+        subscriber_id,
+        ConsentEvent {
+            kind: ConsentEventKind::Signup,
+            origin: &origin,
+            source: source.as_deref(),
+            consent_text_version: consent_text_version.as_deref(),
+        },
+    )
+    .await
+    .context("Failed to record the consent of a new subscriber.")?;
 
     let subscription_token = generate_subscription_token();
-    store_token(&pool, subscriber_id, &subscription_token)
+    store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
-
-    //transaction.commit().await.context("Failed to commit SQL transaction to store a new subscriber.")?;
-
-    send_confirmation_email(
-        &email_client,
+    queue_confirmation_email(
+        &mut transaction,
+        &templates,
+        &list,
         &new_subscriber,
         &app_base_url.0,
         &subscription_token,
+        &unsubscribe_links.link_for(subscriber_id),
     )
-    .await
-    .context("Failed to send a confirmation email.")?;
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -74,8 +196,8 @@ pub async fn subscribe(
 //     name = "Store subscription token in the database.",
 //     skip(transaction, subscription_token)
 // )]
-async fn store_token(
-    transaction: &PgPool, // &Transaction<'_, Postgres>,
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
@@ -85,14 +207,14 @@ async fn store_token(
         subscription_token,
         subscriber_id
     )
-    .execute(transaction)
+    .execute(&mut **transaction)
     .await
     .map_err(StoreTokenError)?;
 
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -104,57 +226,145 @@ fn generate_subscription_token() -> String {
 //     name = "Saving new subscriber details in the database",
 //     skip(new_subscriber, transaction)
 // )]
+/// Returns `None` if the list already has a subscriber with the same email.
 pub async fn insert_subscriber(
-    transaction: &PgPool, // &Transaction<'_, Postgres>,
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let id = Uuid::new_v4();
-    sqlx::query!(
+    let n_inserted_rows = sqlx::query!(
         r#"
-        insert into subscriptions (id, email, name, subscribed_at, status)
-        values ($1, $2, $3, $4, 'pending_confirmation')
+        insert into subscriptions (id, list_id, email, name, subscribed_at, status)
+        values ($1, $2, $3, $4, $5, 'pending_confirmation')
+        on conflict (list_id, lower(email)) do nothing
         "#,
         id,
+        list_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(transaction)
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+
+    Ok(if n_inserted_rows > 0 { Some(id) } else { None })
+}
+
+/// Tag a subscriber, on top of the tags they already have.
+pub async fn add_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    tags: &[SubscriberTag],
+) -> Result<(), sqlx::Error> {
+    let tags: Vec<String> = tags.iter().map(|tag| tag.as_ref().to_owned()).collect();
+    sqlx::query!(
+        r#"insert into subscriber_tags (subscriber_id, tag)
+           select $1, tag from unnest($2::text[]) as tag
+           on conflict do nothing
+        "#,
+        subscriber_id,
+        &tags
+    )
+    .execute(&mut **transaction)
     .await?;
 
-    Ok(id)
+    Ok(())
 }
 
-// #[tracing::instrument(
-//     name = "Sending confirmation email to new subscriber.",
-//     skip(client, new_subscriber, app_base_url)
-// )]
-pub async fn send_confirmation_email(
-    client: &EmailClient,
+/// Put a subscriber that has not confirmed yet, or that unsubscribed, back
+/// to pending confirmation.
+///
+/// Bounced and complaining addresses stay suppressed, as do confirmed ones.
+async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"update subscriptions
+           set status = 'pending_confirmation', unsubscribed_at = null
+           where list_id = $1
+             and lower(email) = lower($2)
+             and status in ('pending_confirmation', 'unsubscribed')
+           returning id
+        "#,
+        list_id,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(result.map(|r| r.id))
+}
+
+/// Queue the confirmation email, it goes out only if `transaction` commits.
+///
+/// Sent on behalf of `list`, a subscriber confirms every list on its own.
+pub async fn queue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    templates: &EmailTemplates,
+    list: &List,
     new_subscriber: &NewSubscriber,
     app_base_url: &str,
     subscription_token: &str,
-) -> Result<SendEmailOutput, SendEmailError> {
+    unsubscribe_link: &str,
+) -> Result<(), anyhow::Error> {
+    let template = templates.get(CONFIRMATION_TEMPLATE).await?;
+    let email = render_confirmation_email(
+        &template,
+        list,
+        new_subscriber,
+        app_base_url,
+        subscription_token,
+        unsubscribe_link,
+    )?;
+
+    enqueue_email(
+        transaction,
+        &QueuedEmail {
+            sender: list.sender_email.as_deref(),
+            recipient: &new_subscriber.email,
+            subject: &confirmation_subject(list),
+            html_content: &email.html,
+            text_content: &email.text,
+            unsubscribe_link: Some(unsubscribe_link),
+        },
+    )
+    .await
+    .context("Failed to queue the confirmation email.")
+}
+
+pub fn confirmation_subject(list: &List) -> String {
+    format!("Welcome to {}!", list.name)
+}
+
+pub fn render_confirmation_email(
+    template: &EmailTemplate,
+    list: &List,
+    new_subscriber: &NewSubscriber,
+    app_base_url: &str,
+    subscription_token: &str,
+    unsubscribe_link: &str,
+) -> Result<RenderedEmail, anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         app_base_url, subscription_token
     );
 
-    let html_body = format!(
-        "welcome to our newsletter!<br />\
-        Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-
-    let text_body = format!(
-        "Welcone to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-
-    client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &text_body)
-        .await
-        .map_err(SendEmailError)
+    template
+        .render(&TemplateVariables {
+            subscriber_name: new_subscriber.name.as_ref(),
+            list_name: &list.name,
+            unsubscribe_link,
+            confirmation_link: Some(&confirmation_link),
+            data_request_link: None,
+            issue_title: None,
+            html_content: None,
+            text_content: None,
+        })
+        .context("Failed to render the confirmation email.")
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -180,26 +390,6 @@ impl std::error::Error for StoreTokenError {
     }
 }
 
-pub struct SendEmailError(aws_sdk_sesv2::Error);
-
-impl Debug for SendEmailError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
-impl Display for SendEmailError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "A error was encountered while sending email.")
-    }
-}
-
-impl std::error::Error for SendEmailError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        Some(&self.0)
-    }
-}
-
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
@@ -1,7 +1,12 @@
+use crate::consent::{record_consent_event, ConsentEvent, ConsentEventKind};
 use crate::helpers::error_chain_fmt;
+use crate::request_origin::RequestOrigin;
+use crate::startup::SubscriptionTokenTtl;
+use actix_web::http::header::ContentType;
 use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
 use anyhow::Context;
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use std::fmt::{Debug, Formatter};
 use uuid::{uuid, Uuid};
 
@@ -15,6 +20,10 @@ pub enum ConfirmEmailError {
     #[error("{0}")]
     TokenNotFoundError(String),
 
+    // the slug of the subscriber's list, to ask for a new link
+    #[error("The confirmation link has expired.")]
+    TokenExpiredError(String),
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -23,9 +32,45 @@ impl ResponseError for ConfirmEmailError {
     fn status_code(&self) -> StatusCode {
         match self {
             ConfirmEmailError::TokenNotFoundError(_) => StatusCode::UNAUTHORIZED,
+            ConfirmEmailError::TokenExpiredError(_) => StatusCode::GONE,
             ConfirmEmailError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            // point the subscriber to a way of getting a new link
+            ConfirmEmailError::TokenExpiredError(list) => HttpResponse::build(self.status_code())
+                .content_type(ContentType::html())
+                .body(format!(
+                    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation link expired</title>
+</head>
+<body>
+    <p>This confirmation link has expired.</p>
+    <form action="/subscriptions/resend_confirmation" method="post">
+        <input type="hidden" name="list" value="{}">
+        <label>Email
+            <input
+                type="email"
+                placeholder="Enter your email"
+                name="email"
+            >
+        </label>
+        <button type="submit">Resend confirmation</button>
+    </form>
+</body>
+</html>"#,
+                    htmlescape::encode_attribute(list)
+                )),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
 }
 
 impl Debug for ConfirmEmailError {
@@ -34,48 +79,124 @@ impl Debug for ConfirmEmailError {
     }
 }
 
-#[tracing::instrument(name = "Confirm subscription.", skip(parameters, pool))]
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    list_slug: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm subscription.",
+    skip(parameters, pool, token_ttl, origin)
+)]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    origin: RequestOrigin,
 ) -> Result<HttpResponse, ConfirmEmailError> {
-    let id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let token = get_unused_token(&mut transaction, &parameters.subscription_token)
         .await
         .context("Failed to get subscriber token from database.")?;
 
-    match id {
-        None => Err(ConfirmEmailError::TokenNotFoundError(format!(
+    // tokens are single-use, a consumed token is as good as an unknown one
+    let token = token.ok_or_else(|| {
+        ConfirmEmailError::TokenNotFoundError(format!(
             "Cannot find token: {}",
             parameters.subscription_token
-        ))),
-        Some(subscriber_id) => Ok(confirm_subscirber(&pool, subscriber_id)
-            .await
-            .map(|_| HttpResponse::Ok().finish())
-            .context("Failed to update subscription.")?),
+        ))
+    })?;
+
+    if token.created_at + token_ttl.0 < Utc::now() {
+        return Err(ConfirmEmailError::TokenExpiredError(token.list_slug));
     }
+
+    consume_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to mark the subscription token as used.")?;
+    let confirmed = confirm_subscirber(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to update subscription.")?;
+    if confirmed {
+        record_consent_event(
+            &mut transaction,
+            token.subscriber_id,
+            ConsentEvent {
+                kind: ConsentEventKind::Confirmation,
+                origin: &origin,
+                source: None,
+                consent_text_version: None,
+            },
+        )
+        .await
+        .context("Failed to record the confirmation of a subscriber.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
-async fn confirm_subscirber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"update subscriptions set status = 'confirmed' where id = $1"#,
+/// Returns `false` if the subscriber is no longer pending, e.g. they
+/// unsubscribed or bounced since the link was sent: an old link must not
+/// sign them back up.
+async fn confirm_subscirber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let n_updated_rows = sqlx::query!(
+        r#"update subscriptions set status = 'confirmed'
+           where id = $1 and status = 'pending_confirmation'
+        "#,
         subscriber_id,
     )
-    .execute(pool)
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected();
+
+    Ok(n_updated_rows > 0)
+}
+
+async fn get_unused_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        SubscriptionToken,
+        r#"select t.subscriber_id, t.created_at, l.slug as list_slug
+           from subscription_tokens t
+           join subscriptions s on s.id = t.subscriber_id
+           join lists l using (list_id)
+           where t.subscription_token = $1 and t.consumed_at is null
+           for update of t
+        "#,
+        subscription_token
+    )
+    .fetch_optional(&mut **transaction)
     .await?;
 
-    Ok(())
+    Ok(result)
 }
 
-async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"select subscriber_id from subscription_tokens where subscription_token = $1"#,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update subscription_tokens set consumed_at = now()
+           where subscription_token = $1
+        "#,
         subscription_token
     )
-    .fetch_optional(pool)
+    .execute(&mut **transaction)
     .await?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(())
 }
@@ -0,0 +1,742 @@
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_outbox::{enqueue_email, QueuedEmail};
+use crate::email_templates::{EmailTemplates, TemplateVariables};
+use crate::helpers::error_chain_fmt;
+use crate::keyed_mac::{self, MacPurpose};
+use crate::routes::generate_subscription_token;
+use crate::startup::{ApplicationBaseUrl, HmacSecret, SubscriptionTokenTtl};
+use crate::unsubscribe::UnsubscribeLinks;
+use actix_web::http::header::ContentType;
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::{Debug, Formatter};
+use uuid::Uuid;
+
+const EXPORT_TEMPLATE: &str = "data_export";
+const ERASURE_TEMPLATE: &str = "data_erasure";
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DataRequestKind {
+    Export,
+    Erasure,
+}
+
+impl DataRequestKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DataRequestKind::Export => "export",
+            DataRequestKind::Erasure => "erasure",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+    kind: DataRequestKind,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum DataRequestError {
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("The link is invalid or has already been used.")]
+    InvalidLinkError,
+
+    #[error("The link has expired.")]
+    LinkExpiredError,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for DataRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DataRequestError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            DataRequestError::InvalidLinkError => StatusCode::UNAUTHORIZED,
+            DataRequestError::LinkExpiredError => StatusCode::GONE,
+            DataRequestError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Debug for DataRequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Everything stored about the subscription a link was sent for, and about
+/// the same email on other lists.
+#[derive(serde::Serialize)]
+struct DataExport {
+    #[serde(flatten)]
+    subscriber: SubscriberData,
+    other_lists: Vec<SubscriberData>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberData {
+    // the slug of the list
+    list: String,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    unsubscribed_at: Option<DateTime<Utc>>,
+    // when the address bounced or complained
+    suppressed_at: Option<DateTime<Utc>>,
+    tags: Vec<String>,
+    // the tokens themselves are secrets, only when they were used is data
+    confirmation_links: Vec<LinkUse>,
+    data_requests: Vec<DataRequestUse>,
+    pending_deliveries: Vec<PendingDelivery>,
+    deliveries: Vec<DeliveryRecord>,
+    consent_events: Vec<ConsentRecord>,
+}
+
+#[derive(serde::Serialize)]
+struct LinkUse {
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct DataRequestUse {
+    kind: String,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct PendingDelivery {
+    newsletter_issue_id: Uuid,
+    title: String,
+    execute_after: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryRecord {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    attempted_at: DateTime<Utc>,
+    opened_at: Option<DateTime<Utc>>,
+    n_opens: i32,
+    n_clicks: i64,
+}
+
+#[derive(serde::Serialize)]
+struct ConsentRecord {
+    kind: String,
+    occurred_at: DateTime<Utc>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    source: Option<String>,
+    consent_text_version: Option<String>,
+}
+
+struct DataRequestToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+/// Email a subscriber a one-time link to export or erase their data.
+///
+/// Like `resend_confirmation`, the response does not tell whether the email
+/// is subscribed. The email is queued along with the token.
+#[tracing::instrument(
+    name = "Request subscriber data",
+    skip(form, pool, templates, app_base_url, unsubscribe_links),
+    fields(subscriber_email = %form.email, kind = ?form.kind)
+)]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestFormData>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+    app_base_url: web::Data<ApplicationBaseUrl>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, DataRequestError> {
+    let DataRequestFormData { email, kind } = form.0;
+    let email = SubscriberEmail::parse(email).map_err(DataRequestError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    // the data of every list goes with the link, the oldest subscription
+    // stands for them; emails are matched regardless of case, see
+    // `subscriptions_lower_email_idx`
+    let subscriber = sqlx::query!(
+        r#"select s.id, s.name, l.name as list_name, l.sender_email
+           from subscriptions s
+           join lists l using (list_id)
+           where lower(s.email) = lower($1)
+           order by s.subscribed_at
+           limit 1
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let (subscriber_id, name, list_name, sender) = match subscriber {
+        Some(subscriber) => (
+            subscriber.id,
+            SubscriberName::parse(subscriber.name).map_err(|e| anyhow::anyhow!(e))?,
+            subscriber.list_name,
+            subscriber.sender_email,
+        ),
+        None => return Ok(HttpResponse::Ok().finish()),
+    };
+
+    let token = generate_subscription_token();
+    sqlx::query!(
+        r#"insert into data_request_tokens (data_request_token, subscriber_id, kind)
+           values ($1, $2, $3)
+        "#,
+        token,
+        subscriber_id,
+        kind.as_str()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the data request token.")?;
+
+    let (template, subject, path) = match kind {
+        DataRequestKind::Export => (EXPORT_TEMPLATE, "Your data", "data"),
+        DataRequestKind::Erasure => (ERASURE_TEMPLATE, "Erase your data", "erase"),
+    };
+    let data_request_link = format!("{}/subscriptions/{}?token={}", app_base_url.0, path, token);
+    let unsubscribe_link = unsubscribe_links.link_for(subscriber_id);
+    let content = templates
+        .get(template)
+        .await
+        .and_then(|template| {
+            template.render(&TemplateVariables {
+                subscriber_name: name.as_ref(),
+                list_name: &list_name,
+                unsubscribe_link: &unsubscribe_link,
+                confirmation_link: None,
+                data_request_link: Some(&data_request_link),
+                issue_title: None,
+                html_content: None,
+                text_content: None,
+            })
+        })
+        .context("Failed to render the data request email.")?;
+    enqueue_email(
+        &mut transaction,
+        &QueuedEmail {
+            // sent on behalf of the list, like its confirmation email
+            sender: sender.as_deref(),
+            recipient: &email,
+            subject,
+            html_content: &content.html,
+            text_content: &content.text,
+            unsubscribe_link: Some(&unsubscribe_link),
+        },
+    )
+    .await
+    .context("Failed to queue the data request email.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a data request token.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Ask for a confirmation before exporting.
+///
+/// Like the erasure link, the export link only works once: a `GET` must not
+/// use it up, see `unsubscribe_form`.
+#[tracing::instrument(
+    name = "Show export confirmation page",
+    skip(parameters, pool, token_ttl)
+)]
+pub async fn export_subscriber_data_form(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+) -> Result<HttpResponse, DataRequestError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    check_token(
+        &mut transaction,
+        &parameters.token,
+        DataRequestKind::Export,
+        &token_ttl,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your data</title>
+</head>
+<body>
+    <p>Download everything we store about you, as JSON. The link only works once.</p>
+    <form action="/subscriptions/data" method="post">
+        <input hidden type="text" name="token" value="{}">
+        <button type="submit">Download my data</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(&parameters.token)
+        )))
+}
+
+/// Everything stored about the subscriber, on every list, as JSON. The link
+/// only works once.
+#[tracing::instrument(name = "Export subscriber data", skip(form, pool, token_ttl))]
+pub async fn export_subscriber_data(
+    form: web::Form<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+) -> Result<HttpResponse, DataRequestError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let subscriber_id = use_token(
+        &mut transaction,
+        &form.token,
+        DataRequestKind::Export,
+        &token_ttl,
+    )
+    .await?;
+    let data = get_data_export(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to collect the subscriber data.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to export subscriber data.")?;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// Ask for a confirmation before erasing.
+///
+/// A `GET` must not erase anything, nor use up the link, see
+/// `unsubscribe_form`.
+#[tracing::instrument(
+    name = "Show erasure confirmation page",
+    skip(parameters, pool, token_ttl)
+)]
+pub async fn erase_subscriber_data_form(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+) -> Result<HttpResponse, DataRequestError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    check_token(
+        &mut transaction,
+        &parameters.token,
+        DataRequestKind::Erasure,
+        &token_ttl,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Erase your data</title>
+</head>
+<body>
+    <p>Do you want to unsubscribe and erase everything we store about you? This cannot be undone.</p>
+    <form action="/subscriptions/erase" method="post">
+        <input hidden type="text" name="token" value="{}">
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_attribute(&parameters.token)
+        )))
+}
+
+/// Delete the subscriber from every list, with their tokens, tags and pending
+/// deliveries.
+///
+/// Only a tombstone with a keyed hash of the email is kept, to show the
+/// erasure happened without keeping the address.
+#[tracing::instrument(
+    name = "Erase subscriber data",
+    skip(form, pool, token_ttl, hmac_secret)
+)]
+pub async fn erase_subscriber_data(
+    form: web::Form<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, DataRequestError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    // the token goes with the subscriber, there is nothing left to consume
+    let subscriber_id = use_token(
+        &mut transaction,
+        &form.token,
+        DataRequestKind::Erasure,
+        &token_ttl,
+    )
+    .await?;
+    erase_subscriber(&mut transaction, subscriber_id, &hmac_secret.0)
+        .await
+        .context("Failed to erase the subscriber.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body("Your data has been erased."))
+}
+
+/// Check the token is unused and fresh, without using it.
+async fn check_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+    kind: DataRequestKind,
+    token_ttl: &SubscriptionTokenTtl,
+) -> Result<Uuid, DataRequestError> {
+    let stored = get_unused_token(transaction, token, kind)
+        .await
+        .context("Failed to look up the data request token.")?
+        .ok_or(DataRequestError::InvalidLinkError)?;
+    if stored.created_at + token_ttl.0 < Utc::now() {
+        return Err(DataRequestError::LinkExpiredError);
+    }
+
+    Ok(stored.subscriber_id)
+}
+
+/// Check the token is unused and fresh, then mark it as used.
+async fn use_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+    kind: DataRequestKind,
+    token_ttl: &SubscriptionTokenTtl,
+) -> Result<Uuid, DataRequestError> {
+    let subscriber_id = check_token(transaction, token, kind, token_ttl).await?;
+
+    sqlx::query!(
+        r#"update data_request_tokens set consumed_at = now()
+           where data_request_token = $1
+        "#,
+        token
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to mark the data request token as used.")?;
+
+    Ok(subscriber_id)
+}
+
+async fn get_unused_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+    kind: DataRequestKind,
+) -> Result<Option<DataRequestToken>, sqlx::Error> {
+    sqlx::query_as!(
+        DataRequestToken,
+        r#"select subscriber_id, created_at from data_request_tokens
+           where data_request_token = $1 and kind = $2 and consumed_at is null
+           for update
+        "#,
+        token,
+        kind.as_str()
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+async fn get_data_export(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<DataExport, sqlx::Error> {
+    let subscriber = get_subscriber_data(transaction, subscriber_id).await?;
+    let other_ids = sqlx::query!(
+        r#"select id from subscriptions
+           where lower(email) = lower($1) and id <> $2
+           order by subscribed_at
+        "#,
+        subscriber.email,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let mut other_lists = Vec::with_capacity(other_ids.len());
+    for other in other_ids {
+        other_lists.push(get_subscriber_data(transaction, other.id).await?);
+    }
+
+    Ok(DataExport {
+        subscriber,
+        other_lists,
+    })
+}
+
+async fn get_subscriber_data(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<SubscriberData, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"select
+               l.slug as list,
+               s.email,
+               s.name,
+               s.status,
+               s.subscribed_at,
+               s.unsubscribed_at,
+               s.suppressed_at
+           from subscriptions s
+           join lists l using (list_id)
+           where s.id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    let tags = sqlx::query!(
+        r#"select tag from subscriber_tags where subscriber_id = $1 order by tag"#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.tag)
+    .collect();
+    let confirmation_links = sqlx::query_as!(
+        LinkUse,
+        r#"select created_at, consumed_at from subscription_tokens
+           where subscriber_id = $1
+           order by created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let data_requests = sqlx::query_as!(
+        DataRequestUse,
+        r#"select kind, created_at, consumed_at from data_request_tokens
+           where subscriber_id = $1
+           order by created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let pending_deliveries = sqlx::query_as!(
+        PendingDelivery,
+        r#"select q.newsletter_issue_id, i.title, q.execute_after
+           from issue_delivery_queue q
+           join newsletter_issues i using (newsletter_issue_id)
+           where lower(q.subscriber_email) = lower($1)
+           order by q.execute_after
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"select
+               d.newsletter_issue_id,
+               i.title,
+               d.status,
+               d.attempted_at,
+               d.opened_at,
+               d.n_opens,
+               (select count(*) from delivery_clicks c
+                where c.delivery_id = d.delivery_id) as "n_clicks!"
+           from deliveries d
+           join newsletter_issues i using (newsletter_issue_id)
+           where d.subscriber_id = $1
+           order by d.attempted_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let consent_events = sqlx::query_as!(
+        ConsentRecord,
+        r#"select kind, occurred_at, ip, user_agent, source, consent_text_version
+           from consent_events
+           where subscriber_id = $1
+           order by occurred_at, consent_event_id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    Ok(SubscriberData {
+        list: subscriber.list,
+        email: subscriber.email,
+        name: subscriber.name,
+        status: subscriber.status,
+        subscribed_at: subscriber.subscribed_at,
+        unsubscribed_at: subscriber.unsubscribed_at,
+        suppressed_at: subscriber.suppressed_at,
+        tags,
+        confirmation_links,
+        data_requests,
+        pending_deliveries,
+        deliveries,
+        consent_events,
+    })
+}
+
+async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    hmac_secret: &Secret<String>,
+) -> Result<(), sqlx::Error> {
+    let email = sqlx::query!(
+        r#"select email from subscriptions where id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?
+    .email;
+
+    // the deliveries stay for the issue stats, anonymous once the rows are
+    // gone, but an error message may name the address
+    sqlx::query!(
+        r#"update deliveries set error = null
+           where subscriber_id in (
+               select id from subscriptions where lower(email) = lower($1)
+           )
+        "#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    // the email goes from every list, tokens and tags go with the rows, see
+    // the `on delete cascade`s
+    sqlx::query!(
+        r#"delete from subscriptions where lower(email) = lower($1)"#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    // the queues refer to subscribers by email
+    sqlx::query!(
+        r#"delete from issue_delivery_queue where lower(subscriber_email) = lower($1)"#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"delete from email_outbox where lower(recipient) = lower($1)"#,
+        email
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"insert into erasure_tombstones (tombstone_id, email_hash, erased_at)
+           values ($1, $2, now())
+        "#,
+        Uuid::new_v4(),
+        hash_email(&email, hmac_secret)
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// An HMAC of the email: a plain hash could be reversed by hashing a list of
+/// addresses.
+///
+/// Emails are case-insensitive in practice, the hash should not tell
+/// `Ursula@example.com` from `ursula@example.com`.
+pub fn hash_email(email: &str, hmac_secret: &Secret<String>) -> String {
+    keyed_mac::sign(
+        hmac_secret,
+        MacPurpose::Erasure,
+        &[email.trim().to_lowercase().as_bytes()],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-secret-used-only-in-tests".into())
+    }
+
+    #[test]
+    fn the_email_hash_ignores_case_and_surrounding_whitespace() {
+        assert_eq!(
+            hash_email(" Ursula@Example.com", &secret()),
+            hash_email("ursula@example.com", &secret())
+        );
+        assert_ne!(
+            hash_email("ursula@example.com", &secret()),
+            hash_email("octavia@example.com", &secret())
+        );
+    }
+
+    #[test]
+    fn the_email_hash_does_not_contain_the_email() {
+        let hash = hash_email("ursula@example.com", &secret());
+
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("ursula"));
+    }
+
+    #[test]
+    fn the_email_hash_cannot_be_computed_without_the_secret() {
+        let other_secret = Secret::new("another-secret".into());
+
+        assert_ne!(
+            hash_email("ursula@example.com", &secret()),
+            hash_email("ursula@example.com", &other_secret)
+        );
+        assert_ne!(
+            hash_email("ursula@example.com", &secret()),
+            hex::encode(<sha2::Sha256 as sha2::Digest>::digest(
+                b"ursula@example.com"
+            ))
+        );
+    }
+}
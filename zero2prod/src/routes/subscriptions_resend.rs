@@ -0,0 +1,105 @@
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_templates::EmailTemplates;
+use crate::lists::{get_list_by_slug, unknown_list};
+use crate::routes::{
+    generate_subscription_token, queue_confirmation_email, store_token, SubscribeError,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::unsubscribe::UnsubscribeLinks;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+    // the default list without one
+    list: Option<String>,
+}
+
+/// Send a new confirmation link to a subscriber that has not confirmed yet.
+///
+/// The response is the same whether the email is pending, confirmed or
+/// unknown, so the endpoint cannot be used to find out who is subscribed.
+#[tracing::instrument(
+    name = "Resend confirmation email",
+    skip(form, pool, templates, app_base_url, unsubscribe_links),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    templates: web::Data<EmailTemplates>,
+    app_base_url: web::Data<ApplicationBaseUrl>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, SubscribeError> {
+    let ResendFormData { email, list } = form.0;
+    let email = SubscriberEmail::parse(email).map_err(SubscribeError::ValidationError)?;
+    let list = get_list_by_slug(&pool, list.as_deref())
+        .await
+        .context("Failed to look up the list.")?
+        .ok_or_else(|| SubscribeError::ValidationError(unknown_list(list.as_deref())))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let (subscriber_id, subscriber) =
+        match get_pending_subscriber(&mut transaction, list.list_id, email)
+            .await
+            .context("Failed to look up a pending subscriber in the database.")?
+        {
+            Some(pending) => pending,
+            None => return Ok(HttpResponse::Ok().finish()),
+        };
+
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store the confirmation token for a pending subscriber.")?;
+    queue_confirmation_email(
+        &mut transaction,
+        &templates,
+        &list,
+        &subscriber,
+        &app_base_url.0,
+        &subscription_token,
+        &unsubscribe_links.link_for(subscriber_id),
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a confirmation token.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn get_pending_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    email: SubscriberEmail,
+) -> Result<Option<(Uuid, NewSubscriber)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"select id, name from subscriptions
+           where list_id = $1 and lower(email) = lower($2)
+             and status = 'pending_confirmation'
+           for update
+        "#,
+        list_id,
+        email.as_ref()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    match row {
+        Some(row) => {
+            let name = SubscriberName::parse(row.name).map_err(|e| anyhow::anyhow!(e))?;
+            Ok(Some((row.id, NewSubscriber { email, name })))
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,171 @@
+use crate::consent::{record_consent_event, ConsentEvent, ConsentEventKind};
+use crate::helpers::error_chain_fmt;
+use crate::request_origin::RequestOrigin;
+use crate::unsubscribe::UnsubscribeLinks;
+use actix_web::http::header::ContentType;
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::{Debug, Formatter};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidLinkError,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidLinkError => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Ask for a confirmation before unsubscribing.
+///
+/// Link scanners follow every link in an email, so a `GET` must not change
+/// anything. The form posts back to the same url as one-click unsubscribe.
+#[tracing::instrument(
+    name = "Show unsubscribe confirmation page.",
+    skip(parameters, pool, unsubscribe_links),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    if !unsubscribe_links.verify(parameters.subscriber_id, &parameters.token) {
+        return Err(UnsubscribeError::InvalidLinkError);
+    }
+
+    let subscriber = sqlx::query!(
+        r#"select s.email, l.name as list_name
+           from subscriptions s
+           join lists l using (list_id)
+           where s.id = $1
+        "#,
+        parameters.subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber.")?
+    .ok_or(UnsubscribeError::InvalidLinkError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving {} at {}?</p>
+    <form action="{}" method="post">
+        <input hidden type="text" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&subscriber.list_name),
+            htmlescape::encode_minimal(&subscriber.email),
+            htmlescape::encode_attribute(&unsubscribe_links.link_for(parameters.subscriber_id)),
+        )))
+}
+
+/// RFC 8058 one-click unsubscribe, also used by the confirmation page.
+#[tracing::instrument(
+    name = "Unsubscribe.",
+    skip(parameters, pool, unsubscribe_links, origin),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, UnsubscribeError> {
+    if !unsubscribe_links.verify(parameters.subscriber_id, &parameters.token) {
+        return Err(UnsubscribeError::InvalidLinkError);
+    }
+
+    mark_as_unsubscribed(&pool, parameters.subscriber_id, &origin)
+        .await
+        .context("Failed to unsubscribe the subscriber.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body("You have been unsubscribed."))
+}
+
+async fn mark_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    origin: &RequestOrigin,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let n_unsubscribed = sqlx::query!(
+        r#"update subscriptions
+           set status = 'unsubscribed', unsubscribed_at = now()
+           where id = $1 and status not in ('unsubscribed', 'bounced', 'complained')
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    // following the link again withdraws nothing more, and suppressed
+    // addresses stay suppressed: they must not be able to subscribe again
+    if n_unsubscribed > 0 {
+        record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEvent {
+                kind: ConsentEventKind::Unsubscription,
+                origin,
+                source: None,
+                consent_text_version: None,
+            },
+        )
+        .await?;
+    }
+
+    // drop the deliveries of the list that are still waiting in the queue
+    sqlx::query!(
+        r#"delete from issue_delivery_queue q
+           using subscriptions s, newsletter_issues i
+           where s.id = $1
+             and q.subscriber_email = s.email
+             and q.newsletter_issue_id = i.newsletter_issue_id
+             and i.list_id = s.list_id
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
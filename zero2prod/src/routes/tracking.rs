@@ -0,0 +1,129 @@
+use crate::helpers::error_chain_fmt;
+use crate::tracking::TrackingLinks;
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION};
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::{Debug, Formatter};
+use uuid::Uuid;
+
+// a transparent 1x1 gif
+const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\
+    !\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    url: String,
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("The link is invalid.")]
+    InvalidLinkError,
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TrackingError::InvalidLinkError => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+impl Debug for TrackingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Serve the open pixel of a delivery, counting the open.
+///
+/// The reader gets their pixel even if the open could not be recorded.
+#[tracing::instrument(name = "Track an open", skip(pool))]
+pub async fn track_open(delivery_id: web::Path<Uuid>, pool: web::Data<PgPool>) -> HttpResponse {
+    if let Err(e) = record_open(&pool, *delivery_id).await {
+        tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to record an open.");
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        // every open should reach us
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL)
+}
+
+/// Count a click on a link of a delivery and send the reader on to it.
+///
+/// Only urls signed for the delivery are followed, the endpoint cannot be
+/// used to redirect anywhere else.
+#[tracing::instrument(name = "Track a click", skip(parameters, pool, tracking_links))]
+pub async fn track_click(
+    delivery_id: web::Path<Uuid>,
+    parameters: web::Query<ClickParameters>,
+    pool: web::Data<PgPool>,
+    tracking_links: web::Data<TrackingLinks>,
+) -> Result<HttpResponse, TrackingError> {
+    let delivery_id = delivery_id.into_inner();
+    if !tracking_links.verify(delivery_id, &parameters.url, &parameters.token) {
+        return Err(TrackingError::InvalidLinkError);
+    }
+
+    if let Err(e) = record_click(&pool, delivery_id, &parameters.url).await {
+        tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to record a click.");
+    }
+
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, parameters.url.as_str()))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish())
+}
+
+async fn record_open(pool: &PgPool, delivery_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"update deliveries
+           set opened_at = coalesce(opened_at, now()), n_opens = n_opens + 1
+           where delivery_id = $1
+        "#,
+        delivery_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the open of a delivery.")?;
+
+    Ok(())
+}
+
+async fn record_click(pool: &PgPool, delivery_id: Uuid, url: &str) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"insert into delivery_clicks (delivery_id, url)
+           select delivery_id, $2 from deliveries where delivery_id = $1
+        "#,
+        delivery_id,
+        url
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the click.")?;
+    // a reader who clicked has opened the email, even with images blocked
+    sqlx::query!(
+        r#"update deliveries
+           set opened_at = coalesce(opened_at, now())
+           where delivery_id = $1
+        "#,
+        delivery_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the open of a delivery.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a click.")?;
+
+    Ok(())
+}
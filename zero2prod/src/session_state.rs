@@ -0,0 +1,54 @@
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+/// A `Session` with typed accessors, so the keys are spelled out only once.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+    // a user who gave their password but still owes a second factor
+    const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+
+    pub fn renew(&self) {
+        self.0.renew();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    /// Whoever was logged in before is logged out.
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.remove(Self::USER_ID_KEY);
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_user_id(&self) {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+    }
+
+    pub fn log_out(self) {
+        self.0.purge()
+    }
+}
+
+impl FromRequest for TypedSession {
+    // same error as `Session`'s extractor
+    type Error = <Session as FromRequest>::Error;
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
@@ -0,0 +1,160 @@
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+type SessionState = HashMap<String, String>;
+
+/// Session storage backed by the `sessions` table, so we don't need to run
+/// Redis next to Postgres.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"select state from sessions
+               where session_key = $1 and expires_at > now()
+            "#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load session state.")
+        .map_err(LoadError::Other)?;
+
+        match row {
+            None => Ok(None),
+            Some(row) => serde_json::from_str(&row.state)
+                .context("Failed to deserialize session state.")
+                .map(Some)
+                .map_err(LoadError::Deserialization),
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize session state.")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+
+        // piggyback on new sessions to clean up the expired ones
+        sqlx::query!(r#"delete from sessions where expires_at <= now()"#)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired sessions.")
+            .map_err(SaveError::Other)?;
+
+        sqlx::query!(
+            r#"insert into sessions (session_key, state, expires_at)
+               values ($1, $2, $3)
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save session state.")
+        .map_err(SaveError::Other)?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize session state.")
+            .map_err(UpdateError::Serialization)?;
+
+        let n_updated_rows = sqlx::query!(
+            r#"update sessions
+               set state = $2, expires_at = $3
+               where session_key = $1
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session state.")
+        .map_err(UpdateError::Other)?
+        .rows_affected();
+
+        if n_updated_rows > 0 {
+            return Ok(session_key);
+        }
+
+        // the session expired in the meantime, start a new one with the same state
+        self.save(session_state, ttl).await.map_err(|e| match e {
+            SaveError::Serialization(e) => UpdateError::Serialization(e),
+            SaveError::Other(e) => UpdateError::Other(e),
+        })
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"update sessions set expires_at = $2 where session_key = $1"#,
+            session_key.as_ref(),
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session expiry.")?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"delete from sessions where session_key = $1"#,
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete session.")?;
+
+        Ok(())
+    }
+}
+
+fn expires_at(ttl: &Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+fn generate_session_key() -> SessionKey {
+    let mut rng = thread_rng();
+    let value: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect();
+
+    // 64 characters are well within the size limit of a session key
+    value.try_into().expect("Generated an invalid session key")
+}
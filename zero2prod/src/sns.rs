@@ -0,0 +1,334 @@
+use crate::helpers::error_chain_fmt;
+use anyhow::Context;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use x509_cert::der::{DecodePem, Encode};
+use x509_cert::Certificate;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnsMessageType {
+    Notification,
+    SubscriptionConfirmation,
+    UnsubscribeConfirmation,
+}
+
+/// A message as posted by SNS to an HTTP(S) subscription.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnsMessage {
+    #[serde(rename = "Type")]
+    pub message_type: SnsMessageType,
+    pub message_id: String,
+    pub topic_arn: String,
+    pub subject: Option<String>,
+    pub message: String,
+    pub timestamp: String,
+    pub signature_version: String,
+    pub signature: String,
+    #[serde(rename = "SigningCertURL")]
+    pub signing_cert_url: String,
+    // only in (un)subscription confirmations
+    #[serde(rename = "SubscribeURL")]
+    pub subscribe_url: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SnsError {
+    #[error("Messages from topic {0} are not accepted.")]
+    UnknownTopic(String),
+
+    #[error("Signing certificates are not downloaded from {0}.")]
+    UntrustedCertificate(String),
+
+    #[error("Signature version {0} is not supported.")]
+    UnsupportedSignatureVersion(String),
+
+    #[error("The message signature is invalid.")]
+    InvalidSignature,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for SnsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Verifies the messages SNS posts to us and confirms the subscription of our
+/// endpoint to the topic.
+///
+/// Signing certificates are downloaded once per url and kept in memory.
+#[derive(Clone)]
+pub struct SnsClient {
+    http_client: reqwest::Client,
+    topic_arn: String,
+    // `None` trusts AWS's `https://sns.<region>.amazonaws.com/` only
+    signing_cert_url_prefix: Option<String>,
+    certificates: Arc<Mutex<HashMap<String, RsaPublicKey>>>,
+}
+
+impl SnsClient {
+    pub fn new(
+        topic_arn: String,
+        signing_cert_url_prefix: Option<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Failed to build the SNS http client"),
+            topic_arn,
+            signing_cert_url_prefix,
+            certificates: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    #[tracing::instrument(name = "Verify SNS message", skip_all, fields(message_id = %message.message_id))]
+    pub async fn verify(&self, message: &SnsMessage) -> Result<(), SnsError> {
+        if message.topic_arn != self.topic_arn {
+            return Err(SnsError::UnknownTopic(message.topic_arn.clone()));
+        }
+        let public_key = self.public_key(&message.signing_cert_url).await?;
+
+        let signature =
+            base64::decode(&message.signature).map_err(|_| SnsError::InvalidSignature)?;
+        let string_to_sign = string_to_sign(message);
+        let verified = match message.signature_version.as_str() {
+            "1" => public_key.verify(
+                Pkcs1v15Sign::new::<Sha1>(),
+                &Sha1::digest(string_to_sign.as_bytes()),
+                &signature,
+            ),
+            "2" => public_key.verify(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(string_to_sign.as_bytes()),
+                &signature,
+            ),
+            version => return Err(SnsError::UnsupportedSignatureVersion(version.to_owned())),
+        };
+
+        verified.map_err(|_| SnsError::InvalidSignature)
+    }
+
+    /// Visit the `SubscribeURL` of a verified subscription confirmation.
+    #[tracing::instrument(name = "Confirm SNS subscription", skip_all, fields(topic_arn = %message.topic_arn))]
+    pub async fn confirm_subscription(&self, message: &SnsMessage) -> Result<(), anyhow::Error> {
+        let subscribe_url = message
+            .subscribe_url
+            .as_deref()
+            .context("The subscription confirmation has no `SubscribeURL`.")?;
+
+        self.http_client
+            .get(subscribe_url)
+            .send()
+            .await
+            .context("Failed to visit the SNS subscribe url.")?
+            .error_for_status()
+            .context("SNS refused to confirm the subscription.")?;
+
+        Ok(())
+    }
+
+    async fn public_key(&self, signing_cert_url: &str) -> Result<RsaPublicKey, SnsError> {
+        if !self.is_trusted(signing_cert_url) {
+            return Err(SnsError::UntrustedCertificate(signing_cert_url.to_owned()));
+        }
+        if let Some(public_key) = self.certificates.lock().unwrap().get(signing_cert_url) {
+            return Ok(public_key.clone());
+        }
+
+        let pem = self
+            .http_client
+            .get(signing_cert_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to download the SNS signing certificate.")?
+            .text()
+            .await
+            .context("Failed to read the SNS signing certificate.")?;
+        let public_key = parse_public_key(&pem)?;
+
+        self.certificates
+            .lock()
+            .unwrap()
+            .insert(signing_cert_url.to_owned(), public_key.clone());
+
+        Ok(public_key)
+    }
+
+    // anyone can sign a message with their own certificate, what matters is
+    // where the certificate comes from
+    fn is_trusted(&self, signing_cert_url: &str) -> bool {
+        match &self.signing_cert_url_prefix {
+            Some(prefix) => signing_cert_url.starts_with(prefix.as_str()),
+            None => is_aws_signing_cert_url(signing_cert_url),
+        }
+    }
+}
+
+fn is_aws_signing_cert_url(signing_cert_url: &str) -> bool {
+    let url = match reqwest::Url::parse(signing_cert_url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    let region = url
+        .host_str()
+        .and_then(|host| host.strip_prefix("sns."))
+        .and_then(|host| {
+            host.strip_suffix(".amazonaws.com")
+                .or_else(|| host.strip_suffix(".amazonaws.com.cn"))
+        });
+
+    url.scheme() == "https"
+        && url.port().is_none()
+        && url.path().ends_with(".pem")
+        && region.is_some_and(|region| {
+            !region.is_empty()
+                && region
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+fn parse_public_key(pem: &str) -> Result<RsaPublicKey, anyhow::Error> {
+    let certificate =
+        Certificate::from_pem(pem.as_bytes()).context("The signing certificate is invalid.")?;
+    let public_key = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .context("Failed to encode the signing certificate public key.")?;
+
+    RsaPublicKey::from_public_key_der(&public_key)
+        .context("The signing certificate does not hold an RSA public key.")
+}
+
+/// The fields covered by the signature, as `name\nvalue\n` pairs in byte order.
+fn string_to_sign(message: &SnsMessage) -> String {
+    let fields: Vec<(&str, Option<&str>)> = match message.message_type {
+        SnsMessageType::Notification => vec![
+            ("Message", Some(message.message.as_str())),
+            ("MessageId", Some(message.message_id.as_str())),
+            ("Subject", message.subject.as_deref()),
+            ("Timestamp", Some(message.timestamp.as_str())),
+            ("TopicArn", Some(message.topic_arn.as_str())),
+            ("Type", Some(message.message_type.as_str())),
+        ],
+        SnsMessageType::SubscriptionConfirmation | SnsMessageType::UnsubscribeConfirmation => {
+            vec![
+                ("Message", Some(message.message.as_str())),
+                ("MessageId", Some(message.message_id.as_str())),
+                ("SubscribeURL", message.subscribe_url.as_deref()),
+                ("Timestamp", Some(message.timestamp.as_str())),
+                ("Token", message.token.as_deref()),
+                ("TopicArn", Some(message.topic_arn.as_str())),
+                ("Type", Some(message.message_type.as_str())),
+            ]
+        }
+    };
+
+    fields
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| format!("{}\n{}\n", name, value)))
+        .collect()
+}
+
+impl SnsMessageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnsMessageType::Notification => "Notification",
+            SnsMessageType::SubscriptionConfirmation => "SubscriptionConfirmation",
+            SnsMessageType::UnsubscribeConfirmation => "UnsubscribeConfirmation",
+        }
+    }
+}
+
+impl Display for SnsMessageType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(subject: Option<&str>) -> SnsMessage {
+        SnsMessage {
+            message_type: SnsMessageType::Notification,
+            message_id: "22b80b92-fdea-4c2c-8f9d-bdfb0c7bf324".into(),
+            topic_arn: "arn:aws:sns:us-west-2:123456789012:MyTopic".into(),
+            subject: subject.map(Into::into),
+            message: "Hello world!".into(),
+            timestamp: "2012-05-02T00:54:06.655Z".into(),
+            signature_version: "1".into(),
+            signature: "".into(),
+            signing_cert_url: "".into(),
+            subscribe_url: None,
+            token: None,
+        }
+    }
+
+    #[test]
+    fn the_subject_is_only_signed_when_present() {
+        assert_eq!(
+            string_to_sign(&notification(Some("My First Message"))),
+            "Message\nHello world!\n\
+             MessageId\n22b80b92-fdea-4c2c-8f9d-bdfb0c7bf324\n\
+             Subject\nMy First Message\n\
+             Timestamp\n2012-05-02T00:54:06.655Z\n\
+             TopicArn\narn:aws:sns:us-west-2:123456789012:MyTopic\n\
+             Type\nNotification\n"
+        );
+        assert!(!string_to_sign(&notification(None)).contains("Subject"));
+    }
+
+    #[test]
+    fn subscription_confirmations_sign_the_subscribe_url_and_token() {
+        let message = SnsMessage {
+            message_type: SnsMessageType::SubscriptionConfirmation,
+            subscribe_url: Some(
+                "https://sns.us-west-2.amazonaws.com/?Action=ConfirmSubscription".into(),
+            ),
+            token: Some("2336412f37".into()),
+            ..notification(None)
+        };
+
+        let s = string_to_sign(&message);
+
+        assert!(s.contains(
+            "SubscribeURL\nhttps://sns.us-west-2.amazonaws.com/?Action=ConfirmSubscription\n\
+             Timestamp\n2012-05-02T00:54:06.655Z\n\
+             Token\n2336412f37\n"
+        ));
+        assert!(s.ends_with("Type\nSubscriptionConfirmation\n"));
+    }
+
+    #[test]
+    fn only_aws_signing_certificates_are_trusted() {
+        assert!(is_aws_signing_cert_url(
+            "https://sns.us-west-2.amazonaws.com/SimpleNotificationService-f3ecfb7224c7233fe7bb5f59f96de52f.pem"
+        ));
+        for url in [
+            "http://sns.us-west-2.amazonaws.com/cert.pem",
+            "https://sns.us-west-2.amazonaws.com.evil.com/cert.pem",
+            "https://evil.com/sns.us-west-2.amazonaws.com/cert.pem",
+            "https://sns.amazonaws.com/cert.pem",
+            "https://sns.us-west-2.amazonaws.com:8443/cert.pem",
+            "https://sns.us-west-2.amazonaws.com/cert.txt",
+            "not a url",
+        ] {
+            assert!(!is_aws_signing_cert_url(url), "{} is trusted", url);
+        }
+    }
+}
@@ -1,14 +1,46 @@
+use crate::authentication::reject_anonymous_users;
+use crate::bot_check::{reject_automated_subscriptions, FormTokens};
+use crate::clock::Clock;
 use crate::configuration::{DatabaseSettings, Settings};
 use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, home, publish_newsletter, subscribe};
+use crate::email_outbox::run_outbox_worker_until_stopped;
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::{run_worker_until_stopped, DeliveryContext};
+use crate::issue_scheduler::run_scheduler_until_stopped;
+use crate::rate_limit::{rate_limit_subscriptions, SubscribeRateLimiter};
+use crate::request_origin::ClientIpHeader;
+use crate::routes::{
+    accept_invitation, accept_invitation_form, admin_dashboard, cancel_newsletter, change_password,
+    change_password_form, confirm, confirm_subscriber, confirm_two_factor, consent_history,
+    create_api_token, create_list, deactivate_user, delete_subscriber, disable_two_factor,
+    enroll_two_factor, erase_subscriber_data, erase_subscriber_data_form, export_subscriber_data,
+    export_subscriber_data_form, export_subscribers, get_subscriber, handle_ses_notification,
+    health_check, home, import_subscribers, invite_user, list_api_tokens, list_lists,
+    list_scheduled_newsletters, list_subscribers, list_users, log_out, login, login_form,
+    login_two_factor, login_two_factor_form, newsletter_stats, preview_template,
+    publish_newsletter, publish_newsletter_form, publish_newsletter_issue,
+    regenerate_recovery_codes, request_subscriber_data, reschedule_newsletter, resend_confirmation,
+    revoke_api_token, set_subscriber_tags, set_user_lists, subscribe, subscribe_form, track_click,
+    track_open, two_factor_status, unsubscribe, unsubscribe_form, unsubscribe_subscriber,
+    update_list, MAX_IMPORT_SIZE,
+};
+use crate::session_store::PgSessionStore;
+use crate::sns::SnsClient;
+use crate::tracking::TrackingLinks;
+use crate::unsubscribe::UnsubscribeLinks;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
-use actix_web::web::{get, post, Data};
+use actix_web::web::{delete, get, patch, post, put, Data, PayloadConfig};
 use actix_web::{web, App, HttpServer};
-use aws_config::timeout::TimeoutConfig;
-use aws_types::region::Region;
+use actix_web_flash_messages::storage::CookieMessageStore;
+use actix_web_flash_messages::FlashMessagesFramework;
+use actix_web_lab::middleware::from_fn;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
 
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
@@ -20,12 +52,27 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 pub struct Application {
     pub port: u16,
     pub server: Server,
+    worker: JoinHandle<Result<(), anyhow::Error>>,
+    outbox_worker: JoinHandle<Result<(), anyhow::Error>>,
+    scheduler: JoinHandle<Result<(), anyhow::Error>>,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
+
+pub struct HmacSecret(pub Secret<String>);
+
 impl Application {
     pub async fn build(configuration: &Settings) -> Result<Self, std::io::Error> {
+        Self::build_with_clock(configuration, Clock::System).await
+    }
+
+    /// Tests use a fixed `clock`, to know which codes the application expects.
+    pub async fn build_with_clock(
+        configuration: &Settings,
+        clock: Clock,
+    ) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
 
         // setup tcp listener.
@@ -37,34 +84,69 @@ impl Application {
             .unwrap_or_else(|_| panic!("Failed to bind port: {}", configuration.application.port));
 
         // setup email client.
-        let timeout = configuration.email_client.timeout();
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-
-        let aws_conf = aws_config::from_env()
-            .region(Region::new(configuration.email_client.region.to_owned()))
-            .timeout_config(
-                TimeoutConfig::builder()
-                    .operation_timeout(configuration.email_client.timeout())
-                    .build(),
-            )
-            .endpoint_url(configuration.email_client.endpoint_url.to_owned())
-            .load()
-            .await;
+        let email_client = configuration.email_client.client().await;
 
-        let email_client = EmailClient::new(sender_email, timeout, aws_conf);
+        let templates = EmailTemplates::new(
+            connection_pool.clone(),
+            &configuration.application.email_templates_directory,
+        );
+
+        let unsubscribe_links = UnsubscribeLinks::new(
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+        );
+        let tracking_links = TrackingLinks::new(
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+        );
+
+        // deliver queued newsletter issues in the background.
+        let worker = tokio::spawn(run_worker_until_stopped(DeliveryContext {
+            pool: connection_pool.clone(),
+            email_client: email_client.clone(),
+            templates: templates.clone(),
+            unsubscribe_links: unsubscribe_links.clone(),
+            tracking_links: tracking_links.clone(),
+        }));
+        // send the emails queued by requests, e.g. confirmation links.
+        let outbox_worker = tokio::spawn(run_outbox_worker_until_stopped(
+            connection_pool.clone(),
+            email_client.clone(),
+        ));
+        // publish scheduled issues once they are due.
+        let scheduler = tokio::spawn(run_scheduler_until_stopped(connection_pool.clone()));
 
         let port = listener.local_addr().unwrap().port();
+        let form_tokens = FormTokens::new(
+            configuration.application.hmac_secret.clone(),
+            configuration.subscriptions.min_time_to_submit(),
+        );
         let server = run(
             listener,
-            connection_pool,
-            email_client,
-            &configuration.application.base_url,
+            AppState {
+                connection_pool,
+                email_client,
+                templates,
+                base_url: configuration.application.base_url.clone(),
+                hmac_secret: configuration.application.hmac_secret.clone(),
+                subscription_token_ttl: configuration.application.subscription_token_ttl(),
+                unsubscribe_links,
+                tracking_links,
+                sns_client: configuration.ses_webhook.client(),
+                rate_limiter: configuration.subscriptions.rate_limiter(),
+                form_tokens,
+                client_ip_header: configuration.subscriptions.client_ip_header(),
+                clock,
+            },
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            worker,
+            outbox_worker,
+            scheduler,
+        })
     }
 
     pub fn port(&self) -> u16 {
@@ -72,31 +154,202 @@ impl Application {
     }
 
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let outcome = self.server.await;
+        // the background tasks would otherwise outlive the server they were
+        // started with
+        self.worker.abort();
+        self.outbox_worker.abort();
+        self.scheduler.abort();
+        outcome
     }
 }
 
-pub fn run(
-    listener: TcpListener,
-    connection_pool: PgPool,
-    email_client: EmailClient,
-    base_url: &String,
-) -> Result<Server, std::io::Error> {
+/// What the request handlers share, registered as application data by `run`.
+pub struct AppState {
+    pub connection_pool: PgPool,
+    pub email_client: EmailClient,
+    pub templates: EmailTemplates,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub subscription_token_ttl: chrono::Duration,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub tracking_links: TrackingLinks,
+    pub sns_client: SnsClient,
+    pub rate_limiter: SubscribeRateLimiter,
+    pub form_tokens: FormTokens,
+    pub client_ip_header: ClientIpHeader,
+    pub clock: Clock,
+}
+
+pub fn run(listener: TcpListener, state: AppState) -> Result<Server, std::io::Error> {
+    let AppState {
+        connection_pool,
+        email_client,
+        templates,
+        base_url,
+        hmac_secret,
+        subscription_token_ttl,
+        unsubscribe_links,
+        tracking_links,
+        sns_client,
+        rate_limiter,
+        form_tokens,
+        client_ip_header,
+        clock,
+    } = state;
+    let session_store = PgSessionStore::new(connection_pool.clone());
     let connection_pool = Data::new(connection_pool);
     let email_client = Data::new(email_client);
-    let base_url = Data::new(ApplicationBaseUrl(base_url.to_owned()));
+    let templates = Data::new(templates);
+    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let subscription_token_ttl = Data::new(SubscriptionTokenTtl(subscription_token_ttl));
+    let unsubscribe_links = Data::new(unsubscribe_links);
+    let tracking_links = Data::new(tracking_links);
+    let sns_client = Data::new(sns_client);
+    // shared by all workers, or every worker would have its own buckets
+    let rate_limiter = Data::new(rate_limiter);
+    let form_tokens = Data::new(form_tokens);
+    let client_ip_header = Data::new(client_ip_header);
+    let clock = Data::new(clock);
+
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let hmac_secret = Data::new(HmacSecret(hmac_secret));
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
             .wrap(TracingLogger::default())
             .route("/", get().to(home))
             .route("/health_check", get().to(health_check))
-            .route("/subscriptions", post().to(subscribe))
+            .route("/login", get().to(login_form))
+            .route("/login", post().to(login))
+            .route("/login/two_factor", get().to(login_two_factor_form))
+            .route("/login/two_factor", post().to(login_two_factor))
+            .service(
+                web::resource("/subscriptions")
+                    .route(get().to(subscribe_form))
+                    // the bot check runs first: automated posts should not
+                    // use up the rate limit of the address they name
+                    .route(
+                        post()
+                            .to(subscribe)
+                            .wrap(from_fn(rate_limit_subscriptions))
+                            .wrap(from_fn(reject_automated_subscriptions)),
+                    ),
+            )
             .route("/subscriptions/confirm", get().to(confirm))
+            .service(
+                web::resource("/subscriptions/resend_confirmation")
+                    .wrap(from_fn(rate_limit_subscriptions))
+                    .route(post().to(resend_confirmation)),
+            )
+            .route("/subscriptions/unsubscribe", get().to(unsubscribe_form))
+            .route("/subscriptions/unsubscribe", post().to(unsubscribe))
+            .service(
+                web::resource("/subscriptions/data_request")
+                    .wrap(from_fn(rate_limit_subscriptions))
+                    .route(post().to(request_subscriber_data)),
+            )
+            .route("/subscriptions/data", get().to(export_subscriber_data_form))
+            .route("/subscriptions/data", post().to(export_subscriber_data))
+            .route("/subscriptions/erase", get().to(erase_subscriber_data_form))
+            .route("/subscriptions/erase", post().to(erase_subscriber_data))
+            .route("/invitations/accept", get().to(accept_invitation_form))
+            .route("/invitations/accept", post().to(accept_invitation))
             .route("/newsletters", post().to(publish_newsletter))
+            .route("/webhooks/ses", post().to(handle_ses_notification))
+            .route("/tracking/open/{delivery_id}", get().to(track_open))
+            .route("/tracking/click/{delivery_id}", get().to(track_click))
+            .route(
+                "/newsletters/scheduled",
+                get().to(list_scheduled_newsletters),
+            )
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}",
+                patch().to(reschedule_newsletter),
+            )
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}",
+                delete().to(cancel_newsletter),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/api_tokens", get().to(list_api_tokens))
+                    .route("/api_tokens", post().to(create_api_token))
+                    .route("/api_tokens/{api_token_id}", delete().to(revoke_api_token))
+                    .route("/dashboard", get().to(admin_dashboard))
+                    .route("/lists", get().to(list_lists))
+                    .route("/lists", post().to(create_list))
+                    .route("/lists/{slug}", patch().to(update_list))
+                    .route("/newsletters", get().to(publish_newsletter_form))
+                    .route("/newsletters", post().to(publish_newsletter_issue))
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/stats",
+                        get().to(newsletter_stats),
+                    )
+                    .route("/password", get().to(change_password_form))
+                    .route("/password", post().to(change_password))
+                    .route("/subscribers", get().to(list_subscribers))
+                    // literal paths, before they are taken for a subscriber id
+                    .service(
+                        web::resource("/subscribers/import")
+                            .app_data(PayloadConfig::new(MAX_IMPORT_SIZE))
+                            .route(post().to(import_subscribers)),
+                    )
+                    .route("/subscribers/export", get().to(export_subscribers))
+                    .route("/subscribers/consent", get().to(consent_history))
+                    .route("/subscribers/{subscriber_id}", get().to(get_subscriber))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        delete().to(delete_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        post().to(confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        post().to(unsubscribe_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/tags",
+                        put().to(set_subscriber_tags),
+                    )
+                    .route("/templates/{name}/preview", get().to(preview_template))
+                    .route("/two_factor", get().to(two_factor_status))
+                    .route("/two_factor", post().to(enroll_two_factor))
+                    .route("/two_factor", delete().to(disable_two_factor))
+                    .route("/two_factor/confirm", post().to(confirm_two_factor))
+                    .route(
+                        "/two_factor/recovery_codes",
+                        post().to(regenerate_recovery_codes),
+                    )
+                    .route("/users", get().to(list_users))
+                    .route("/users/invitations", post().to(invite_user))
+                    .route("/users/{user_id}/lists", put().to(set_user_lists))
+                    .route("/users/{user_id}/deactivate", post().to(deactivate_user))
+                    .route("/logout", post().to(log_out)),
+            )
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
+            .app_data(templates.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(unsubscribe_links.clone())
+            .app_data(tracking_links.clone())
+            .app_data(sns_client.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(form_tokens.clone())
+            .app_data(client_ip_header.clone())
+            .app_data(clock.clone())
     })
     .listen(listener)?
     .run();
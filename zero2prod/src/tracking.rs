@@ -0,0 +1,230 @@
+use crate::html_usage::HtmlUsage;
+use crate::keyed_mac::{self, MacPurpose};
+use secrecy::Secret;
+use uuid::Uuid;
+
+/// Which reader activity an issue tracks, nothing unless the publisher opts in.
+#[derive(serde::Deserialize, Default, Clone, Copy, Debug)]
+pub struct Tracking {
+    #[serde(default)]
+    pub opens: bool,
+    #[serde(default)]
+    pub clicks: bool,
+}
+
+/// Builds the open pixel and click links of a delivery, and verifies the
+/// latter.
+///
+/// Click links carry an HMAC of the delivery id and the target url: otherwise
+/// the redirect endpoint would send readers anywhere it is asked to.
+#[derive(Clone)]
+pub struct TrackingLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+impl TrackingLinks {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn open_pixel_for(&self, delivery_id: Uuid) -> String {
+        format!("{}/tracking/open/{}", self.base_url, delivery_id)
+    }
+
+    pub fn click_link_for(&self, delivery_id: Uuid, url: &str) -> String {
+        reqwest::Url::parse_with_params(
+            &format!("{}/tracking/click/{}", self.base_url, delivery_id),
+            &[("url", url), ("token", &self.token_for(delivery_id, url))],
+        )
+        .expect("The application base url is invalid")
+        .to_string()
+    }
+
+    pub fn token_for(&self, delivery_id: Uuid, url: &str) -> String {
+        keyed_mac::sign(
+            &self.hmac_secret,
+            MacPurpose::Click,
+            &[delivery_id.as_bytes(), url.as_bytes()],
+        )
+    }
+
+    pub fn verify(&self, delivery_id: Uuid, url: &str, token: &str) -> bool {
+        keyed_mac::verify(
+            &self.hmac_secret,
+            MacPurpose::Click,
+            &[delivery_id.as_bytes(), url.as_bytes()],
+            token,
+        )
+    }
+
+    /// Add the tracking an issue opted into to the html body of a delivery.
+    ///
+    /// Only http(s) links are rewritten, `mailto:` and anchors keep working
+    /// as they are.
+    pub fn track(&self, html: &str, delivery_id: Uuid, tracking: Tracking) -> String {
+        let mut html = if tracking.clicks {
+            self.track_clicks(html, delivery_id)
+        } else {
+            html.to_owned()
+        };
+        if tracking.opens {
+            html.push_str(&format!(
+                r#"<img src="{}" width="1" height="1" alt="">"#,
+                htmlescape::encode_attribute(&self.open_pixel_for(delivery_id))
+            ));
+        }
+
+        html
+    }
+
+    /// Point the `href` of every `<a>` tag at the click endpoint.
+    ///
+    /// ammonia parses the html and writes it back with nothing removed, as
+    /// `HtmlUsage` lets it keep whatever the issue uses.
+    fn track_clicks(&self, html: &str, delivery_id: Uuid) -> String {
+        let usage = HtmlUsage::of(html);
+        let links = self.clone();
+        let mut builder = ammonia::Builder::empty();
+        usage
+            .keep_all(&mut builder)
+            .link_rel(None)
+            .strip_comments(false)
+            .attribute_filter(
+                move |element, attribute, value| match (element, attribute) {
+                    ("a", "href") if is_http_url(value) => {
+                        Some(links.click_link_for(delivery_id, value).into())
+                    }
+                    _ => Some(value.into()),
+                },
+            )
+            .clean(html)
+            .to_string()
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    fn links() -> TrackingLinks {
+        TrackingLinks::new(
+            "http://127.0.0.1".into(),
+            Secret::new("a-secret-used-only-in-tests".into()),
+        )
+    }
+
+    const CLICKS: Tracking = Tracking {
+        opens: false,
+        clicks: true,
+    };
+
+    // as it appears in an attribute
+    fn escaped(url: &str) -> String {
+        url.replace('&', "&amp;")
+    }
+
+    #[test]
+    fn a_token_is_only_valid_for_its_delivery_and_url() {
+        let links = links();
+        let delivery_id = Uuid::new_v4();
+        let token = links.token_for(delivery_id, "https://example.com");
+
+        assert!(links.verify(delivery_id, "https://example.com", &token));
+        assert!(!links.verify(delivery_id, "https://evil.com", &token));
+        assert!(!links.verify(Uuid::new_v4(), "https://example.com", &token));
+        assert!(!links.verify(delivery_id, "https://example.com", "not-hex"));
+    }
+
+    #[test]
+    fn a_mac_without_the_click_prefix_is_rejected() {
+        let links = links();
+        let delivery_id = Uuid::new_v4();
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"a-secret-used-only-in-tests").unwrap();
+        mac.update(delivery_id.as_bytes());
+        mac.update(b"https://example.com");
+        let token = hex::encode(mac.finalize().into_bytes());
+
+        assert!(!links.verify(delivery_id, "https://example.com", &token));
+    }
+
+    #[test]
+    fn hrefs_are_rewritten_whatever_their_quoting() {
+        let links = links();
+        let delivery_id = Uuid::new_v4();
+        let html = r#"<p><a href="https://a.com">a</a> <A class=x HREF='https://b.com'>b</A> <a title="1 > 0" href=https://c.com>c</a></p>"#;
+
+        let tracked = links.track(html, delivery_id, CLICKS);
+
+        for url in ["https://a.com", "https://b.com", "https://c.com"] {
+            let click_link = escaped(&links.click_link_for(delivery_id, url));
+            assert!(tracked.contains(&click_link), "{} was not rewritten", url);
+        }
+        assert!(tracked.contains(r#"class="x""#));
+        assert!(tracked.contains(r#"title="1 > 0""#));
+    }
+
+    #[test]
+    fn urls_are_unescaped_before_they_are_signed() {
+        let links = links();
+        let delivery_id = Uuid::new_v4();
+
+        let tracked = links.track(
+            r#"<a href="https://a.com/?x=1&amp;y=2">a</a>"#,
+            delivery_id,
+            CLICKS,
+        );
+
+        let click_link = links.click_link_for(delivery_id, "https://a.com/?x=1&y=2");
+        assert!(tracked.contains(&escaped(&click_link)));
+    }
+
+    #[test]
+    fn hrefs_in_comments_and_attribute_values_are_left_alone() {
+        let links = links();
+        let html = r#"<!-- <a href="https://a.com"> --><abbr title="<a href='https://b.com'>">y</abbr><img src="https://c.com"><a name="top">z</a>"#;
+
+        assert_eq!(links.track(html, Uuid::new_v4(), CLICKS), html);
+    }
+
+    #[test]
+    fn the_rest_of_the_html_is_kept() {
+        let links = links();
+        let html = r#"<table style="width: 100%"><tbody><tr><td><a href="tel:+441234">call</a></td></tr></tbody></table>"#;
+
+        assert_eq!(links.track(html, Uuid::new_v4(), CLICKS), html);
+    }
+
+    #[test]
+    fn only_the_tracking_an_issue_opted_into_is_added() {
+        let links = links();
+        let delivery_id = Uuid::new_v4();
+        let html = r#"<a href="https://a.com">a</a> <a href="mailto:a@a.com">mail</a>"#;
+
+        assert_eq!(links.track(html, delivery_id, Tracking::default()), html);
+
+        let tracked = links.track(
+            html,
+            delivery_id,
+            Tracking {
+                opens: true,
+                clicks: true,
+            },
+        );
+        assert!(!tracked.contains(r#"href="https://a.com""#));
+        assert!(tracked.contains(r#"href="mailto:a@a.com""#));
+        assert!(htmlescape::decode_html(&tracked)
+            .unwrap()
+            .contains(&links.open_pixel_for(delivery_id)));
+    }
+}
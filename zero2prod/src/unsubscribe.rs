@@ -0,0 +1,103 @@
+use crate::keyed_mac::{self, MacPurpose};
+use secrecy::Secret;
+use uuid::Uuid;
+
+/// Builds and verifies per-subscriber unsubscribe links.
+///
+/// The token is an HMAC of the subscriber id, so links cannot be forged for
+/// other subscribers and nothing has to be stored to verify them.
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn link_for(&self, subscriber_id: Uuid) -> String {
+        format!(
+            "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+            self.base_url,
+            subscriber_id,
+            self.token_for(subscriber_id)
+        )
+    }
+
+    pub fn token_for(&self, subscriber_id: Uuid) -> String {
+        keyed_mac::sign(
+            &self.hmac_secret,
+            MacPurpose::Unsubscribe,
+            &[subscriber_id.as_bytes()],
+        )
+    }
+
+    pub fn verify(&self, subscriber_id: Uuid, token: &str) -> bool {
+        keyed_mac::verify(
+            &self.hmac_secret,
+            MacPurpose::Unsubscribe,
+            &[subscriber_id.as_bytes()],
+            token,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    fn links() -> UnsubscribeLinks {
+        UnsubscribeLinks::new(
+            "http://127.0.0.1".into(),
+            Secret::new("a-secret-used-only-in-tests".into()),
+        )
+    }
+
+    #[test]
+    fn a_generated_token_is_accepted() {
+        let links = links();
+        let subscriber_id = Uuid::new_v4();
+
+        assert!(links.verify(subscriber_id, &links.token_for(subscriber_id)));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let links = links();
+
+        assert!(!links.verify(Uuid::new_v4(), &links.token_for(Uuid::new_v4())));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let other = UnsubscribeLinks::new(
+            "http://127.0.0.1".into(),
+            Secret::new("another-secret".into()),
+        );
+        let subscriber_id = Uuid::new_v4();
+
+        assert!(!links().verify(subscriber_id, &other.token_for(subscriber_id)));
+    }
+
+    #[test]
+    fn a_mac_of_the_bare_subscriber_id_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"a-secret-used-only-in-tests").unwrap();
+        mac.update(subscriber_id.as_bytes());
+        let token = hex::encode(mac.finalize().into_bytes());
+
+        assert!(!links().verify(subscriber_id, &token));
+    }
+
+    #[test]
+    fn a_malformed_token_is_rejected() {
+        assert!(!links().verify(Uuid::new_v4(), "not-hex"));
+    }
+}
@@ -0,0 +1,300 @@
+use crate::helpers::{assert_is_redirect_to, TestApp};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const SUBSCRIBE_FORM_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=scifi";
+
+async fn create_subscriber(app: &TestApp) -> Uuid {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+/// Store a subscriber directly, `minutes_ago` orders them.
+async fn insert_subscriber(app: &TestApp, email: &str, status: &str, minutes_ago: i64) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"insert into subscriptions (id, email, name, subscribed_at, status)
+           values ($1, $2, 'name', $3, $4)
+        "#,
+        id,
+        email,
+        Utc::now() - Duration::minutes(minutes_ago),
+        status
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+async fn get_tags(app: &TestApp) -> Vec<String> {
+    sqlx::query!("select tag from subscriber_tags order by tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.tag)
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_tag_a_subscriber() {
+    let app = TestApp::new().await;
+    let subscriber_id = create_subscriber(&app).await;
+
+    let response = app
+        .put_subscriber_tags(subscriber_id, serde_json::json!({ "tags": ["rust"] }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_eq!(get_tags(&app).await, vec!["scifi"]);
+}
+
+#[tokio::test]
+async fn admins_can_replace_the_tags_of_a_subscriber() {
+    let app = TestApp::new().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .put_subscriber_tags(
+            subscriber_id,
+            serde_json::json!({ "tags": ["Rust", "beta", "rust"] }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(get_tags(&app).await, vec!["beta", "rust"]);
+}
+
+#[tokio::test]
+async fn invalid_tags_are_rejected_with_a_400() {
+    let app = TestApp::new().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .put_subscriber_tags(
+            subscriber_id,
+            serde_json::json!({ "tags": ["rust", "not a tag"] }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(get_tags(&app).await, vec!["scifi"]);
+}
+
+#[tokio::test]
+async fn tagging_an_unknown_subscriber_returns_a_404() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .put_subscriber_tags(Uuid::new_v4(), serde_json::json!({ "tags": ["rust"] }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    let app = TestApp::new().await;
+    let subscriber_id = create_subscriber(&app).await;
+
+    let responses = vec![
+        app.get_admin_subscribers("").await,
+        app.get_admin_subscriber(subscriber_id).await,
+        app.post_admin_subscriber_action(subscriber_id, "confirm")
+            .await,
+        app.delete_admin_subscriber(subscriber_id).await,
+    ];
+
+    for response in responses {
+        assert_is_redirect_to(&response, "/login");
+    }
+    let status = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_newest_first_one_page_at_a_time() {
+    let app = TestApp::new().await;
+    insert_subscriber(&app, "oldest@example.com", "confirmed", 3).await;
+    insert_subscriber(&app, "middle@example.com", "confirmed", 2).await;
+    insert_subscriber(&app, "newest@example.com", "confirmed", 1).await;
+    app.test_user.login(&app).await;
+
+    let first: serde_json::Value = app
+        .get_admin_subscribers("limit=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        emails(&first),
+        vec!["newest@example.com", "middle@example.com"]
+    );
+
+    let next = first["next"].as_str().unwrap();
+    let second: serde_json::Value = app
+        .get_admin_subscribers(&format!("limit=2&after={}", next))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(emails(&second), vec!["oldest@example.com"]);
+    assert!(second["next"].is_null());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_email_prefix() {
+    let app = TestApp::new().await;
+    insert_subscriber(&app, "ursula@example.com", "confirmed", 4).await;
+    insert_subscriber(&app, "ursula@example.org", "unsubscribed", 3).await;
+    insert_subscriber(&app, "octavia@example.com", "confirmed", 2).await;
+    insert_subscriber(
+        &app,
+        "ursula_pending@example.com",
+        "pending_confirmation",
+        1,
+    )
+    .await;
+    app.test_user.login(&app).await;
+
+    let page: serde_json::Value = app
+        .get_admin_subscribers("status=confirmed&email_prefix=URSULA")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(emails(&page), vec!["ursula@example.com"]);
+}
+
+#[tokio::test]
+async fn an_invalid_cursor_is_rejected_with_a_400() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_admin_subscribers("after=garbage").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_viewed_with_their_tags() {
+    let app = TestApp::new().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_admin_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+    assert_eq!(subscriber["tags"], serde_json::json!(["scifi"]));
+
+    let response = app.get_admin_subscriber(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn admins_can_confirm_and_unsubscribe_a_subscriber() {
+    let app = TestApp::new().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "confirm")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["status"], "confirmed");
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["status"], "unsubscribed");
+    assert!(!subscriber["unsubscribed_at"].is_null());
+
+    let response = app
+        .post_admin_subscriber_action(Uuid::new_v4(), "confirm")
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn unsubscribing_a_bounced_subscriber_keeps_them_suppressed() {
+    let app = TestApp::new().await;
+    let subscriber_id = insert_subscriber(&app, "ursula_le_guin@gmail.com", "bounced", 1).await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["status"], "bounced");
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_their_tokens_and_tags() {
+    let app = TestApp::new().await;
+    let subscriber_id = create_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let response = app.delete_admin_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    let remaining = sqlx::query!(
+        r#"select
+               (select count(*) from subscriptions) as "subscriptions!",
+               (select count(*) from subscription_tokens) as "tokens!",
+               (select count(*) from subscriber_tags) as "tags!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.tags, 0);
+
+    let response = app.delete_admin_subscriber(subscriber_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,282 @@
+use crate::helpers::{assert_is_redirect_to, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const VALID_CSV: &str = "email,name,tags\n\
+                         ursula@example.com,Ursula Le Guin,\"scifi, fantasy\"\n\
+                         octavia@example.com,Octavia Butler,\n";
+
+async fn get_subscribers(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("select email, status from subscriptions order by email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_or_export_subscribers() {
+    let app = TestApp::new().await;
+
+    let response = app.post_subscribers_import("", VALID_CSV).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_subscribers_export("").await;
+    assert_is_redirect_to(&response, "/login");
+
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_dry_run_reports_without_importing() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscribers_import("dry_run=true", VALID_CSV).await;
+    app.dispatch_queued_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["imported"], 2);
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_rows_are_reported_and_nothing_is_imported() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\n\
+               ursula@example.com,Ursula Le Guin\n\
+               not-an-email,Octavia Butler\n\
+               ursula@example.com,Ursula again\n\
+               ted@example.com,\n";
+
+    let response = app.post_subscribers_import("", csv).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let report: serde_json::Value = response.json().await.unwrap();
+    let lines: Vec<u64> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(lines, vec![3, 4, 5]);
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_file_without_an_email_column_is_rejected_with_a_400() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_subscribers_import("", "name\nUrsula Le Guin\n")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_sent_a_confirmation_email_by_default() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscribers_import("", VALID_CSV).await;
+    app.dispatch_queued_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(
+        get_subscribers(&app).await,
+        vec![
+            ("octavia@example.com".into(), "pending_confirmation".into()),
+            ("ursula@example.com".into(), "pending_confirmation".into()),
+        ]
+    );
+    let tags = sqlx::query!("select tag from subscriber_tags order by tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let tags: Vec<&str> = tags.iter().map(|r| r.tag.as_str()).collect();
+    assert_eq!(tags, vec!["fantasy", "scifi"]);
+}
+
+#[tokio::test]
+async fn confirmation_emails_that_fail_are_retried_and_the_import_stands() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscribers_import("", VALID_CSV).await;
+    app.dispatch_queued_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(get_subscribers(&app).await.len(), 2);
+    let queued = sqlx::query!("select n_retries from email_outbox")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 2);
+    assert!(queued.iter().all(|email| email.n_retries == 1));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_imported_as_confirmed() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscribers_import("status=confirmed", VALID_CSV)
+        .await;
+    app.dispatch_queued_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        get_subscribers(&app).await,
+        vec![
+            ("octavia@example.com".into(), "confirmed".into()),
+            ("ursula@example.com".into(), "confirmed".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_skipped() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    app.post_subscribers_import(
+        "status=confirmed",
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = app
+        .post_subscribers_import("status=confirmed", VALID_CSV)
+        .await;
+    app.dispatch_queued_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["skipped"][0]["line"], 2);
+}
+
+#[tokio::test]
+async fn an_export_can_be_imported_back() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    app.post_subscribers_import("status=confirmed", VALID_CSV)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.get_subscribers_export("").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("email,name,status,subscribed_at,unsubscribed_at,tags")
+    );
+    assert!(csv.contains("ursula@example.com,Ursula Le Guin,confirmed,"));
+    assert!(csv.contains("\"fantasy,scifi\""));
+
+    sqlx::query!("delete from subscriptions")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app.post_subscribers_import("status=confirmed", &csv).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(get_subscribers(&app).await.len(), 2);
+}
+
+#[tokio::test]
+async fn the_export_can_be_filtered_by_status() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    app.post_subscribers_import(
+        "status=confirmed",
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let csv = app
+        .get_subscribers_export("status=unsubscribed")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert_eq!(csv.lines().count(), 1);
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_are_skipped_regardless_of_case() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    app.post_subscribers_import(
+        "status=confirmed",
+        "email,name\nursula@example.com,Ursula\n",
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    sqlx::query!("update subscriptions set status = 'bounced'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_subscribers_import(
+            "status=confirmed",
+            "email,name\nUrsula@Example.com,Ursula\n",
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["skipped"][0]["line"], 2);
+    assert_eq!(
+        get_subscribers(&app).await,
+        vec![("ursula@example.com".to_string(), "bounced".to_string())]
+    );
+}
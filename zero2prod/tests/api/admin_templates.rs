@@ -0,0 +1,95 @@
+use crate::helpers::{assert_is_redirect_to, TestApp};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_preview_a_template() {
+    let app = TestApp::new().await;
+
+    let response = app.get_template_preview("newsletter", "html").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn templates_are_previewed_against_a_sample_subscriber() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_template_preview("confirmation", "html").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Ursula Le Guin"));
+    assert!(html.contains("<a href="));
+}
+
+#[tokio::test]
+async fn the_text_alternative_can_be_previewed() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_template_preview("newsletter", "text").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("The body of a sample issue."));
+    assert!(!text.contains("<p>"));
+}
+
+#[tokio::test]
+async fn previewing_an_unknown_template_returns_a_404() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_template_preview("not_a_template", "html").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn templates_in_the_database_override_the_files() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        r#"insert into templates (name, html_body)
+           values ('newsletter', '<p>Dear {{ subscriber_name }}, {{ issue_title }}</p>')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let html = app
+        .get_template_preview("newsletter", "html")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(html, "<p>Dear Ursula Le Guin, Sample issue</p>");
+
+    // without a text template the alternative is generated from the html
+    let text = app
+        .get_template_preview("newsletter", "text")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(text.trim(), "Dear Ursula Le Guin, Sample issue");
+}
+
+#[tokio::test]
+async fn an_invalid_template_returns_a_400() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        r#"insert into templates (name, html_body)
+           values ('broken', '<p>{{ subscriber_name </p>')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_template_preview("broken", "html").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
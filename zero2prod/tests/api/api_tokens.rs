@@ -0,0 +1,246 @@
+use crate::helpers::{assert_is_redirect_to, TestApp, TestUser};
+
+fn issue() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Release notes",
+        "content": {
+            "text": "Release notes as plain text",
+            "html": "<p>Release notes as html</p>",
+        }
+    })
+}
+
+/// Create a token for the logged in user, returning its id and the token.
+async fn create_token(app: &TestApp, scopes: &[&str]) -> (String, String) {
+    let response = app
+        .post_api_token(&serde_json::json!({"name": "ci", "scopes": scopes}))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    (
+        body["api_token_id"].as_str().unwrap().to_owned(),
+        body["token"].as_str().unwrap().to_owned(),
+    )
+}
+
+#[tokio::test]
+async fn a_token_publishes_on_behalf_of_its_user() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (_, token) = create_token(&app, &["publish"]).await;
+
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn the_last_use_of_a_token_is_recorded() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (_, token) = create_token(&app, &["publish"]).await;
+    app.post_newsletters_with_token(&token, issue())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.get_api_tokens().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let listed = &body["api_tokens"][0];
+    assert_eq!(listed["name"], "ci");
+    assert!(listed["last_used_at"].is_string());
+    // only shown when created
+    assert!(listed.get("token").is_none());
+}
+
+#[tokio::test]
+async fn only_a_hash_of_the_token_is_stored() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (_, token) = create_token(&app, &["publish"]).await;
+
+    let stored = sqlx::query!("select token_hash from api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_ne!(stored.token_hash, token);
+    assert!(!stored.token_hash.contains(&token));
+}
+
+#[tokio::test]
+async fn a_revoked_token_is_rejected() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (api_token_id, token) = create_token(&app, &["publish"]).await;
+
+    let response = app.delete_api_token(&api_token_id).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_expired_token_is_rejected() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let expires_at = chrono::Utc::now() + chrono::Duration::days(1);
+    let response = app
+        .post_api_token(&serde_json::json!({
+            "name": "ci",
+            "scopes": ["publish"],
+            "expires_at": expires_at,
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let token = body["token"].as_str().unwrap();
+    sqlx::query!("update api_tokens set expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_newsletters_with_token(token, issue()).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_unknown_token_is_rejected() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_newsletters_with_token("z2p_not-a-token", issue())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_token_differing_by_one_character_is_rejected() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (_, token) = create_token(&app, &["publish"]).await;
+    let mut altered = token.clone();
+    let last = if altered.pop() == Some('a') { 'b' } else { 'a' };
+    altered.push(last);
+
+    let response = app.post_newsletters_with_token(&altered, issue()).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn a_token_is_only_accepted_for_its_scopes() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (_, token) = create_token(&app, &["schedule"]).await;
+
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/newsletters/scheduled", &app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_token_has_the_permissions_of_its_user() {
+    let app = TestApp::new().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+    let (_, token) = create_token(&app, &["publish"]).await;
+
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_tokens_of_a_deactivated_user_are_rejected() {
+    let app = TestApp::new().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+    let (_, token) = create_token(&app, &["publish"]).await;
+    sqlx::query!(
+        "update users set deactivated_at = now() where user_id = $1",
+        editor.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_tokens_are_not_created() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "ci", "scopes": ["everything"]}),
+            "an unknown scope",
+        ),
+        (serde_json::json!({"name": "ci", "scopes": []}), "no scope"),
+        (
+            serde_json::json!({"name": " ", "scopes": ["publish"]}),
+            "an empty name",
+        ),
+        (
+            serde_json::json!({
+                "name": "ci",
+                "scopes": ["publish"],
+                "expires_at": chrono::Utc::now() - chrono::Duration::days(1),
+            }),
+            "an expiry in the past",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_api_token(&body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the token had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_tokens_of_someone_else_cannot_be_revoked() {
+    let app = TestApp::new().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+    let (api_token_id, _) = create_token(&app, &["publish"]).await;
+    app.test_user.login(&app).await;
+
+    let response = app.delete_api_token(&api_token_id).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_tokens() {
+    let app = TestApp::new().await;
+
+    let response = app.get_api_tokens().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,166 @@
+use crate::helpers::{assert_is_redirect_to, TestApp};
+use uuid::Uuid;
+
+// long enough to satisfy the password policy
+fn new_password() -> String {
+    format!("{}-new", Uuid::new_v4())
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_change_password_form() {
+    let app = TestApp::new().await;
+
+    let response = app.get_change_password().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_password() {
+    let app = TestApp::new().await;
+    let new_password = new_password();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": new_password(),
+            "new_password_check": new_password(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - \
+        the field values must match.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let new_password = new_password();
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": Uuid::new_v4().to_string(),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn new_password_must_respect_the_length_limits() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let test_cases = vec![
+        ("a".repeat(11), "at least 12 characters"),
+        ("a".repeat(129), "at most 128 characters"),
+    ];
+
+    for (new_password, error_message) in test_cases {
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/password");
+
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(error_message),
+            "The password policy did not reject a password that is not {}",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn new_password_must_differ_from_the_current_one() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &app.test_user.password,
+            "new_password_check": &app.test_user.password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page
+        .contains("<p><i>The new password must be different from the current one.</i></p>"));
+}
+
+#[tokio::test]
+async fn changing_password_works_and_is_audited() {
+    let app = TestApp::new().await;
+    let new_password = new_password();
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    let audit = sqlx::query!(
+        "select user_id from password_change_audit where user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audit.len(), 1);
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
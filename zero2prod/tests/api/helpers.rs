@@ -1,13 +1,24 @@
-use sha3::Digest;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::Connection;
 use sqlx::PgConnection;
 use sqlx::{migrate::MigrateDatabase, PgPool, Postgres};
 use uuid::Uuid;
 use wiremock::MockServer;
+use zero2prod::authentication::{time_step, TotpSecret};
+use zero2prod::bot_check::FormTokens;
+use zero2prod::clock::Clock;
 use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::email_client::EmailClient;
+use zero2prod::email_outbox::try_send_queued_email;
+use zero2prod::email_templates::EmailTemplates;
+use zero2prod::issue_delivery_worker::{try_execute_task, DeliveryContext, ExecutionOutcome};
+use zero2prod::issue_scheduler::{try_publish_due_issue, SchedulerOutcome};
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::tracking::TrackingLinks;
+use zero2prod::unsubscribe::UnsubscribeLinks;
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_log_level = "info".into();
@@ -29,6 +40,15 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub postgres_connection_str: String,
     pub test_user: TestUser,
+    pub email_client: EmailClient,
+    // the same as the worker spawned by `Application::build`
+    pub delivery_context: DeliveryContext,
+    // fixed, moves only when a test advances it
+    pub clock: Clock,
+    // signs subscription forms with the application's secret
+    pub form_tokens: FormTokens,
+    // keeps cookies between requests and does not follow redirects
+    pub api_client: reqwest::Client,
 }
 
 pub struct ConfirmationLinks {
@@ -73,31 +93,51 @@ pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::generate_with_role("admin")
+    }
+
+    pub fn generate_with_role(role: &'static str) -> Self {
         Self {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
-            password: Uuid::new_v4().to_string()
+            password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
-        let password_hash = sha3::Sha3_256::digest(
-            self.password.as_bytes()
-        );
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
 
-        let password_hash = format!("{:x}", password_hash);
+    pub async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        // same parameters as the application, so verification costs the same
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
 
         sqlx::query!(
-            r#"insert into users (user_id, username, password_hash)
-               values ($1, $2, $3)
+            r#"insert into users (user_id, username, password_hash, role)
+               values ($1, $2, $3, $4)
             "#,
             self.user_id,
             self.username,
             password_hash,
+            self.role,
         )
         .execute(pool)
         .await
@@ -119,19 +159,40 @@ impl TestApp {
             c.database.database_name = format!("data/{}", Uuid::new_v4());
             c.application.port = 0;
             c.email_client.endpoint_url = email_server.uri();
+            // tests sign SNS messages with their own certificate, served by the mock
+            c.ses_webhook.signing_cert_url_prefix = Some(email_server.uri());
             c
         };
 
         let db_pool = configure_database(&configuration.database).await;
 
         // Spin up the server
-        let app = Application::build(&configuration)
+        let clock = Clock::fixed(chrono::Utc::now());
+        let app = Application::build_with_clock(&configuration, clock.clone())
             .await
             .expect("Failed to build application");
         let address = format!("http://127.0.0.1:{}", app.port());
         let port = app.port();
         std::mem::drop(tokio::spawn(app.run_until_stopped()));
 
+        let email_client = configuration.email_client.client().await;
+        let delivery_context = DeliveryContext {
+            pool: db_pool.clone(),
+            email_client: email_client.clone(),
+            templates: EmailTemplates::new(
+                db_pool.clone(),
+                &configuration.application.email_templates_directory,
+            ),
+            unsubscribe_links: UnsubscribeLinks::new(
+                configuration.application.base_url.clone(),
+                configuration.application.hmac_secret.clone(),
+            ),
+            tracking_links: TrackingLinks::new(
+                configuration.application.base_url.clone(),
+                configuration.application.hmac_secret.clone(),
+            ),
+        };
+
         let test_app = TestApp {
             port,
             address,
@@ -139,6 +200,18 @@ impl TestApp {
             email_server,
             postgres_connection_str: configuration.database.connection_str_with_db(),
             test_user: TestUser::generate(),
+            email_client,
+            delivery_context,
+            clock,
+            form_tokens: FormTokens::new(
+                configuration.application.hmac_secret.clone(),
+                configuration.subscriptions.min_time_to_submit(),
+            ),
+            api_client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .cookie_store(true)
+                .build()
+                .unwrap(),
         };
 
         test_app.test_user.store(&test_app.db_pool).await;
@@ -146,29 +219,653 @@ impl TestApp {
         test_app
     }
 
+    /// Deliver every queued email that is due.
+    ///
+    /// The worker spawned by `Application::build` may be holding a task when
+    /// the queue looks empty from here, so wait until no due or claimed task
+    /// is left.
+    pub async fn dispatch_all_pending_emails(&self) {
+        self.dispatch_queued_emails().await;
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.delivery_context).await.unwrap()
+            {
+                // a task claimed by that worker is pushed back while it is
+                // sent, only failed attempts have been retried
+                let pending = sqlx::query!(
+                    r#"select count(*) as "n!" from issue_delivery_queue
+                       where execute_after <= now() or n_retries = 0
+                    "#
+                )
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap();
+
+                if pending.n == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    }
+
+    /// Send every email of the outbox that is due, e.g. confirmation links.
+    pub async fn dispatch_queued_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_send_queued_email(&self.db_pool, &self.email_client)
+                    .await
+                    .unwrap()
+            {
+                // locked by the worker spawned by `Application::build`
+                let pending = sqlx::query!(
+                    r#"select count(*) as "n!" from email_outbox
+                       where execute_after <= now()
+                    "#
+                )
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap();
+
+                if pending.n == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    }
+
+    /// Publish every scheduled issue that is due, queueing its deliveries.
+    pub async fn publish_due_issues(&self) {
+        loop {
+            if let SchedulerOutcome::NothingDue { .. } =
+                try_publish_due_issue(&self.db_pool).await.unwrap()
+            {
+                // the scheduler spawned by `Application::build` may be
+                // holding a due issue
+                let due = sqlx::query!(
+                    r#"select count(*) as "n!" from newsletter_issues
+                       where status = 'scheduled' and send_at <= now()
+                    "#
+                )
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap();
+
+                if due.n == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        }
+    }
+
+    /// A form token for a subscription form rendered a minute ago.
+    pub fn form_token(&self) -> String {
+        self.form_tokens
+            .issue(self.clock.now() - chrono::Duration::minutes(1))
+    }
+
+    /// Submit `body` as a person who filled in the subscription form would.
     pub async fn post_subscriptions(&self, body: &str) -> reqwest::Response {
+        self.post_subscription_form(&format!("{}&form_token={}", body, self.form_token()))
+            .await
+    }
+
+    /// Submit `body` as is, without a form token.
+    ///
+    /// The confirmation email is queued, it is sent before this returns.
+    pub async fn post_subscription_form(&self, body: &str) -> reqwest::Response {
         let url = format!("{}/subscriptions", self.address);
-        reqwest::Client::new()
+        let response = reqwest::Client::new()
             .post(&url)
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body.to_string())
             .send()
             .await
+            .expect("Failed to execute request.");
+        self.dispatch_queued_emails().await;
+        response
+    }
+
+    /// The confirmation email is queued, it is sent before this returns.
+    pub async fn post_resend_confirmation(&self, body: &str) -> reqwest::Response {
+        let response = reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/resend_confirmation",
+                self.address
+            ))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        self.dispatch_queued_emails().await;
+        response
+    }
+
+    pub async fn post_data_request(&self, body: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/data_request", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_ses_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        // SNS does not send JSON as `application/json`
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/ses", self.address))
+            .header("Content-Type", "text/plain; charset=UTF-8")
+            .body(body.to_string())
+            .send()
+            .await
             .expect("Failed to execute request.")
     }
 
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.post_newsletters_with_key(body, &Uuid::new_v4().to_string())
+            .await
+    }
+
+    pub async fn post_newsletters_with_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_as(
+        &self,
+        user: &TestUser,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&user.username, Some(&user.password))
+            .header("Idempotency-Key", Uuid::new_v4().to_string())
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_token(
+        &self,
+        token: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .bearer_auth(token)
+            .header("Idempotency-Key", Uuid::new_v4().to_string())
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_scheduled_newsletters(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/scheduled", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_scheduled_newsletters_as(&self, user: &TestUser) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/scheduled", &self.address))
+            .basic_auth(&user.username, Some(&user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn reschedule_newsletter(
+        &self,
+        newsletter_issue_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .patch(format!(
+                "{}/newsletters/scheduled/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn cancel_newsletter(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/newsletters/scheduled/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_template_preview(&self, name: &str, format: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/templates/{}/preview?format={}",
+                &self.address, name, format
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `action` is either `confirm` or `unsubscribe`.
+    pub async fn post_admin_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_admin_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_subscriber_tags(
+        &self,
+        subscriber_id: Uuid,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/subscribers/{}/tags",
+                &self.address, subscriber_id
+            ))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscribers_import(&self, query: &str, csv: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/import?{}",
+                &self.address, query
+            ))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_consent_history(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/consent", &self.address))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lists(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_list(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn patch_list(&self, slug: &str, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .patch(format!("{}/admin/lists/{}", &self.address, slug))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_api_tokens(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/api_tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_api_token(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/api_tokens", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_api_token(&self, api_token_id: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/api_tokens/{}",
+                &self.address, api_token_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The invitation email is queued, it is sent before this returns.
+    pub async fn post_invitation(&self, body: &serde_json::Value) -> reqwest::Response {
+        let response = self
+            .api_client
+            .post(format!("{}/admin/users/invitations", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        self.dispatch_queued_emails().await;
+        response
+    }
+
+    pub async fn put_user_lists(&self, user_id: Uuid, lists: &[&str]) -> reqwest::Response {
+        self.api_client
+            .put(format!("{}/admin/users/{}/lists", &self.address, user_id))
+            .json(&serde_json::json!({ "lists": lists }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_deactivate_user(&self, user_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/users/{}/deactivate",
+                &self.address, user_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_accept_invitation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/invitations/accept", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_two_factor(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/login/two_factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_two_factor<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login/two_factor", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_status(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/two_factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_two_factor_enrollment(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/two_factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_two_factor_confirmation(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/two_factor/confirm", &self.address))
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_recovery_codes(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/two_factor/recovery_codes", &self.address))
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/two_factor", &self.address))
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_code(
+        &self,
+        code: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("X-TOTP-Code", code)
+            .header("Idempotency-Key", Uuid::new_v4().to_string())
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    /// The code an authenticator app with `secret` shows at the time of the
+    /// test clock.
+    pub fn totp_code(&self, secret: &str) -> String {
+        TotpSecret::parse(secret)
+            .unwrap()
+            .code_at(time_step(self.clock.now()))
+    }
+
+    pub async fn get_newsletter_stats(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/stats",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
-        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let email = SentEmail::from_request(email_request);
 
         let get_link = |s: &str| {
             let links: Vec<_> = linkify::LinkFinder::new()
@@ -177,25 +874,110 @@ impl TestApp {
                 .collect();
             assert_eq!(links.len(), 1);
             let raw_link = links[0].as_str().to_owned();
-            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
-            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
-            confirmation_link.set_port(Some(self.port)).unwrap();
-            confirmation_link
+            self.local_link(&raw_link)
         };
 
-        let html = get_link(
-            body.pointer("/Content/Simple/Body/Html/Data")
-                .unwrap()
-                .as_str()
-                .unwrap(),
-        );
-        let text = get_link(
-            body.pointer("/Content/Simple/Body/Text/Data")
-                .unwrap()
-                .as_str()
-                .unwrap(),
-        );
+        // the templates escape the links, `/` included
+        let html = get_link(&htmlescape::decode_html(&email.html).unwrap());
+        let text = get_link(&email.text);
 
         ConfirmationLinks { html, text }
     }
+
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let email = SentEmail::from_request(email_request);
+        let header = email.header("List-Unsubscribe").unwrap();
+        let raw_link = header.trim_start_matches('<').trim_end_matches('>');
+
+        self.local_link(raw_link)
+    }
+
+    // links in emails use the configured base url, point them at the test server
+    pub fn local_link(&self, raw_link: &str) -> reqwest::Url {
+        let mut link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+}
+
+/// An email as received by the mock SES server, decoded from its raw MIME form.
+pub struct SentEmail {
+    pub headers: Vec<(String, String)>,
+    pub html: String,
+    pub text: String,
+}
+
+impl SentEmail {
+    pub fn from_request(email_request: &wiremock::Request) -> Self {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let data = body.pointer("/Content/Raw/Data").unwrap().as_str().unwrap();
+        let raw = String::from_utf8(base64::decode(data).unwrap()).unwrap();
+
+        Self::parse(&raw)
+    }
+
+    pub fn parse(raw: &str) -> Self {
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+        let headers = parse_headers(head);
+
+        let content_type = find_header(&headers, "Content-Type").unwrap();
+        let boundary = content_type
+            .split("boundary=")
+            .nth(1)
+            .unwrap()
+            .trim_matches('"');
+
+        let mut html = String::new();
+        let mut text = String::new();
+        for part in body.split(&format!("--{}", boundary)).skip(1) {
+            // the closing delimiter is followed by "--"
+            if part.starts_with("--") {
+                break;
+            }
+            let (part_head, part_body) = part.trim_start().split_once("\r\n\r\n").unwrap();
+            let part_headers = parse_headers(part_head);
+            let encoded: String = part_body.split_whitespace().collect();
+            let decoded = String::from_utf8(base64::decode(encoded).unwrap()).unwrap();
+
+            let part_content_type = find_header(&part_headers, "Content-Type").unwrap();
+            if part_content_type.starts_with("text/html") {
+                html = decoded;
+            } else if part_content_type.starts_with("text/plain") {
+                text = decoded;
+            }
+        }
+
+        Self {
+            headers,
+            html,
+            text,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+fn parse_headers(head: &str) -> Vec<(String, String)> {
+    // a line starting with whitespace continues the previous header
+    let unfolded = head.replace("\r\n ", " ").replace("\r\n\t", " ");
+    unfolded
+        .split("\r\n")
+        .filter_map(|line| line.split_once(": "))
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect()
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
 }
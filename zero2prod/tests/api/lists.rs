@@ -0,0 +1,332 @@
+use crate::helpers::{assert_is_redirect_to, SentEmail, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const WEEKLY_SENDER: &str = "weekly@example.com";
+
+async fn create_weekly_list(app: &TestApp) {
+    let response = app
+        .post_list(&serde_json::json!({
+            "slug": "weekly",
+            "name": "Weekly notes",
+            "sender_email": WEEKLY_SENDER,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+/// Subscribe with `body` and return the confirmation email.
+async fn subscribe(app: &TestApp, body: &str) -> wiremock::Request {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+async fn confirm(app: &TestApp, email_request: &wiremock::Request) {
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn created_lists_are_listed_after_the_default_one() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+
+    let response = app.get_lists().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let lists = body["lists"].as_array().unwrap();
+    assert_eq!(lists.len(), 2);
+    assert_eq!(lists[0]["slug"], "default");
+    assert_eq!(lists[1]["slug"], "weekly");
+    assert_eq!(lists[1]["name"], "Weekly notes");
+    assert_eq!(lists[1]["sender_email"], WEEKLY_SENDER);
+}
+
+#[tokio::test]
+async fn a_slug_can_only_be_taken_once() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+
+    let response = app
+        .post_list(&serde_json::json!({
+            "slug": "weekly",
+            "name": "Another weekly",
+            "sender_email": "another@example.com",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn invalid_lists_are_rejected() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"slug": "Weekly Notes", "name": "Weekly", "sender_email": WEEKLY_SENDER}),
+            "invalid slug",
+        ),
+        (
+            serde_json::json!({"slug": "weekly", "name": " ", "sender_email": WEEKLY_SENDER}),
+            "empty name",
+        ),
+        (
+            serde_json::json!({"slug": "weekly", "name": "Weekly", "sender_email": "not-an-email"}),
+            "invalid sender",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_list(&body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the list had an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_list_can_be_renamed_and_given_a_sender() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .patch_list(
+            "default",
+            &serde_json::json!({"name": "The zero2prod letter", "sender_email": WEEKLY_SENDER}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let list: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(list["name"], "The zero2prod letter");
+    assert_eq!(list["sender_email"], WEEKLY_SENDER);
+}
+
+#[tokio::test]
+async fn updating_an_unknown_list_is_a_404() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .patch_list("nope", &serde_json::json!({"name": "Nope"}))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn one_email_can_subscribe_to_several_lists() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+
+    subscribe(&app, "name=le%20guin&email=ursula_le_guin%40gmail.com").await;
+    let weekly_request = subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly",
+    )
+    .await;
+    confirm(&app, &weekly_request).await;
+
+    let subscriptions = sqlx::query!(
+        r#"select l.slug, s.status
+           from subscriptions s join lists l using (list_id)
+           order by l.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(subscriptions.len(), 2);
+    assert_eq!(subscriptions[0].slug, "default");
+    assert_eq!(subscriptions[0].status, "pending_confirmation");
+    assert_eq!(subscriptions[1].slug, "weekly");
+    assert_eq!(subscriptions[1].status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmations_are_sent_by_the_list() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+
+    let email_request = subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly",
+    )
+    .await;
+
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["FromEmailAddress"], WEEKLY_SENDER);
+    let email = SentEmail::from_request(&email_request);
+    assert_eq!(email.header("From"), Some(WEEKLY_SENDER));
+    assert!(email.text.contains("Welcome to Weekly notes"));
+}
+
+#[tokio::test]
+async fn an_expired_link_asks_for_a_new_one_on_the_same_list() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+    let email_request = subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly",
+    )
+    .await;
+    sqlx::query!("update subscription_tokens set created_at = now() - interval '49 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"<input type="hidden" name="list" value="weekly">"#));
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // as the form on the page posts it
+    let response = app
+        .post_resend_confirmation("list=weekly&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    confirm(&app, &email_request).await;
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=nope")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_are_delivered_to_the_subscribers_of_their_list_only() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+    let default_request = subscribe(&app, "name=octavia&email=octavia%40example.com").await;
+    confirm(&app, &default_request).await;
+    let weekly_request = subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly",
+    )
+    .await;
+    confirm(&app, &weekly_request).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "list": "weekly",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as html</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let issue_request = requests.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&issue_request.body).unwrap();
+    assert_eq!(body["FromEmailAddress"], WEEKLY_SENDER);
+    assert_eq!(
+        body["Destination"]["ToAddresses"][0],
+        "ursula_le_guin@gmail.com"
+    );
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "list": "nope",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as html</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    let app = TestApp::new().await;
+
+    let response = app.get_lists().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .patch_list("default", &serde_json::json!({"name": "Hijacked"}))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
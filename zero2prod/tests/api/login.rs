@@ -0,0 +1,63 @@
+use crate::helpers::{assert_is_redirect_to, TestApp};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = TestApp::new().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // the flash message is gone after a reload
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed."));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = TestApp::new().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn a_wrong_password_is_rejected() {
+    let app = TestApp::new().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": Uuid::new_v4().to_string()
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn sessions_are_stored_in_postgres() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let saved = sqlx::query!(r#"select count(*) as "n!" from sessions where expires_at > now()"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.n, 1);
+}
@@ -2,9 +2,28 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 #![allow(unused_imports)]
+// e.g. `.get(&format!(..))` in `health_check`
+#![allow(clippy::needless_borrows_for_generic_args)]
 
+mod admin_dashboard;
+mod admin_newsletter;
+mod admin_subscribers;
+mod admin_subscribers_csv;
+mod admin_templates;
+mod api_tokens;
+mod change_password;
 mod health_check;
 mod helpers;
+mod lists;
+mod login;
 mod newsletter;
+mod newsletter_scheduled;
+mod newsletter_tracking;
+mod ses_webhook;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_consent;
+mod subscriptions_data;
+mod subscriptions_unsubscribe;
+mod two_factor;
+mod users;
@@ -1,8 +1,12 @@
 use crate::helpers::ConfirmationLinks;
-use crate::helpers::TestApp;
+use crate::helpers::{SentEmail, TestApp};
 use reqwest::Client;
-use wiremock::matchers::{any, header, method, path};
+use sha3::Digest;
+use uuid::Uuid;
+use wiremock::matchers::{any, body_string_contains, header, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::idempotency::{try_processing, IdempotencyKey, NextAction};
+use zero2prod::issue_delivery_worker::try_execute_task;
 
 const POST: &str = "POST";
 const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
@@ -31,10 +35,15 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    create_unconfirmed_subscriber_with(app, SUBSCRIBE_FORM_BODY).await
+}
+
+async fn create_unconfirmed_subscriber_with(app: &TestApp, body: &str) -> ConfirmationLinks {
     // do not use _ but _g, otherwise it will be dropped and receiving request
     let _g = Mock::given(path(SEND_EMAIL_END_POINT))
         .and(header("Content-Type", "application/json"))
@@ -45,7 +54,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .mount_as_scoped(&app.email_server)
         .await;
 
-    app.post_subscriptions(SUBSCRIBE_FORM_BODY)
+    app.post_subscriptions(body)
         .await
         .error_for_status()
         .unwrap();
@@ -62,7 +71,11 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
 }
 
 async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    create_confirmed_subscriber_with(app, SUBSCRIBE_FORM_BODY).await
+}
+
+async fn create_confirmed_subscriber_with(app: &TestApp, body: &str) {
+    let confirmation_link = create_unconfirmed_subscriber_with(app, body).await;
 
     reqwest::get(confirmation_link.html)
         .await
@@ -93,7 +106,158 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_rendered_with_the_newsletter_template() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email = SentEmail::from_request(requests.last().unwrap());
+
+    let html = htmlescape::decode_html(&email.html).unwrap();
+    assert!(html.contains("<p>newsletters body as html</p>"));
+    assert!(html.contains("/subscriptions/unsubscribe?subscriber_id="));
+    assert!(email.text.starts_with("newsletter body as plain text"));
+    assert!(email.text.contains("Unsubscribe: "));
+    // one-click unsubscribe, RFC 8058
+    assert!(email
+        .header("List-Unsubscribe")
+        .unwrap()
+        .contains("/subscriptions/unsubscribe?subscriber_id="));
+    assert_eq!(
+        email.header("List-Unsubscribe-Post"),
+        Some("List-Unsubscribe=One-Click")
+    );
+}
+
+#[tokio::test]
+async fn markdown_newsletters_are_rendered_to_html_and_text() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "markdown": "Some **news**, read the [archive](/issues/42)."
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let saved = sqlx::query!("select html_content, text_content from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.html_content.contains("<strong>news</strong>"));
+    // relative links would be broken in an inbox
+    assert!(saved
+        .html_content
+        .contains(r#"href="http://127.0.0.1/issues/42""#));
+    assert!(saved.text_content.contains("news"));
+    assert!(!saved.text_content.contains("<strong>"));
+}
+
+#[tokio::test]
+async fn markdown_with_unsafe_html_is_rejected_with_a_400() {
+    let app = TestApp::new().await;
+
+    let test_cases = vec![
+        ("Hello\n\n<script>alert('hi')</script>", "a script tag"),
+        (
+            r#"<img src="cat.png" onerror="alert('hi')">"#,
+            "an event handler",
+        ),
+        ("[click me](javascript:alert('hi'))", "a javascript link"),
+    ];
+
+    for (markdown, description) in test_cases {
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "markdown": markdown
+            }))
+            .await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the markdown had {}",
+            description
+        );
+    }
+    let issues = sqlx::query!("select count(*) as \"count!\" from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 0);
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_their_segment() {
+    let app = TestApp::new().await;
+    for (name, email, tags) in [
+        ("rustacean", "rustacean%40example.com", "rust"),
+        ("beta%20tester", "beta_tester%40example.com", "rust%2Cbeta"),
+        ("gopher", "gopher%40example.com", "go"),
+    ] {
+        create_confirmed_subscriber_with(
+            &app,
+            &format!("name={}&email={}&tags={}", name, email, tags),
+        )
+        .await;
+    }
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            },
+            "segment": "tag:rust AND NOT tag:beta"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // the confirmation emails went out before the issue was published
+    let requests = app.email_server.received_requests().await.unwrap();
+    let recipients: Vec<String> = requests[3..]
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let to = body.pointer("/Destination/ToAddresses/0").unwrap();
+            to.as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(recipients, vec!["rustacean@example.com"]);
 }
 
 #[tokio::test]
@@ -114,6 +278,28 @@ async fn newsletters_returns_400_for_invalid_data() {
             serde_json::json!({"title": "Newsletter!"}),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {
+                    "text": "newsletter body as plain text",
+                    "html": "<p>newsletters body as html</p>"
+                },
+                "markdown": "newsletter body as markdown"
+            }),
+            "both content and markdown",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {
+                    "text": "newsletter body as plain text",
+                    "html": "<p>newsletters body as html</p>"
+                },
+                "segment": "tag:rust AND"
+            }),
+            "a malformed segment",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -133,7 +319,362 @@ async fn requests_missing_authorization_are_rejected() {
     let app = TestApp::new().await;
 
     let response = Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_without_an_idempotency_key_are_rejected() {
+    let app = TestApp::new().await;
+
+    let response = Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "newsletter body as plain text",
+            "html": "<p>newsletters body as html</p>"
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_newsletters_with_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // retry with the same key, the saved response is replayed and no issue is queued
+    let response = app
+        .post_newsletters_with_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn requests_with_a_key_still_in_flight_get_a_409() {
+    let app = TestApp::new().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // a claimed key without a saved response belongs to a request still running
+    sqlx::query!(
+        r#"insert into idempotency (user_id, idempotency_key, created_at)
+           values ($1, $2, now())
+        "#,
+        app.test_user.user_id,
+        idempotency_key
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .post_newsletters_with_key(
+            serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": "newsletter body as plain text",
+                    "html": "<p>newsletters body as html</p>"
+                }
+            }),
+            &idempotency_key,
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn a_key_claimed_by_a_request_still_running_gets_a_409() {
+    let app = TestApp::new().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "newsletter body as plain text",
+            "html": "<p>newsletters body as html</p>"
+        }
+    });
+
+    // the claim is held until the transaction ends
+    let next_action = try_processing(
+        &app.db_pool,
+        &IdempotencyKey::parse(idempotency_key.clone()).unwrap(),
+        app.test_user.user_id,
+    )
+    .await
+    .unwrap();
+    let transaction = match next_action {
+        NextAction::StartProcessing(transaction) => transaction,
+        _ => panic!("The key was not claimed."),
+    };
+
+    let response = app
+        .post_newsletters_with_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    // a request that fails leaves nothing behind
+    transaction.rollback().await.unwrap();
+    let response = app
+        .post_newsletters_with_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn the_issue_is_not_stored_if_saving_the_response_fails() {
+    let app = TestApp::new().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "newsletter body as plain text",
+            "html": "<p>newsletters body as html</p>"
+        }
+    });
+
+    sqlx::query!("alter table idempotency drop column response_body")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .post_newsletters_with_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 500);
+
+    let issues = sqlx::query!(r#"select count(*) as "count!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 0);
+
+    // the key was not left in flight, a retry goes through
+    sqlx::query!("alter table idempotency add column response_body bytea null")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .post_newsletters_with_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let issues = sqlx::query!(r#"select count(*) as "count!" from newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 1);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        r#"select n_retries, execute_after > now() as "in_future!"
+           from issue_delivery_queue
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The failed delivery was dropped from the queue.");
+
+    assert_eq!(task.n_retries, 1);
+    assert!(task.in_future);
+}
+
+#[tokio::test]
+async fn only_the_failed_deliveries_of_a_batch_are_retried() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber_with(&app, "name=tolkien&email=tolkien%40gmail.com").await;
+
+    // mounted first, it takes precedence for this recipient
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(body_string_contains("tolkien@gmail.com"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+
+    let tasks = sqlx::query!(r#"select n_retries from issue_delivery_queue"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].n_retries, 1);
+    let sent = sqlx::query!(r#"select count(*) as "count!" from deliveries where status = 'sent'"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sent.count, 1);
+}
+
+#[tokio::test]
+async fn a_batch_mixing_issues_sends_each_issue_once_per_subscriber() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber_with(&app, "name=tolkien&email=tolkien%40gmail.com").await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(4)
+        .mount(&app.email_server)
+        .await;
+
+    // stored but not queued, the tasks are queued below in our own order
+    let mut issue_ids = Vec::new();
+    for title in ["First issue", "Second issue"] {
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": title,
+                "content": {
+                    "text": "newsletter body as plain text",
+                    "html": "<p>newsletters body as html</p>"
+                },
+                "send_at": chrono::Utc::now() + chrono::Duration::hours(1)
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 202);
+        let body: serde_json::Value = response.json().await.unwrap();
+        issue_ids.push(
+            body["newsletter_issue_id"]
+                .as_str()
+                .unwrap()
+                .parse::<Uuid>()
+                .unwrap(),
+        );
+    }
+    // claimed in the order they were inserted: A, B, A, B
+    for email in ["ursula_le_guin@gmail.com", "tolkien@gmail.com"] {
+        for issue_id in &issue_ids {
+            sqlx::query!(
+                r#"insert into issue_delivery_queue (newsletter_issue_id, subscriber_email)
+                   values ($1, $2)
+                "#,
+                issue_id,
+                email
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    app.dispatch_all_pending_emails().await;
+
+    let mut sent: Vec<(String, String)> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(SentEmail::from_request)
+        .map(|email| {
+            (
+                email.header("To").unwrap().to_owned(),
+                email.header("Subject").unwrap().to_owned(),
+            )
+        })
+        // the confirmation emails went out before
+        .filter(|(_, subject)| subject.ends_with(" issue"))
+        .collect();
+    sent.sort();
+    assert_eq!(sent.len(), 4);
+    sent.dedup();
+    assert_eq!(sent.len(), 4);
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = TestApp::new().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
         .json(&serde_json::json!({
             "title": "Newsletter title",
             "content": {
@@ -151,3 +692,159 @@ async fn requests_missing_authorization_are_rejected() {
         response.headers()["WWW-Authenticate"]
     );
 }
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = TestApp::new().await;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    let response = Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn legacy_password_hashes_are_upgraded_on_login() {
+    let app = TestApp::new().await;
+    let legacy_hash = format!(
+        "{:x}",
+        sha3::Sha3_256::digest(app.test_user.password.as_bytes())
+    );
+    sqlx::query!(
+        "update users set password_hash = $1 where user_id = $2",
+        legacy_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let saved = sqlx::query!(
+        "select password_hash from users where user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(saved.password_hash.starts_with("$argon2id$"));
+
+    // the upgraded hash keeps accepting the same password
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn an_issue_that_cannot_be_sent_does_not_hold_up_the_rest_of_the_batch() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // the nil id comes first in a batch, its list has an invalid sender
+    let broken_list_id = Uuid::new_v4();
+    let broken_issue_id = Uuid::nil();
+    sqlx::query!(
+        r#"insert into lists (list_id, slug, name, sender_email)
+           values ($1, 'broken', 'Broken', 'not an email')
+        "#,
+        broken_list_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"insert into newsletter_issues (
+               newsletter_issue_id, list_id, title, text_content, html_content,
+               status, send_at, published_at
+           )
+           values ($1, $2, 'Broken issue', 'text', '<p>html</p>', 'published', now(), now())
+        "#,
+        broken_issue_id,
+        broken_list_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // stored but not queued, its task is queued below with the broken one
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Working issue",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            },
+            "send_at": chrono::Utc::now() + chrono::Duration::hours(1)
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id: Uuid = body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    for issue_id in [broken_issue_id, issue_id] {
+        sqlx::query!(
+            r#"insert into issue_delivery_queue (newsletter_issue_id, subscriber_email)
+               values ($1, 'ursula_le_guin@gmail.com')
+            "#,
+            issue_id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    // the worker spawned by `Application::build` may claim the batch first
+    try_execute_task(&app.delivery_context).await.unwrap();
+    let mut sent = false;
+    for _ in 0..50 {
+        let remaining = sqlx::query!(r#"select newsletter_issue_id from issue_delivery_queue"#)
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+        if remaining.len() == 1 {
+            assert_eq!(remaining[0].newsletter_issue_id, broken_issue_id);
+            sent = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(sent, "The working issue was not delivered.");
+}
@@ -0,0 +1,232 @@
+use crate::helpers::TestApp;
+use chrono::{Duration, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_scheduler::try_publish_due_issue;
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const SUBSCRIBE_FORM_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Schedule an issue an hour from now, returning its id.
+async fn schedule_newsletter(app: &TestApp) -> String {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            },
+            "send_at": Utc::now() + Duration::hours(1)
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn count_delivery_tasks(app: &TestApp) -> i64 {
+    sqlx::query!(r#"select count(*) as "n!" from issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_are_not_delivered_before_send_at() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_issue_id = schedule_newsletter(&app).await;
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.get_scheduled_newsletters().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let scheduled: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(scheduled.as_array().unwrap().len(), 1);
+    assert_eq!(scheduled[0]["newsletter_issue_id"], newsletter_issue_id);
+    assert_eq!(scheduled[0]["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_are_delivered_once_due() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_issue_id = schedule_newsletter(&app).await;
+    let response = app
+        .reschedule_newsletter(
+            &newsletter_issue_id,
+            serde_json::json!({ "send_at": Utc::now() - Duration::minutes(1) }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    let scheduled: serde_json::Value = app.get_scheduled_newsletters().await.json().await.unwrap();
+    assert!(scheduled.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn cancelled_newsletters_are_never_delivered() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_issue_id = schedule_newsletter(&app).await;
+    let response = app.cancel_newsletter(&newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    sqlx::query!("update newsletter_issues set send_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(count_delivery_tasks(&app).await, 0);
+    let scheduled: serde_json::Value = app.get_scheduled_newsletters().await.json().await.unwrap();
+    assert!(scheduled.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn only_pending_newsletters_can_be_rescheduled_or_cancelled() {
+    let app = TestApp::new().await;
+
+    // published right away, there is nothing left to reschedule
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let published_issue_id = body["newsletter_issue_id"].as_str().unwrap().to_owned();
+    let unknown_issue_id = uuid::Uuid::new_v4().to_string();
+
+    for newsletter_issue_id in [published_issue_id, unknown_issue_id] {
+        let response = app
+            .reschedule_newsletter(
+                &newsletter_issue_id,
+                serde_json::json!({ "send_at": Utc::now() + Duration::hours(1) }),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 404);
+
+        let response = app.cancel_newsletter(&newsletter_issue_id).await;
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[tokio::test]
+async fn scheduling_endpoints_require_authentication() {
+    let app = TestApp::new().await;
+    let newsletter_issue_id = schedule_newsletter(&app).await;
+    let url = format!("{}/newsletters/scheduled", app.address);
+    let issue_url = format!("{}/{}", url, newsletter_issue_id);
+    let client = reqwest::Client::new();
+
+    let requests = vec![
+        client.get(&url),
+        client
+            .patch(&issue_url)
+            .json(&serde_json::json!({ "send_at": Utc::now() })),
+        client.delete(&issue_url),
+    ];
+
+    for request in requests {
+        let response = request.send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let scheduled: serde_json::Value = app.get_scheduled_newsletters().await.json().await.unwrap();
+    assert_eq!(scheduled.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn concurrent_schedulers_publish_a_due_issue_once() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    let newsletter_issue_id = schedule_newsletter(&app).await;
+    app.reschedule_newsletter(
+        &newsletter_issue_id,
+        serde_json::json!({ "send_at": Utc::now() - Duration::minutes(1) }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let (first, second) = tokio::join!(
+        try_publish_due_issue(&app.db_pool),
+        try_publish_due_issue(&app.db_pool)
+    );
+    first.unwrap();
+    second.unwrap();
+    app.publish_due_issues().await;
+
+    // a second publication would have collided with the queue's primary key
+    assert_eq!(count_delivery_tasks(&app).await, 1);
+    let status = sqlx::query!("select status from newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "published");
+}
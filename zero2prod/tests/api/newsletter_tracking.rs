@@ -0,0 +1,278 @@
+use crate::helpers::{assert_is_redirect_to, SentEmail, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const SUBSCRIBE_FORM_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+const ARTICLE_URL: &str = "https://example.com/articles/1?from=newsletter&lang=en";
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Publish an issue linking to `ARTICLE_URL`, deliver it and return its id.
+async fn publish_issue(app: &TestApp, tracking: serde_json::Value) -> String {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": format!(
+                    r#"<p><a href="{}">Read more</a></p>"#,
+                    htmlescape::encode_attribute(ARTICLE_URL)
+                ),
+            },
+            "tracking": tracking
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+/// The html of the last issue sent, unescaped.
+async fn sent_html(app: &TestApp) -> String {
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email = SentEmail::from_request(requests.last().unwrap());
+
+    htmlescape::decode_html(&email.html).unwrap()
+}
+
+/// The tracking link in `html` that contains `kind`, pointed at the test server.
+fn tracking_link(app: &TestApp, html: &str, kind: &str) -> reqwest::Url {
+    let raw_link = linkify::LinkFinder::new()
+        .links(html)
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains(kind))
+        .unwrap();
+    app.local_link(&raw_link)
+}
+
+async fn get_stats(app: &TestApp, newsletter_issue_id: &str) -> serde_json::Value {
+    let response = app.get_newsletter_stats(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn every_delivery_is_recorded() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    publish_issue(&app, serde_json::json!({})).await;
+
+    let delivery = sqlx::query!("select status, error, subscriber_id from deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(delivery.error, None);
+    assert!(delivery.subscriber_id.is_some());
+}
+
+#[tokio::test]
+async fn a_failed_delivery_is_recorded_with_its_error() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!("select status, error from deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "retrying");
+    assert!(delivery.error.is_some());
+}
+
+#[tokio::test]
+async fn issues_are_not_tracked_unless_asked_to() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+
+    publish_issue(&app, serde_json::json!({})).await;
+
+    let html = sent_html(&app).await;
+    assert!(html.contains(&format!(r#"href="{}""#, ARTICLE_URL)));
+    assert!(!html.contains("/tracking/"));
+}
+
+#[tokio::test]
+async fn a_click_is_counted_and_redirected_to_the_original_link() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app, serde_json::json!({ "clicks": true })).await;
+    let html = sent_html(&app).await;
+    assert!(!html.contains(&format!(r#"href="{}""#, ARTICLE_URL)));
+    // the unsubscribe link is not ours to track
+    assert!(html.contains("/subscriptions/unsubscribe?subscriber_id="));
+    let click_link = tracking_link(&app, &html, "/tracking/click/");
+
+    let response = app.api_client.get(click_link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(response.headers().get("Location").unwrap(), ARTICLE_URL);
+
+    app.test_user.login(&app).await;
+    let stats = get_stats(&app, &issue_id).await;
+    assert_eq!(stats["clicks"]["unique"], 1);
+    assert_eq!(stats["clicks"]["total"], 1);
+    assert_eq!(stats["clicks"]["links"][0]["url"], ARTICLE_URL);
+    // opens are not tracked for this issue
+    assert_eq!(stats["opens"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn a_click_link_cannot_be_pointed_elsewhere() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    publish_issue(&app, serde_json::json!({ "clicks": true })).await;
+    let mut click_link = tracking_link(&app, &sent_html(&app).await, "/tracking/click/");
+    let token = click_link
+        .query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    click_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("url", "https://evil.example.com")
+        .append_pair("token", &token);
+
+    let response = app.api_client.get(click_link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let n_clicks = sqlx::query!(r#"select count(*) as "count!" from delivery_clicks"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_clicks, 0);
+}
+
+#[tokio::test]
+async fn opens_are_counted_through_the_pixel() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app, serde_json::json!({ "opens": true })).await;
+    let pixel = tracking_link(&app, &sent_html(&app).await, "/tracking/open/");
+
+    for _ in 0..2 {
+        let response = reqwest::get(pixel.clone()).await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "image/gif");
+    }
+
+    app.test_user.login(&app).await;
+    let stats = get_stats(&app, &issue_id).await;
+    assert_eq!(stats["deliveries"]["sent"], 1);
+    assert_eq!(stats["opens"]["unique"], 1);
+    assert_eq!(stats["opens"]["total"], 2);
+    assert_eq!(stats["clicks"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn an_unknown_pixel_still_returns_an_image() {
+    let app = TestApp::new().await;
+
+    let response = reqwest::get(format!("{}/tracking/open/{}", app.address, Uuid::new_v4()))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn stats_of_an_unknown_issue_are_a_404() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_newsletter_stats(&Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_issue_stats() {
+    let app = TestApp::new().await;
+
+    let response = app.get_newsletter_stats(&Uuid::new_v4().to_string()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_keeps_their_deliveries_anonymously() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app, serde_json::json!({})).await;
+
+    app.test_user.login(&app).await;
+    let subscriber_id = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    app.delete_admin_subscriber(subscriber_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let delivery = sqlx::query!("select subscriber_id from deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.subscriber_id, None);
+    let stats = get_stats(&app, &issue_id).await;
+    assert_eq!(stats["deliveries"]["sent"], 1);
+}
@@ -0,0 +1,323 @@
+use crate::helpers::TestApp;
+use chrono::Utc;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey};
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const TOPIC_ARN: &str = "arn:aws:sns:ap-southeast-2:123456789012:zero2prod-ses-notifications";
+const SIGNING_KEY: &str = include_str!("../fixtures/sns_signing_key.pem");
+const SIGNING_CERT: &str = include_str!("../fixtures/sns_signing_cert.pem");
+const SIGNING_CERT_PATH: &str = "/sns/signing-cert.pem";
+const SUBSCRIBER_EMAIL: &str = "ursula_le_guin@gmail.com";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+
+// canned SES notifications, as in the SES developer guide
+const PERMANENT_BOUNCE: &str = r#"{
+    "notificationType": "Bounce",
+    "bounce": {
+        "bounceType": "Permanent",
+        "bounceSubType": "General",
+        "bouncedRecipients": [
+            {
+                "emailAddress": "Ursula_Le_Guin@gmail.com",
+                "action": "failed",
+                "status": "5.1.1",
+                "diagnosticCode": "smtp; 550 5.1.1 user unknown"
+            }
+        ],
+        "timestamp": "2023-10-30T08:00:00.000Z",
+        "feedbackId": "000001378603176d-5a4b5ad9-6f30-4198-a8c3-b1eb0c270a1d-000000",
+        "reportingMTA": "dsn; a8-70.smtp-out.amazonses.com"
+    },
+    "mail": {
+        "timestamp": "2023-10-30T07:59:59.000Z",
+        "source": "testing@gmail.com",
+        "messageId": "00000137860315fd-34208509-5b74-41f3-95c5-22c1edc3c924-000000",
+        "destination": ["Ursula_Le_Guin@gmail.com"]
+    }
+}"#;
+const TRANSIENT_BOUNCE: &str = r#"{
+    "notificationType": "Bounce",
+    "bounce": {
+        "bounceType": "Transient",
+        "bounceSubType": "MailboxFull",
+        "bouncedRecipients": [{ "emailAddress": "ursula_le_guin@gmail.com" }],
+        "timestamp": "2023-10-30T08:00:00.000Z",
+        "feedbackId": "000001378603176d-5a4b5ad9-6f30-4198-a8c3-b1eb0c270a1d-000000"
+    },
+    "mail": {
+        "timestamp": "2023-10-30T07:59:59.000Z",
+        "source": "testing@gmail.com",
+        "messageId": "00000137860315fd-34208509-5b74-41f3-95c5-22c1edc3c924-000000",
+        "destination": ["ursula_le_guin@gmail.com"]
+    }
+}"#;
+const COMPLAINT: &str = r#"{
+    "notificationType": "Complaint",
+    "complaint": {
+        "userAgent": "AnyCompany Feedback Loop (V0.01)",
+        "complainedRecipients": [{ "emailAddress": "ursula_le_guin@gmail.com" }],
+        "complaintFeedbackType": "abuse",
+        "arrivalDate": "2023-10-30T08:00:00.000Z",
+        "timestamp": "2023-10-30T08:00:01.000Z",
+        "feedbackId": "000001378603177f-18c07c78-fa81-4a58-9dd1-fedc3cb8f49a-000000"
+    },
+    "mail": {
+        "timestamp": "2023-10-30T07:59:59.000Z",
+        "source": "testing@gmail.com",
+        "messageId": "000001378603177f-7a5433e7-8edb-42ae-af10-f0181f34d6ee-000000",
+        "destination": ["ursula_le_guin@gmail.com"]
+    }
+}"#;
+
+async fn mount_signing_cert(app: &TestApp) {
+    Mock::given(path(SIGNING_CERT_PATH))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(SIGNING_CERT))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    sqlx::query!(
+        r#"insert into subscriptions (id, email, name, subscribed_at, status)
+           values ($1, $2, 'le guin', now(), 'confirmed')
+        "#,
+        Uuid::new_v4(),
+        SUBSCRIBER_EMAIL
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn subscription_status(app: &TestApp) -> String {
+    sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+/// Fill in the signature fields the way SNS does.
+fn sign(app: &TestApp, mut message: serde_json::Value) -> serde_json::Value {
+    let signed_fields: &[&str] = if message["Type"] == "Notification" {
+        &[
+            "Message",
+            "MessageId",
+            "Subject",
+            "Timestamp",
+            "TopicArn",
+            "Type",
+        ]
+    } else {
+        &[
+            "Message",
+            "MessageId",
+            "SubscribeURL",
+            "Timestamp",
+            "Token",
+            "TopicArn",
+            "Type",
+        ]
+    };
+    let string_to_sign: String = signed_fields
+        .iter()
+        .filter_map(|&name| {
+            message[name]
+                .as_str()
+                .map(|value| format!("{}\n{}\n", name, value))
+        })
+        .collect();
+
+    let key = RsaPrivateKey::from_pkcs8_pem(SIGNING_KEY).unwrap();
+    let signature = key
+        .sign(
+            Pkcs1v15Sign::new::<Sha1>(),
+            &Sha1::digest(string_to_sign.as_bytes()),
+        )
+        .unwrap();
+    message["SignatureVersion"] = "1".into();
+    message["Signature"] = base64::encode(signature).into();
+    message["SigningCertURL"] = format!("{}{}", app.email_server.uri(), SIGNING_CERT_PATH).into();
+    message
+}
+
+fn notification(app: &TestApp, ses_notification: &str) -> serde_json::Value {
+    sign(
+        app,
+        serde_json::json!({
+            "Type": "Notification",
+            "MessageId": Uuid::new_v4().to_string(),
+            "TopicArn": TOPIC_ARN,
+            "Message": ses_notification,
+            "Timestamp": Utc::now().to_rfc3339(),
+        }),
+    )
+}
+
+#[tokio::test]
+async fn a_permanent_bounce_stops_newsletters_to_the_address() {
+    let app = TestApp::new().await;
+    mount_signing_cert(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app
+        .post_ses_webhook(&notification(&app, PERMANENT_BOUNCE))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "bounced");
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "newsletter body as plain text",
+                "html": "<p>newsletters body as html</p>"
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_complaint_marks_the_subscriber_as_complained() {
+    let app = TestApp::new().await;
+    mount_signing_cert(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app.post_ses_webhook(&notification(&app, COMPLAINT)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn a_transient_bounce_is_ignored() {
+    let app = TestApp::new().await;
+    mount_signing_cert(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app
+        .post_ses_webhook(&notification(&app, TRANSIENT_BOUNCE))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_tampered_message_is_rejected_with_a_403() {
+    let app = TestApp::new().await;
+    mount_signing_cert(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let mut message = notification(&app, TRANSIENT_BOUNCE);
+    message["Message"] = PERMANENT_BOUNCE.into();
+
+    let response = app.post_ses_webhook(&message).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn messages_from_another_topic_are_rejected_with_a_403() {
+    let app = TestApp::new().await;
+    mount_signing_cert(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let mut message = notification(&app, PERMANENT_BOUNCE);
+    message["TopicArn"] = "arn:aws:sns:ap-southeast-2:123456789012:someone-else".into();
+    let message = sign(&app, message);
+
+    let response = app.post_ses_webhook(&message).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn certificates_from_untrusted_urls_are_not_downloaded() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    let mut message = notification(&app, PERMANENT_BOUNCE);
+    message["SigningCertURL"] = "https://evil.example.com/cert.pem".into();
+
+    let response = app.post_ses_webhook(&message).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn the_subscription_confirmation_handshake_visits_the_subscribe_url() {
+    let app = TestApp::new().await;
+    mount_signing_cert(&app).await;
+    Mock::given(path("/sns/confirm-subscription"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let message = sign(
+        &app,
+        serde_json::json!({
+            "Type": "SubscriptionConfirmation",
+            "MessageId": Uuid::new_v4().to_string(),
+            "Token": "2336412f37fb687f5d51e6e241d09c805a5a57b30d712f794cc5f6a988666d92768dd60a747ba6f3",
+            "TopicArn": TOPIC_ARN,
+            "Message": format!("You have chosen to subscribe to the topic {}.", TOPIC_ARN),
+            "SubscribeURL": format!("{}/sns/confirm-subscription?Token=2336412f37", app.email_server.uri()),
+            "Timestamp": Utc::now().to_rfc3339(),
+        }),
+    );
+    let response = app.post_ses_webhook(&message).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_malformed_message_is_rejected_with_a_400() {
+    let app = TestApp::new().await;
+
+    let response = app
+        .post_ses_webhook(&serde_json::json!({ "Type": "Notification" }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_looked_up_through_an_index() {
+    let app = TestApp::new().await;
+    let mut connection = app.db_pool.acquire().await.unwrap();
+    // the table is too small for the planner to bother otherwise
+    sqlx::query("set enable_seqscan = off")
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+
+    let plan: Vec<(String,)> =
+        sqlx::query_as("explain select id from subscriptions where lower(email) = any($1)")
+            .bind(vec!["ursula@example.com".to_string()])
+            .fetch_all(&mut *connection)
+            .await
+            .unwrap();
+
+    assert!(
+        plan.iter()
+            .any(|(line,)| line.contains("subscriptions_lower_email_idx")),
+        "{:?}",
+        plan
+    );
+}
@@ -49,6 +49,33 @@ async fn subscribe_persists_the_new_subscriber() {
     cleanup(&app).await;
 }
 
+#[tokio::test]
+async fn subscribe_persists_the_tags_of_the_new_subscriber() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&format!("{}&tags=Rust%2C%20beta", SUBSCRIBE_FORM_BODY))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let tags: Vec<String> = sqlx::query!("select tag from subscriber_tags order by tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.tag)
+        .collect();
+    assert_eq!(tags, vec!["beta", "rust"]);
+
+    cleanup(&app).await;
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     let app = TestApp::new().await;
@@ -82,6 +109,10 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
         ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
         ("name=Ursula&email=", "empty email"),
         ("name=Ursula&email=definitely-not-an-email", "invalid email"),
+        (
+            "name=Ursula&email=ursula_le_guin%40gmail.com&tags=not%20a%20tag",
+            "invalid tag",
+        ),
     ];
 
     for (body, description) in test_cases {
@@ -115,6 +146,63 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     assert_eq!(confirmation_links.html, confirmation_links.text);
 }
 
+#[tokio::test]
+async fn a_confirmation_email_that_fails_to_send_is_retried_later() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let queued = sqlx::query!(
+        r#"select recipient, n_retries, execute_after > now() as "in_future!"
+           from email_outbox
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The confirmation email was dropped from the outbox.");
+    assert_eq!(queued.recipient, "ursula_le_guin@gmail.com");
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.in_future);
+}
+
+#[tokio::test]
+async fn a_suppressed_address_cannot_subscribe_again_in_another_case() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    sqlx::query!("update subscriptions set status = 'bounced', suppressed_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("select email, status from subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved[0].status, "bounced");
+}
+
 #[tokio::test]
 async fn sbscribe_failes_if_there_is_a_fatal_database_error() {
     let app = TestApp::new().await;
@@ -128,3 +216,276 @@ async fn sbscribe_failes_if_there_is_a_fatal_database_error() {
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_does_not_persist_the_subscriber_if_storing_the_token_fails() {
+    let app = TestApp::new().await;
+
+    sqlx::query!("alter table subscription_tokens drop column subscription_token")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    assert_eq!(response.status().as_u16(), 500);
+
+    let saved = sqlx::query!("select email from subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn subscribing_twice_before_confirming_sends_a_fresh_confirmation_email() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+
+    let saved = sqlx::query!("select id from subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+
+    let tokens = sqlx::query!(
+        "select subscription_token from subscription_tokens where subscriber_id = $1",
+        saved[0].id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tokens.len(), 2);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_does_not_send_an_email() {
+    let app = TestApp::new().await;
+
+    {
+        let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+            .and(method(POST))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+
+        app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+        let email_request = &app.email_server.received_requests().await.unwrap()[0];
+        let confirmation_links = app.get_confirmation_links(email_request);
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+async fn count_subscriptions(app: &TestApp) -> i64 {
+    sqlx::query!(r#"select count(*) as "count!" from subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn the_subscription_form_carries_a_honeypot_and_a_form_token() {
+    let app = TestApp::new().await;
+
+    let response = reqwest::get(format!("{}/subscriptions?list=rust", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"name="website""#));
+    assert!(html.contains(r#"name="form_token""#));
+    assert!(html.contains(r#"name="list" value="rust""#));
+}
+
+#[tokio::test]
+async fn a_form_submitted_at_a_human_pace_is_accepted() {
+    let app = TestApp::new().await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let html = reqwest::get(format!("{}/subscriptions", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let form_token = form_token_in(&html);
+    app.clock.advance(chrono::Duration::seconds(30));
+
+    let response = app
+        .post_subscription_form(&format!(
+            "{}&website=&form_token={}",
+            SUBSCRIBE_FORM_BODY, form_token
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(count_subscriptions(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_form_submitted_too_fast_is_silently_ignored() {
+    let app = TestApp::new().await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let html = reqwest::get(format!("{}/subscriptions", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    let response = app
+        .post_subscription_form(&format!(
+            "{}&website=&form_token={}",
+            SUBSCRIBE_FORM_BODY,
+            form_token_in(&html)
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(count_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_is_silently_ignored() {
+    let app = TestApp::new().await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(&format!(
+            "{}&website=https%3A%2F%2Fspam.example.com",
+            SUBSCRIBE_FORM_BODY
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(count_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_form_without_a_valid_form_token_is_rejected_with_a_400() {
+    let app = TestApp::new().await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let an_hour_ago = (app.clock.now() - chrono::Duration::hours(1)).timestamp();
+
+    for body in [
+        SUBSCRIBE_FORM_BODY.to_string(),
+        // a client picked timestamp, as the form used to send
+        format!("{}&rendered_at={}", SUBSCRIBE_FORM_BODY, an_hour_ago),
+        format!("{}&form_token={}.00", SUBSCRIBE_FORM_BODY, an_hour_ago),
+    ] {
+        let response = app.post_subscription_form(&body).await;
+
+        assert_eq!(response.status().as_u16(), 400);
+    }
+    assert_eq!(count_subscriptions(&app).await, 0);
+}
+
+fn form_token_in(html: &str) -> String {
+    let start =
+        html.find(r#"name="form_token" value=""#).unwrap() + r#"name="form_token" value=""#.len();
+    let length = html[start..].find('"').unwrap();
+    html[start..start + length].to_owned()
+}
+
+#[tokio::test]
+async fn subscribing_the_same_email_too_often_is_rate_limited() {
+    let app = TestApp::new().await;
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // the case of the email does not matter
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+}
+
+#[tokio::test]
+async fn subscribing_too_often_from_one_ip_is_rate_limited() {
+    let app = TestApp::new().await;
+
+    // invalid names, nothing is stored or sent but the requests still count
+    for i in 0..20 {
+        let response = app
+            .post_subscriptions(&format!("name=&email=user{}%40example.com", i))
+            .await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+    let response = app
+        .post_subscriptions("name=&email=another%40example.com")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
@@ -5,6 +5,7 @@ use wiremock::{Mock, ResponseTemplate};
 
 const POST: &str = "POST";
 const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const SUBSCRIBE_FORM_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -86,3 +87,179 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     cleanup(&app).await;
 }
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let saved = sqlx::query!("select consumed_at from subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.consumed_at.is_some());
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_confirm_a_bounced_subscriber() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    sqlx::query!("update subscriptions set status = 'bounced'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "bounced");
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_returns_a_410() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    sqlx::query!("update subscription_tokens set created_at = now() - interval '49 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("/subscriptions/resend_confirmation"));
+
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn resending_the_confirmation_issues_a_working_link() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    sqlx::query!("update subscription_tokens set created_at = now() - interval '49 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn resending_to_an_unknown_email_does_not_send_anything() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resending_the_confirmation_too_often_is_rate_limited() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    // the subscription takes the first token from the bucket of the email
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    for _ in 0..2 {
+        let response = app
+            .post_resend_confirmation("email=ursula_le_guin%40gmail.com")
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+}
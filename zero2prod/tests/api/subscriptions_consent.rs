@@ -0,0 +1,169 @@
+use crate::helpers::{assert_is_redirect_to, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+const SUBSCRIBE_FORM_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com\
+    &source=homepage-footer&consent_text_version=2023-10";
+const SIGNUP_BROWSER: &str = "signup-browser/1.0";
+const CONFIRMATION_BROWSER: &str = "confirmation-browser/2.0";
+
+/// Sign up from one browser and confirm from another, returning the
+/// unsubscribe link of the confirmation email.
+async fn create_confirmed_subscriber(app: &TestApp) -> reqwest::Url {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("User-Agent", SIGNUP_BROWSER)
+        .body(format!(
+            "{}&form_token={}",
+            SUBSCRIBE_FORM_BODY,
+            app.form_token()
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_queued_emails().await;
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    reqwest::Client::new()
+        .get(confirmation_links.html)
+        .header("User-Agent", CONFIRMATION_BROWSER)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.get_unsubscribe_link(email_request)
+}
+
+async fn get_history(app: &TestApp, email: &str) -> serde_json::Value {
+    let response = app.get_consent_history(email).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn signup_and_confirmation_are_recorded_with_where_they_came_from() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let history = get_history(&app, EMAIL).await;
+
+    assert_eq!(history["status"], "confirmed");
+    let events = history["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+
+    let signup = &events[0];
+    assert_eq!(signup["kind"], "signup");
+    assert_eq!(signup["ip"], "127.0.0.1");
+    assert_eq!(signup["user_agent"], SIGNUP_BROWSER);
+    assert_eq!(signup["source"], "homepage-footer");
+    assert_eq!(signup["consent_text_version"], "2023-10");
+
+    let confirmation = &events[1];
+    assert_eq!(confirmation["kind"], "confirmation");
+    assert_eq!(confirmation["ip"], "127.0.0.1");
+    assert_eq!(confirmation["user_agent"], CONFIRMATION_BROWSER);
+}
+
+#[tokio::test]
+async fn unsubscribing_is_recorded_once() {
+    let app = TestApp::new().await;
+    let unsubscribe_link = create_confirmed_subscriber(&app).await;
+
+    for _ in 0..2 {
+        reqwest::Client::new()
+            .post(unsubscribe_link.clone())
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    app.test_user.login(&app).await;
+    let history = get_history(&app, EMAIL).await;
+    let kinds: Vec<_> = history["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["signup", "confirmation", "unsubscription"]);
+}
+
+#[tokio::test]
+async fn changes_made_by_an_admin_are_recorded_as_such() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let subscriber_id = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    app.post_admin_subscriber_action(subscriber_id, "unsubscribe")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let history = get_history(&app, EMAIL).await;
+    let unsubscription = &history["events"][2];
+    assert_eq!(unsubscription["kind"], "unsubscription");
+    assert_eq!(unsubscription["source"], "admin");
+    // the admin's ip is not the subscriber's
+    assert_eq!(unsubscription["ip"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn the_email_is_looked_up_regardless_of_case() {
+    let app = TestApp::new().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+
+    let history = get_history(&app, &EMAIL.to_uppercase()).await;
+
+    assert_eq!(history["email"], EMAIL);
+}
+
+#[tokio::test]
+async fn the_history_of_an_unknown_email_is_a_404() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_consent_history("nobody@example.com").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_a_consent_history() {
+    let app = TestApp::new().await;
+
+    let response = app.get_consent_history(EMAIL).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,320 @@
+use crate::helpers::TestApp;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const SUBSCRIBE_FORM_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=scifi";
+
+async fn create_subscriber(app: &TestApp) {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+/// Ask for a data request link and return it, as sent by email.
+async fn request_link(app: &TestApp, kind: &str) -> reqwest::Url {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_data_request(&format!("email=ursula_le_guin%40gmail.com&kind={}", kind))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_queued_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(email_request);
+    assert_eq!(links.html, links.text);
+    links.html
+}
+
+async fn post_export(link: &reqwest::Url) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(link.join("/subscriptions/data").unwrap())
+        .form(&[("token", token_of(link))])
+        .send()
+        .await
+        .unwrap()
+}
+
+fn token_of(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[tokio::test]
+async fn requesting_data_for_an_unknown_email_looks_the_same_and_sends_nothing() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_data_request("email=nobody%40example.com&kind=export")
+        .await;
+    app.dispatch_queued_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn data_requests_ignore_the_case_of_the_email() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_data_request("email=Ursula_Le_Guin%40Gmail.com&kind=export")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_queued_emails().await;
+}
+
+#[tokio::test]
+async fn the_data_request_email_is_sent_by_the_list() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    app.post_list(&serde_json::json!({
+        "slug": "weekly",
+        "name": "Weekly notes",
+        "sender_email": "weekly@example.com",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(&format!("{}&list=weekly", SUBSCRIBE_FORM_BODY))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    app.post_data_request("email=ursula_le_guin%40gmail.com&kind=export")
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_queued_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["FromEmailAddress"], "weekly@example.com");
+}
+
+#[tokio::test]
+async fn data_requests_for_the_same_email_are_rate_limited() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // the subscription took a token from the bucket of the email already
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let response = app
+            .post_data_request("email=ursula_le_guin%40gmail.com&kind=export")
+            .await;
+        statuses.push(response.status().as_u16());
+    }
+
+    assert_eq!(statuses, vec![200, 200, 429]);
+}
+
+#[tokio::test]
+async fn an_invalid_data_request_is_rejected_with_a_400() {
+    let app = TestApp::new().await;
+
+    for body in [
+        "email=not-an-email&kind=export",
+        "email=ursula_le_guin%40gmail.com&kind=everything",
+        "email=ursula_le_guin%40gmail.com",
+    ] {
+        let response = app.post_data_request(body).await;
+
+        assert_eq!(response.status().as_u16(), 400, "{} was accepted", body);
+    }
+}
+
+#[tokio::test]
+async fn following_the_export_link_does_not_use_it_up() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+    let link = request_link(&app, "export").await;
+
+    for _ in 0..2 {
+        let response = reqwest::get(link.clone()).await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 200);
+        let html = response.text().await.unwrap();
+        assert!(html.contains(r#"<form action="/subscriptions/data" method="post">"#));
+    }
+    let n_consumed = sqlx::query!(
+        r#"select count(*) as "count!" from data_request_tokens where consumed_at is not null"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_consumed, 0);
+}
+
+#[tokio::test]
+async fn the_export_returns_the_subscriber_data_once() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+    let link = request_link(&app, "export").await;
+
+    let response = post_export(&link).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(data["name"], "le guin");
+    assert_eq!(data["status"], "pending_confirmation");
+    assert_eq!(data["tags"], serde_json::json!(["scifi"]));
+    assert_eq!(data["confirmation_links"].as_array().unwrap().len(), 1);
+    assert_eq!(data["data_requests"][0]["kind"], "export");
+    // tokens are never part of the export
+    assert!(!data.to_string().contains(&token_of(&link)));
+
+    let response = post_export(&link).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_expired_link_is_rejected_with_a_410() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+    let link = request_link(&app, "export").await;
+    sqlx::query!("update data_request_tokens set created_at = now() - interval '49 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        reqwest::get(link.clone()).await.unwrap().status().as_u16(),
+        410
+    );
+    assert_eq!(post_export(&link).await.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn following_the_erasure_link_does_not_erase_anything() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+    let link = request_link(&app, "erasure").await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"<form action="/subscriptions/erase" method="post">"#));
+    let n_subscribers = sqlx::query!(r#"select count(*) as "count!" from subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn erasing_leaves_only_a_tombstone_with_a_hashed_email() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+    let link = request_link(&app, "erasure").await;
+
+    let response = reqwest::Client::new()
+        .post(link.join("/subscriptions/erase").unwrap())
+        .form(&[("token", token_of(&link))])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let remaining = sqlx::query!(
+        r#"select
+               (select count(*) from subscriptions) as "subscriptions!",
+               (select count(*) from subscription_tokens) as "tokens!",
+               (select count(*) from subscriber_tags) as "tags!",
+               (select count(*) from data_request_tokens) as "data_requests!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.tags, 0);
+    assert_eq!(remaining.data_requests, 0);
+
+    let tombstone = sqlx::query!("select email_hash from erasure_tombstones")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!tombstone.email_hash.contains("ursula"));
+    assert_eq!(tombstone.email_hash.len(), 64);
+}
+
+#[tokio::test]
+async fn an_export_link_cannot_be_used_to_erase() {
+    let app = TestApp::new().await;
+    create_subscriber(&app).await;
+    let link = request_link(&app, "export").await;
+
+    let response = reqwest::Client::new()
+        .post(link.join("/subscriptions/erase").unwrap())
+        .form(&[("token", token_of(&link))])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let n_subscribers = sqlx::query!(r#"select count(*) as "count!" from subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
@@ -0,0 +1,253 @@
+use crate::helpers::{cleanup, SentEmail, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const SUBSCRIBE_FORM_BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// Subscribe and confirm, returning the unsubscribe link of the confirmation email.
+async fn create_confirmed_subscriber(app: &TestApp) -> reqwest::Url {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.get_unsubscribe_link(email_request)
+}
+
+async fn subscription_status(app: &TestApp) -> String {
+    sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn emails_carry_one_click_unsubscribe_headers() {
+    let app = TestApp::new().await;
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email = SentEmail::from_request(email_request);
+
+    let list_unsubscribe = email.header("List-Unsubscribe").unwrap();
+    assert!(list_unsubscribe.starts_with('<'));
+    assert!(list_unsubscribe.contains("/subscriptions/unsubscribe?subscriber_id="));
+    assert_eq!(
+        email.header("List-Unsubscribe-Post"),
+        Some("List-Unsubscribe=One-Click")
+    );
+
+    cleanup(&app).await;
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_asks_for_a_confirmation() {
+    let app = TestApp::new().await;
+    let unsubscribe_link = create_confirmed_subscriber(&app).await;
+
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("ursula_le_guin@gmail.com"));
+    // a GET must not unsubscribe, link scanners follow every link
+    assert_eq!(subscription_status(&app).await, "confirmed");
+
+    cleanup(&app).await;
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    let app = TestApp::new().await;
+    let unsubscribe_link = create_confirmed_subscriber(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("select status, unsubscribed_at from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+
+    cleanup(&app).await;
+}
+
+#[tokio::test]
+async fn a_tampered_unsubscribe_link_is_rejected_with_a_401() {
+    let app = TestApp::new().await;
+    let mut unsubscribe_link = create_confirmed_subscriber(&app).await;
+
+    let subscriber_id = unsubscribe_link
+        .query_pairs()
+        .find(|(k, _)| k == "subscriber_id")
+        .unwrap()
+        .1
+        .into_owned();
+    unsubscribe_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &subscriber_id)
+        .append_pair("token", &"0".repeat(64));
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(subscription_status(&app).await, "confirmed");
+
+    cleanup(&app).await;
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    let app = TestApp::new().await;
+    let unsubscribe_link = create_confirmed_subscriber(&app).await;
+
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "newsletter body as plain text",
+            "html": "<p>newsletters body as html</p>"
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    cleanup(&app).await;
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_sends_a_new_confirmation_link() {
+    let app = TestApp::new().await;
+    let unsubscribe_link = create_confirmed_subscriber(&app).await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("select status, unsubscribed_at from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(subscription_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribing_a_bounced_address_keeps_it_suppressed() {
+    let app = TestApp::new().await;
+    let unsubscribe_link = create_confirmed_subscriber(&app).await;
+    sqlx::query!("update subscriptions set status = 'bounced', suppressed_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "bounced");
+
+    Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(SUBSCRIBE_FORM_BODY).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscription_status(&app).await, "bounced");
+}
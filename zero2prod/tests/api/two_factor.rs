@@ -0,0 +1,360 @@
+use crate::helpers::{assert_is_redirect_to, TestApp};
+
+fn issue() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        }
+    })
+}
+
+/// Enroll the logged in test user, returning their secret and recovery codes.
+async fn enroll(app: &TestApp) -> (String, Vec<String>) {
+    let response = app.post_two_factor_enrollment().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let secret = body["secret"].as_str().unwrap().to_owned();
+
+    let response = app
+        .post_two_factor_confirmation(&app.totp_code(&secret))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let recovery_codes = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_owned())
+        .collect();
+
+    // the confirmation used up the code of the current step
+    app.clock.advance(chrono::Duration::seconds(30));
+    (secret, recovery_codes)
+}
+
+async fn post_password(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await
+}
+
+async fn post_code(app: &TestApp, code: &str) -> reqwest::Response {
+    app.post_login_two_factor(&serde_json::json!({ "code": code }))
+        .await
+}
+
+#[tokio::test]
+async fn enrollment_returns_a_provisioning_uri() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_two_factor_enrollment().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let secret = body["secret"].as_str().unwrap();
+    let uri = body["provisioning_uri"].as_str().unwrap();
+    assert!(uri.starts_with(&format!(
+        "otpauth://totp/zero2prod:{}?",
+        app.test_user.username
+    )));
+    assert!(uri.contains(&format!("secret={}", secret)));
+}
+
+#[tokio::test]
+async fn the_second_factor_is_not_required_until_confirmed() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    app.post_two_factor_enrollment()
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = post_password(&app).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_wrong_code_does_not_confirm_the_enrollment() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    app.post_two_factor_enrollment()
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.post_two_factor_confirmation("abcdef").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = app.get_two_factor_status().await.json().await.unwrap();
+    assert_eq!(body["enabled"], false);
+}
+
+#[tokio::test]
+async fn once_enrolled_the_password_alone_does_not_log_in() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    enroll(&app).await;
+
+    let response = post_password(&app).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_valid_code_completes_the_login() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+    post_password(&app).await;
+
+    let response = post_code(&app, &app.totp_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn an_invalid_code_does_not_log_in() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    enroll(&app).await;
+    post_password(&app).await;
+
+    let response = post_code(&app, "abcdef").await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+
+    let html_page = app.get_login_two_factor().await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_code_comes_after_the_password() {
+    let app = TestApp::new().await;
+
+    let response = app.get_login_two_factor().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = post_code(&app, "123456").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_code_cannot_be_used_twice() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+    let code = app.totp_code(&secret);
+    post_password(&app).await;
+    post_code(&app, &code).await;
+
+    post_password(&app).await;
+    let response = post_code(&app, &code).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+
+    // the code of the next step is fine
+    app.clock.advance(chrono::Duration::seconds(30));
+    let response = post_code(&app, &app.totp_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_code_of_the_previous_step_is_accepted() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+    let code = app.totp_code(&secret);
+    app.clock.advance(chrono::Duration::seconds(30));
+    post_password(&app).await;
+
+    let response = post_code(&app, &code).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_older_code_is_rejected() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+    let code = app.totp_code(&secret);
+    app.clock.advance(chrono::Duration::seconds(60));
+    post_password(&app).await;
+
+    let response = post_code(&app, &code).await;
+
+    assert_is_redirect_to(&response, "/login/two_factor");
+}
+
+#[tokio::test]
+async fn a_recovery_code_logs_in_once() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (_, recovery_codes) = enroll(&app).await;
+
+    post_password(&app).await;
+    let response = post_code(&app, &recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    post_password(&app).await;
+    let response = post_code(&app, &recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+
+    let left = sqlx::query_scalar!("select count(*) from recovery_codes where used_at is null")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(left, Some(9));
+}
+
+#[tokio::test]
+async fn only_hashes_of_the_recovery_codes_are_stored() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (_, recovery_codes) = enroll(&app).await;
+
+    let stored = sqlx::query!("select code_hash from recovery_codes")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(stored.len(), recovery_codes.len());
+    for row in stored {
+        assert!(!recovery_codes.contains(&row.code_hash));
+    }
+}
+
+#[tokio::test]
+async fn new_recovery_codes_replace_the_old_ones() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, old_codes) = enroll(&app).await;
+
+    let response = app.post_recovery_codes(&app.totp_code(&secret)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let new_code = body["recovery_codes"][0].as_str().unwrap();
+
+    post_password(&app).await;
+    let response = post_code(&app, &old_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+    let response = post_code(&app, new_code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_second_factor_for_a_while() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+    post_password(&app).await;
+    for _ in 0..5 {
+        post_code(&app, "abcdef").await;
+    }
+
+    let response = post_code(&app, &app.totp_code(&secret)).await;
+    assert_is_redirect_to(&response, "/login/two_factor");
+
+    app.clock.advance(chrono::Duration::minutes(5));
+    let response = post_code(&app, &app.totp_code(&secret)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn publishing_with_a_password_requires_the_second_factor() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+
+    let response = app.post_newsletters(issue()).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_newsletters_with_code("abcdef", issue()).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_newsletters_with_code(&app.totp_code(&secret), issue())
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn api_tokens_do_not_need_the_second_factor() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    enroll(&app).await;
+    let response = app
+        .post_api_token(&serde_json::json!({"name": "ci", "scopes": ["publish"]}))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    let response = app
+        .post_newsletters_with_token(body["token"].as_str().unwrap(), issue())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn api_tokens_created_before_enrolling_are_revoked() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let response = app
+        .post_api_token(&serde_json::json!({"name": "ci", "scopes": ["publish"]}))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let token = body["token"].as_str().unwrap().to_owned();
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    enroll(&app).await;
+
+    let response = app.post_newsletters_with_token(&token, issue()).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn enrolling_twice_is_a_conflict() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    enroll(&app).await;
+
+    let response = app.post_two_factor_enrollment().await;
+
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn disabling_the_second_factor_takes_a_code() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let (secret, _) = enroll(&app).await;
+
+    let response = app.delete_two_factor("abcdef").await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.delete_two_factor(&app.totp_code(&secret)).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = post_password(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_the_second_factor() {
+    let app = TestApp::new().await;
+
+    let response = app.post_two_factor_enrollment().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
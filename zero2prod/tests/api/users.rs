@@ -0,0 +1,343 @@
+use crate::helpers::{assert_is_redirect_to, SentEmail, TestApp, TestUser};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const POST: &str = "POST";
+const SEND_EMAIL_END_POINT: &str = "/v2/email/outbound-emails";
+const INVITED_EMAIL: &str = "octavia@example.com";
+const NEW_PASSWORD: &str = "a long enough password";
+
+fn issue(list: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "list": list,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as html</p>",
+        }
+    })
+}
+
+async fn create_weekly_list(app: &TestApp) {
+    app.post_list(&serde_json::json!({
+        "slug": "weekly",
+        "name": "Weekly notes",
+        "sender_email": "weekly@example.com",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+/// Invite someone as an admin and return the invitation email.
+async fn invite(app: &TestApp, body: serde_json::Value) -> wiremock::Request {
+    let _g = Mock::given(path(SEND_EMAIL_END_POINT))
+        .and(method(POST))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_invitation(&body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+fn invitation_token(app: &TestApp, email_request: &wiremock::Request) -> String {
+    let link = app.get_confirmation_links(email_request).html;
+    assert_eq!(link.path(), "/invitations/accept");
+    link.query_pairs()
+        .find(|(name, _)| name == "token")
+        .map(|(_, token)| token.into_owned())
+        .unwrap()
+}
+
+async fn accept(app: &TestApp, token: &str, username: &str) -> reqwest::Response {
+    app.post_accept_invitation(&serde_json::json!({
+        "token": token,
+        "username": username,
+        "password": NEW_PASSWORD,
+        "password_check": NEW_PASSWORD,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn an_invited_user_joins_with_the_role_and_lists_of_the_invitation() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+
+    let email_request = invite(
+        &app,
+        serde_json::json!({"email": INVITED_EMAIL, "role": "editor", "lists": ["weekly"]}),
+    )
+    .await;
+    let token = invitation_token(&app, &email_request);
+
+    let form = reqwest::get(app.get_confirmation_links(&email_request).html)
+        .await
+        .unwrap();
+    assert_eq!(form.status().as_u16(), 200);
+    let response = accept(&app, &token, "octavia").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let user = sqlx::query!(
+        r#"select u.role, array(
+               select l.slug from list_permissions p join lists l using (list_id)
+               where p.user_id = u.user_id
+           ) as "lists!"
+           from users u where username = 'octavia'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(user.role, "editor");
+    assert_eq!(user.lists, ["weekly"]);
+
+    let response = app
+        .post_login(&serde_json::json!({"username": "octavia", "password": NEW_PASSWORD}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn invitation_emails_have_nothing_to_unsubscribe_from() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let email_request = invite(
+        &app,
+        serde_json::json!({"email": INVITED_EMAIL, "role": "viewer"}),
+    )
+    .await;
+
+    let email = SentEmail::from_request(&email_request);
+    assert_eq!(email.header("To"), Some(INVITED_EMAIL));
+    assert_eq!(email.header("List-Unsubscribe"), None);
+}
+
+#[tokio::test]
+async fn an_invitation_can_only_be_accepted_once() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    let email_request = invite(
+        &app,
+        serde_json::json!({"email": INVITED_EMAIL, "role": "viewer"}),
+    )
+    .await;
+    let token = invitation_token(&app, &email_request);
+    accept(&app, &token, "octavia").await;
+
+    let response = accept(&app, &token, "octavia-again").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn invalid_invitations_are_rejected() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"email": INVITED_EMAIL, "role": "owner"}),
+            "an unknown role",
+        ),
+        (
+            serde_json::json!({"email": "not-an-email", "role": "viewer"}),
+            "an invalid email",
+        ),
+        (
+            serde_json::json!({"email": INVITED_EMAIL, "role": "editor", "lists": ["nope"]}),
+            "an unknown list",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_invitation(&body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the invitation had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn only_admins_manage_users() {
+    let app = TestApp::new().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    assert_eq!(app.get_users().await.status().as_u16(), 403);
+    let response = app
+        .post_invitation(&serde_json::json!({"email": INVITED_EMAIL, "role": "admin"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app.post_deactivate_user(app.test_user.user_id).await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn viewers_cannot_change_subscribers() {
+    let app = TestApp::new().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    viewer.login(&app).await;
+
+    let response = app.delete_admin_subscriber(Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_publish_only_to_their_lists() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    app.put_user_lists(editor.user_id, &["weekly"])
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.post_newsletters_as(&editor, issue("default")).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.post_newsletters_as(&editor, issue("weekly")).await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn editors_only_see_the_scheduled_issues_of_their_lists() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+    create_weekly_list(&app).await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    app.put_user_lists(editor.user_id, &["weekly"])
+        .await
+        .error_for_status()
+        .unwrap();
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    for list in ["default", "weekly"] {
+        let mut body = issue(list);
+        body["title"] = list.into();
+        body["send_at"] = serde_json::json!(chrono::Utc::now() + chrono::Duration::hours(1));
+        app.post_newsletters(body).await.error_for_status().unwrap();
+    }
+
+    let titles = |scheduled: serde_json::Value| -> Vec<String> {
+        scheduled
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|issue| issue["title"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    let scheduled = app.get_scheduled_newsletters().await.json().await.unwrap();
+    assert_eq!(titles(scheduled).len(), 2);
+    let response = app.get_scheduled_newsletters_as(&editor).await;
+    assert_eq!(titles(response.json().await.unwrap()), vec!["weekly"]);
+    let response = app.get_scheduled_newsletters_as(&viewer).await;
+    assert!(titles(response.json().await.unwrap()).is_empty());
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish() {
+    let app = TestApp::new().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+
+    let response = app.post_newsletters_as(&viewer, issue("default")).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_users_are_listed_with_their_role() {
+    let app = TestApp::new().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_users().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let users = body["users"].as_array().unwrap();
+    assert_eq!(users.len(), 2);
+    let listed_viewer = users
+        .iter()
+        .find(|u| u["username"] == viewer.username.as_str())
+        .unwrap();
+    assert_eq!(listed_viewer["role"], "viewer");
+}
+
+#[tokio::test]
+async fn a_deactivated_user_can_no_longer_log_in_nor_publish() {
+    let app = TestApp::new().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_deactivate_user(editor.user_id).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = app.post_newsletters_as(&editor, issue("default")).await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &editor.username,
+            "password": &editor.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn deactivating_a_user_ends_their_sessions() {
+    let app = TestApp::new().await;
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.db_pool).await;
+    editor.login(&app).await;
+
+    sqlx::query!(
+        "update users set deactivated_at = now() where user_id = $1",
+        editor.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn admins_cannot_deactivate_themselves() {
+    let app = TestApp::new().await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_deactivate_user(app.test_user.user_id).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}